@@ -1,22 +1,40 @@
+use crate::bulk::BulkOptions;
 use crate::cli::args::AppData;
 use crate::cli::runtime::confirm::UserConfirm;
-use crate::{error, get_hash};
+use crate::encryption::{DecryptionOptions, EncryptionOptions};
+use crate::error;
+use crate::kdf::KdfParams;
+use crate::keyfile::Keyfile;
+use crate::keyslot::{Identity, Recipient};
+use crate::meta::raw::RawMeta;
+use crate::parity::ParityParams;
+use crate::recipient::{
+    parse_keys, read_keys_file, SshEd25519Identity, SshEd25519Recipient,
+    X25519Recipient,
+};
+use path_absolutize::Absolutize;
 use rpassword::prompt_password;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-pub type KeyHashType = [u8; 32];
+/// The key itself is kept, because the hash is derived per file
+/// using the salt and parameters stored in the file
+pub type KeyType = String;
+
+/// How many times the key is prompted, while it does not unlock the file
+pub const KEY_ATTEMPTS: usize = 3;
 
 #[derive(Debug, Clone)]
 pub struct AppContext {
     pub cli_current_path: PathBuf,
     pub cli_exit: bool,
-    pub key_hash: Option<KeyHashType>,
+    pub key: Option<KeyType>,
 
     pub data: AppData,
     pub term: console::Term,
 }
 
-pub fn user_key_hash() -> error::Result<KeyHashType> {
+pub fn user_key() -> error::Result<KeyType> {
     let key = loop {
         let password1 = prompt_password("Enter the key : ")?;
         let password2 = prompt_password("Repeat the key: ")?;
@@ -29,14 +47,212 @@ pub fn user_key_hash() -> error::Result<KeyHashType> {
         break password1;
     };
 
-    get_hash(&key)
+    Ok(key)
 }
 
-pub fn set_context_key_hash(ctx: &mut AppContext) -> error::Result<()> {
-    ctx.key_hash = Some(user_key_hash()?);
+pub fn set_context_key(ctx: &mut AppContext) -> error::Result<()> {
+    ctx.key = Some(user_key()?);
     Ok(())
 }
 
+/// Whether the recipient argument is the public key itself, not a file
+pub fn is_recipient_key_arg(arg: &str) -> bool {
+    let arg = arg.trim();
+    arg.starts_with(X25519Recipient::PREFIX)
+        || arg.starts_with(SshEd25519Recipient::KEY_TYPE)
+}
+
+/// Parses the recipient argument: the public key itself or the recipients file
+pub fn parse_recipient_arg(
+    ctx: &AppContext,
+    arg: &str,
+) -> error::Result<Vec<Recipient>> {
+    if is_recipient_key_arg(arg) {
+        return parse_keys(arg);
+    }
+    read_keys_file(&ctx.cli_current_path.join(arg))
+}
+
+/// Parses the identity file argument: the OpenSSH private key
+/// or the X25519 identity file
+pub fn parse_identity_arg(
+    ctx: &AppContext,
+    arg: &str,
+) -> error::Result<Vec<Identity>> {
+    let path = ctx.cli_current_path.join(arg);
+    let content = fs::read_to_string(&path)?;
+    if content
+        .trim_start()
+        .starts_with(SshEd25519Identity::PEM_BEGIN)
+    {
+        return Ok(vec![Identity::SshEd25519(
+            content.parse()?,
+        )]);
+    }
+
+    Ok(read_keys_file(&path)?
+        .into_iter()
+        .map(Identity::X25519)
+        .collect())
+}
+
+pub fn get_context_keyfile(ctx: &AppContext) -> error::Result<Option<Keyfile>> {
+    ctx.data
+        .keyfile
+        .as_ref()
+        .map(|path| Keyfile::read(&ctx.cli_current_path.join(path)))
+        .transpose()
+}
+
+/// Whether the key must be asked for: there is no key and no keyfile
+/// (or the keyfile is combined with the key), and no public keys are used
+pub fn is_context_key_required(
+    ctx: &AppContext,
+    public_keys: &[String],
+) -> bool {
+    ctx.key.is_none()
+        && public_keys.is_empty()
+        && (ctx.data.keyfile.is_none() || ctx.data.combine_key)
+}
+
+/// Makes the paths of the key files absolute,
+/// so they do not depend on the current path of the context
+pub fn absolutize_key_paths(data: &mut AppData) -> error::Result<()> {
+    let absolutize = |path: &mut String| -> error::Result<()> {
+        *path = Path::new(path)
+            .absolutize()?
+            .display()
+            .to_string();
+        Ok(())
+    };
+
+    if let Some(keyfile) = &mut data.keyfile {
+        absolutize(keyfile)?;
+    }
+    for recipient in &mut data.recipients {
+        if !is_recipient_key_arg(recipient) {
+            absolutize(recipient)?;
+        }
+    }
+    data.identities
+        .iter_mut()
+        .try_for_each(absolutize)
+}
+
+/// The context key and all the recipients, the file is encrypted to.
+/// The key and the keyfile are combined into the single key slot
+pub fn get_context_recipients(
+    ctx: &AppContext,
+) -> error::Result<Vec<Recipient>> {
+    let mut recipients = Vec::new();
+    match (
+        ctx.key.clone(),
+        get_context_keyfile(ctx)?,
+    ) {
+        (Some(key), None) => recipients.push(Recipient::Password(key)),
+        (None, Some(keyfile)) => recipients.push(Recipient::Keyfile(keyfile)),
+        (Some(key), Some(keyfile)) => {
+            recipients.push(Recipient::PasswordKeyfile(key, keyfile))
+        }
+        (None, None) => {}
+    }
+    for arg in &ctx.data.recipients {
+        recipients.extend(parse_recipient_arg(ctx, arg)?);
+    }
+
+    Ok(recipients)
+}
+
+/// The context key and all the identities, the file is decrypted with
+pub fn get_context_identities(
+    ctx: &AppContext,
+) -> error::Result<Vec<Identity>> {
+    let mut identities = Vec::new();
+    match (
+        ctx.key.clone(),
+        get_context_keyfile(ctx)?,
+    ) {
+        (Some(key), None) => identities.push(Identity::Password(key)),
+        (None, Some(keyfile)) => identities.push(Identity::Keyfile(keyfile)),
+        (Some(key), Some(keyfile)) => {
+            identities.push(Identity::PasswordKeyfile(key, keyfile))
+        }
+        (None, None) => {}
+    }
+    for arg in &ctx.data.identities {
+        identities.extend(parse_identity_arg(ctx, arg)?);
+    }
+
+    Ok(identities)
+}
+
+pub fn get_context_encryption_options(ctx: &AppContext) -> EncryptionOptions {
+    let default_kdf = KdfParams::default();
+    EncryptionOptions {
+        cipher_kind: ctx.data.cipher.unwrap_or_default(),
+        kdf: KdfParams {
+            m_cost: ctx
+                .data
+                .kdf_memory
+                .unwrap_or(default_kdf.m_cost),
+            t_cost: ctx
+                .data
+                .kdf_time
+                .unwrap_or(default_kdf.t_cost),
+            p_cost: ctx
+                .data
+                .kdf_parallelism
+                .unwrap_or(default_kdf.p_cost),
+            ..default_kdf
+        },
+        chunk_size: ctx
+            .data
+            .chunk_size
+            .unwrap_or(RawMeta::DEFAULT_CHUNK_SIZE),
+        parity: ctx.data.parity.map(ParityParams::new),
+        xattrs: ctx.data.xattrs,
+    }
+}
+
+/// Which files of the directory are processed and where they are written
+pub fn get_context_bulk_options(
+    ctx: &AppContext,
+    output_dir: Option<PathBuf>,
+) -> BulkOptions {
+    BulkOptions {
+        output_dir,
+        keep_original: ctx.data.keep_original,
+        include: ctx.data.include.clone(),
+        exclude: ctx.data.exclude.clone(),
+        jobs: ctx.data.jobs.unwrap_or(0),
+    }
+}
+
+/// Asks for the preview, if it is not set
+pub fn get_context_decryption_options(
+    ctx: &AppContext,
+) -> error::Result<DecryptionOptions> {
+    Ok(DecryptionOptions {
+        preview: get_context_preview(ctx)?,
+        ..get_context_archive_options(ctx)
+    })
+}
+
+/// The entries are written by the unpacking or the extraction,
+/// so the preview is not asked
+pub fn get_context_archive_options(ctx: &AppContext) -> DecryptionOptions {
+    DecryptionOptions {
+        preview: false,
+        restore_attributes: !ctx.data.no_restore_attributes,
+        restore_owner: ctx.data.restore_owner,
+        restore_acls: ctx.data.restore_acls,
+        filename_policy: ctx
+            .data
+            .filename_policy
+            .unwrap_or_default(),
+    }
+}
+
 pub fn get_context_preview(ctx: &AppContext) -> error::Result<bool> {
     let val = match ctx.data.preview {
         Some(v) => v,
@@ -0,0 +1,502 @@
+#[cfg(test)]
+mod tests;
+
+use crate::bulk::BulkReport;
+use crate::encryption::safe_file_name;
+use crate::error;
+use crate::error::ErrorKind;
+use crate::file::{os_str_to_bytes, restore_attributes, RestorePolicy};
+use crate::meta::enc::{ContentDigest, ContentHasher, FileAttributes};
+use crate::meta::index::{ArchiveEntry, ArchiveIndex};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::{fs, io, vec};
+
+/// Size of the index length (u64, LE), which starts the archive content.
+/// The [`ArchiveIndex`] and the content of the entries follow it,
+/// so the entries are unpacked while the archive is decrypted
+pub const INDEX_LEN_SIZE: usize = 8;
+
+/// Limit of the index size, so the content of a file,
+/// which is not an archive, is not buffered
+pub const MAX_INDEX_SIZE: u64 = 1 << 28;
+
+/// Entry path of the file of the tree, the components relative
+/// to the root, separated by `/`. None, if it is not representable
+pub fn entry_path(root: &Path, file_path: &Path) -> Option<Vec<u8>> {
+    let relative = file_path.strip_prefix(root).ok()?;
+    let mut result = Vec::new();
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            return None;
+        };
+        if !result.is_empty() {
+            result.push(b'/');
+        }
+        result.extend(os_str_to_bytes(name)?);
+    }
+    (!result.is_empty()).then_some(result)
+}
+
+/// Path of the entry in the directory. None, if any component of the entry
+/// path is unsafe (see [`crate::encryption::is_filename_safe`]),
+/// so the entry never leaves the directory
+pub fn entry_file_path(dir: &Path, path: &[u8]) -> Option<PathBuf> {
+    let mut result = dir.to_path_buf();
+    for name in path.split(|c| *c == b'/') {
+        result.push(safe_file_name(name)?);
+    }
+    Some(result)
+}
+
+/// Index of the files of the tree, see [`crate::bulk::walk_files`].
+/// Every file is read for its digest. The files, which can not be read,
+/// are reported as failed and left out.
+/// Returns the index and the files of its entries
+pub fn read_index(
+    root: &Path,
+    files: Vec<PathBuf>,
+    report: &mut BulkReport,
+) -> (ArchiveIndex, Vec<PathBuf>) {
+    let mut index = ArchiveIndex::default();
+    let mut result = Vec::with_capacity(files.len());
+    for file_path in files {
+        match read_entry(root, &file_path) {
+            Ok(entry) => {
+                index.entries.push(entry);
+                result.push(file_path);
+            }
+            Err(e) => {
+                log::warn!(target: "archive read_index", "File {file_path:?} is failed: {e}");
+                report.failed.push((file_path, e));
+            }
+        }
+    }
+    (index, result)
+}
+
+fn read_entry(root: &Path, file_path: &Path) -> error::Result<ArchiveEntry> {
+    let path = entry_path(root, file_path).ok_or_else(|| {
+        error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!("Path {file_path:?} is not representable in the archive"),
+        )
+    })?;
+    let mut file = File::open(file_path)?;
+    let metadata = file.metadata()?;
+    Ok(ArchiveEntry {
+        path,
+        content: ContentDigest::from_reader(&mut file)?,
+        attributes: FileAttributes::from_metadata(&metadata),
+    })
+}
+
+/// The archive content: the index length, the index and the content
+/// of the entries in order. The file, which is changed since
+/// [`read_index`], fails the reading
+pub struct PackReader {
+    header: Cursor<Vec<u8>>,
+    content_len: u64,
+    files: vec::IntoIter<(PathBuf, ContentDigest)>,
+    current: Option<PackedFile>,
+}
+
+struct PackedFile {
+    path: PathBuf,
+    content: ContentDigest,
+    file: io::Take<File>,
+    hasher: ContentHasher,
+}
+
+impl PackReader {
+    pub fn new(index: &ArchiveIndex, files: Vec<PathBuf>) -> Self {
+        let index_bytes = index.to_vec();
+        let header = [
+            (index_bytes.len() as u64)
+                .to_le_bytes()
+                .as_slice(),
+            &index_bytes,
+        ]
+        .concat();
+        let content_len = header.len() as u64 + index.content_size();
+        let files: Vec<_> = files
+            .into_iter()
+            .zip(
+                index
+                    .entries
+                    .iter()
+                    .map(|entry| entry.content),
+            )
+            .collect();
+
+        Self {
+            header: Cursor::new(header),
+            content_len,
+            files: files.into_iter(),
+            current: None,
+        }
+    }
+
+    /// Size of the whole archive content
+    pub fn content_len(&self) -> u64 {
+        self.content_len
+    }
+}
+
+impl Read for PackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.header.read(buf)?;
+        if count > 0 || buf.is_empty() {
+            return Ok(count);
+        }
+
+        loop {
+            if self.current.is_none() {
+                let Some((path, content)) = self.files.next() else {
+                    return Ok(0);
+                };
+                let file = File::open(&path)?.take(content.size);
+                self.current = Some(PackedFile {
+                    path,
+                    content,
+                    file,
+                    hasher: ContentHasher::default(),
+                });
+            }
+            let current = self
+                .current
+                .as_mut()
+                .expect("The current file is opened");
+
+            let count = current.file.read(buf)?;
+            if count > 0 {
+                current
+                    .hasher
+                    .write_all(&buf[..count])?;
+                return Ok(count);
+            }
+
+            let PackedFile {
+                path,
+                content,
+                hasher,
+                ..
+            } = self
+                .current
+                .take()
+                .expect("The current file is read");
+            if hasher.finish() != content {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("File {path:?} is changed while packing"),
+                ));
+            }
+        }
+    }
+}
+
+/// What [`ArchiveWriter`] does with the entries
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum UnpackTarget {
+    /// Only checks the entries by their digests
+    List,
+
+    /// Writes every entry into the directory under its path
+    Directory(PathBuf),
+
+    /// Writes the entry with the path into the file
+    Entry { path: Vec<u8>, file_path: PathBuf },
+}
+
+/// Receives the decrypted archive content (see [`PackReader`]):
+/// reads the index and writes the entries by the target, checking them
+/// by their digests. It is a handle, so the state outlives the clone,
+/// which the cipher consumes
+#[derive(Clone)]
+pub struct ArchiveWriter {
+    state: Rc<RefCell<Unpacker>>,
+}
+
+struct Unpacker {
+    target: UnpackTarget,
+    restore_policy: Option<RestorePolicy>,
+    header: Vec<u8>,
+    index: Option<ArchiveIndex>,
+    next: usize,
+    current: Option<UnpackedEntry>,
+    error: Option<error::Error>,
+}
+
+struct UnpackedEntry {
+    entry: ArchiveEntry,
+    remaining: u64,
+    hasher: ContentHasher,
+    file: Option<(PathBuf, File)>,
+}
+
+impl ArchiveWriter {
+    pub fn new(
+        target: UnpackTarget,
+        restore_policy: Option<RestorePolicy>,
+    ) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(Unpacker {
+                target,
+                restore_policy,
+                header: Vec::with_capacity(INDEX_LEN_SIZE),
+                index: None,
+                next: 0,
+                current: None,
+                error: None,
+            })),
+        }
+    }
+
+    /// The index, once every entry is complete
+    pub fn finish(&self) -> error::Result<ArchiveIndex> {
+        let mut state = self.state.borrow_mut();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        match &state.index {
+            Some(index)
+                if state.current.is_none()
+                    && state.next == index.entries.len() =>
+            {
+                Ok(index.clone())
+            }
+            _ => Err(error::Error::new_const(
+                ErrorKind::StreamTruncated,
+                &"The archive content is incomplete",
+            )),
+        }
+    }
+
+    /// The error of the writing. The cipher reports it as the I/O one
+    pub fn take_error(&self) -> Option<error::Error> {
+        self.state.borrow_mut().error.take()
+    }
+}
+
+impl Write for ArchiveWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.borrow_mut();
+        if let Some(e) = &state.error {
+            return Err(io::Error::other(e.to_string()));
+        }
+        match state.write_content(buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(e) => {
+                let io_error = io::Error::other(e.to_string());
+                state.error = Some(e);
+                Err(io_error)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Unpacker {
+    fn write_content(&mut self, mut buf: &[u8]) -> error::Result<()> {
+        while !buf.is_empty() {
+            if self.index.is_none() {
+                let count = self.read_header(buf)?;
+                buf = &buf[count..];
+                continue;
+            }
+
+            let current = self.current.as_mut().ok_or_else(|| {
+                error::Error::new_const(
+                    ErrorKind::FormatError,
+                    &"Content after the last archive entry",
+                )
+            })?;
+            let count = buf
+                .len()
+                .min(current.remaining as usize);
+            current.write_all(&buf[..count])?;
+            buf = &buf[count..];
+            if current.remaining == 0 {
+                self.close_entry()?;
+                self.open_entries()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the index length and the index. Returns the number of bytes read
+    fn read_header(&mut self, buf: &[u8]) -> error::Result<usize> {
+        let mut count = 0usize;
+        if self.header.len() < INDEX_LEN_SIZE {
+            count = buf
+                .len()
+                .min(INDEX_LEN_SIZE - self.header.len());
+            self.header
+                .extend_from_slice(&buf[..count]);
+            if self.header.len() < INDEX_LEN_SIZE {
+                return Ok(count);
+            }
+        }
+
+        let index_len = u64::from_le_bytes(
+            self.header[..INDEX_LEN_SIZE]
+                .try_into()
+                .expect("Wrong slice size"),
+        );
+        if index_len > MAX_INDEX_SIZE {
+            return Err(error::Error::new(
+                ErrorKind::FormatError,
+                format!("Not an archive or the index is too large: {index_len} bytes"),
+            ));
+        }
+        let header_len = INDEX_LEN_SIZE + index_len as usize;
+        let index_count =
+            (buf.len() - count).min(header_len - self.header.len());
+        self.header
+            .extend_from_slice(&buf[count..count + index_count]);
+        count += index_count;
+
+        if self.header.len() == header_len {
+            let index =
+                ArchiveIndex::try_from_bytes(&self.header[INDEX_LEN_SIZE..])?;
+            self.check_index(&index)?;
+            log::debug!(target: "archive ArchiveWriter", "Index: {} entries", index.entries.len());
+            self.index = Some(index);
+            self.header = Vec::new();
+            self.open_entries()?;
+        }
+        Ok(count)
+    }
+
+    fn check_index(&self, index: &ArchiveIndex) -> error::Result<()> {
+        if let Some(entry) = index
+            .entries
+            .iter()
+            .find(|entry| entry_file_path(Path::new(""), &entry.path).is_none())
+        {
+            return Err(error::Error::new(
+                ErrorKind::UnsafeFilename,
+                format!(
+                    "Unsafe archive entry path '{}'",
+                    entry.path_lossy()
+                ),
+            ));
+        }
+        if let UnpackTarget::Entry { path, .. } = &self.target {
+            if index.find(path).is_none() {
+                return Err(error::Error::new(
+                    ErrorKind::FileNotFound,
+                    format!(
+                        "Entry '{}' is not in the archive",
+                        String::from_utf8_lossy(path)
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the next entry. The empty entries are completed at once
+    fn open_entries(&mut self) -> error::Result<()> {
+        while self.current.is_none() {
+            let Some(entry) = self
+                .index
+                .as_ref()
+                .and_then(|index| index.entries.get(self.next))
+                .cloned()
+            else {
+                return Ok(());
+            };
+            self.next += 1;
+
+            let file_path = match &self.target {
+                UnpackTarget::List => None,
+                UnpackTarget::Directory(dir) => {
+                    entry_file_path(dir, &entry.path)
+                }
+                UnpackTarget::Entry { path, file_path } => {
+                    (*path == entry.path).then(|| file_path.clone())
+                }
+            };
+            let file = match file_path {
+                None => None,
+                Some(file_path) => {
+                    if let Some(parent) = file_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&file_path)?;
+                    Some((file_path, file))
+                }
+            };
+
+            let remaining = entry.content.size;
+            self.current = Some(UnpackedEntry {
+                entry,
+                remaining,
+                hasher: ContentHasher::default(),
+                file,
+            });
+            if remaining == 0 {
+                self.close_entry()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close_entry(&mut self) -> error::Result<()> {
+        let UnpackedEntry {
+            entry,
+            hasher,
+            file,
+            ..
+        } = self
+            .current
+            .take()
+            .expect("The current entry is written");
+        if hasher.finish() != entry.content {
+            return Err(error::Error::new(
+                ErrorKind::DigestMismatch,
+                format!(
+                    "Archive entry '{}' does not match its digest",
+                    entry.path_lossy()
+                ),
+            ));
+        }
+
+        if let Some((file_path, mut file)) = file {
+            file.flush()?;
+            drop(file);
+            if let Some(policy) = self.restore_policy {
+                for warning in restore_attributes(
+                    &file_path,
+                    &entry.attributes,
+                    &[],
+                    policy,
+                ) {
+                    log::warn!(target: "archive ArchiveWriter", "{warning}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UnpackedEntry {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.hasher.write_all(buf)?;
+        if let Some((_, file)) = &mut self.file {
+            file.write_all(buf)?;
+        }
+        self.remaining -= buf.len() as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use crate::archive::{
+    entry_file_path, entry_path, read_index, ArchiveWriter, PackReader,
+    UnpackTarget, INDEX_LEN_SIZE,
+};
+use crate::bulk::BulkReport;
+use crate::error;
+use crate::file::RestorePolicy;
+use crate::meta::index::ArchiveIndex;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[test]
+fn test_entry_path() {
+    let root = Path::new("/src");
+    assert_eq!(
+        entry_path(root, Path::new("/src/a/b.txt")),
+        Some(b"a/b.txt".to_vec())
+    );
+    assert_eq!(
+        entry_path(root, Path::new("/src")),
+        None
+    );
+    assert_eq!(
+        entry_path(root, Path::new("/other/b.txt")),
+        None
+    );
+}
+
+#[test]
+fn test_entry_file_path() {
+    let dir = Path::new("/out");
+    assert_eq!(
+        entry_file_path(dir, b"a/b.txt"),
+        Some(PathBuf::from("/out/a/b.txt"))
+    );
+    for path in [
+        b"".as_slice(),
+        b"../a.txt",
+        b"a/../../b.txt",
+        b"/etc/passwd",
+        b"a//b.txt",
+        b"a/./b.txt",
+        b"a\\..\\b.txt",
+    ] {
+        assert_eq!(
+            entry_file_path(dir, path),
+            None,
+            "{path:?}"
+        );
+    }
+}
+
+const TREE: [(&str, &[u8]); 4] = [
+    ("a.txt", b"first"),
+    ("empty", b""),
+    ("src/b.bin", &[0xAB; 10_000]),
+    ("src/c/d.txt", b"last"),
+];
+
+/// The tree of the files and the archive content of it
+fn pack_tree(root: &Path) -> error::Result<(ArchiveIndex, Vec<u8>)> {
+    let mut files = Vec::new();
+    for (name, content) in TREE {
+        let path = root.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, content)?;
+        files.push(path);
+    }
+
+    let mut report = BulkReport::default();
+    let (index, files) = read_index(root, files, &mut report);
+    assert!(report.is_success());
+
+    let mut reader = PackReader::new(&index, files);
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+    assert_eq!(
+        content.len() as u64,
+        reader.content_len()
+    );
+    Ok((index, content))
+}
+
+/// Writes the content by the small parts, like the chunks are
+fn unpack(content: &[u8], target: UnpackTarget) -> error::Result<ArchiveIndex> {
+    let mut writer = ArchiveWriter::new(target, Some(RestorePolicy::default()));
+    for part in content.chunks(777) {
+        if let Err(e) = writer.write_all(part) {
+            return Err(writer
+                .take_error()
+                .unwrap_or_else(|| e.into()));
+        }
+    }
+    writer.finish()
+}
+
+#[test]
+fn test_pack_unpack() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path().join("root");
+    let (index, content) = pack_tree(&root)?;
+    assert_eq!(
+        index
+            .entries
+            .iter()
+            .map(|entry| entry.path_lossy())
+            .collect::<Vec<_>>(),
+        TREE.map(|(name, _)| name)
+    );
+
+    let out_dir = temp.path().join("out");
+    fs::create_dir(&out_dir)?;
+    assert_eq!(
+        unpack(
+            &content,
+            UnpackTarget::Directory(out_dir.clone())
+        )?,
+        index
+    );
+    for (name, expected) in TREE {
+        assert_eq!(
+            fs::read(out_dir.join(name))?,
+            expected,
+            "{name}"
+        );
+    }
+
+    assert_eq!(
+        unpack(&content, UnpackTarget::List)?,
+        index
+    );
+
+    let file_path = temp.path().join("d.txt");
+    unpack(
+        &content,
+        UnpackTarget::Entry {
+            path: b"src/c/d.txt".to_vec(),
+            file_path: file_path.clone(),
+        },
+    )?;
+    assert_eq!(fs::read(file_path)?, b"last");
+
+    Ok(())
+}
+
+#[test]
+fn test_unpack_err() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let (_, content) = pack_tree(&temp.path().join("root"))?;
+
+    // Truncated
+    assert_eq!(
+        unpack(
+            &content[..content.len() - 1],
+            UnpackTarget::List
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        error::ErrorKind::StreamTruncated
+    );
+
+    // Extended
+    let extended = [content.as_slice(), b"x"].concat();
+    assert_eq!(
+        unpack(&extended, UnpackTarget::List)
+            .err()
+            .unwrap()
+            .kind(),
+        error::ErrorKind::FormatError
+    );
+
+    // Modified entry content
+    let mut modified = content.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 0x01;
+    assert_eq!(
+        unpack(&modified, UnpackTarget::List)
+            .err()
+            .unwrap()
+            .kind(),
+        error::ErrorKind::DigestMismatch
+    );
+
+    // Not an archive
+    assert_eq!(
+        unpack(
+            &[0xFF; INDEX_LEN_SIZE + 1],
+            UnpackTarget::List
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        error::ErrorKind::FormatError
+    );
+
+    let missing = UnpackTarget::Entry {
+        path: b"src/none".to_vec(),
+        file_path: temp.path().join("none"),
+    };
+    assert_eq!(
+        unpack(&content, missing)
+            .err()
+            .unwrap()
+            .kind(),
+        error::ErrorKind::FileNotFound
+    );
+    assert!(!temp.path().join("none").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_unpack_unsafe_path() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let (mut index, _) = pack_tree(&temp.path().join("root"))?;
+    index.entries[0].path = b"../escaped".to_vec();
+    let index_bytes = index.to_vec();
+    let content = [
+        (index_bytes.len() as u64)
+            .to_le_bytes()
+            .as_slice(),
+        &index_bytes,
+    ]
+    .concat();
+
+    let out_dir = temp.path().join("out");
+    fs::create_dir(&out_dir)?;
+    assert_eq!(
+        unpack(
+            &content,
+            UnpackTarget::Directory(out_dir)
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        error::ErrorKind::UnsafeFilename
+    );
+    assert!(!temp.path().join("escaped").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_pack_reader_changed() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    let file_path = root.join("a.txt");
+    fs::write(&file_path, "before")?;
+
+    let mut report = BulkReport::default();
+    let (index, files) = read_index(
+        root,
+        vec![file_path.clone()],
+        &mut report,
+    );
+    fs::write(&file_path, "after!")?;
+
+    let mut content = Vec::new();
+    assert!(PackReader::new(&index, files)
+        .read_to_end(&mut content)
+        .is_err());
+
+    // The file, which is not read, is left out
+    let (index, files) = read_index(
+        root,
+        vec![root.join("none")],
+        &mut report,
+    );
+    assert!(index.entries.is_empty());
+    assert!(files.is_empty());
+    assert_eq!(report.failed.len(), 1);
+
+    Ok(())
+}
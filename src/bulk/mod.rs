@@ -0,0 +1,232 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error;
+use core::fmt;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Ignore file with the gitignore semantics, read in every directory of the tree
+pub const IGNORE_FILE_NAME: &str = ".rfeignore";
+
+/// Which files of the directory tree the bulk operation processes and
+/// where it writes them, see [`crate::try_encrypt_dir`]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BulkOptions {
+    /// Output directory, which mirrors the tree.
+    /// The files are processed in place, if none
+    pub output_dir: Option<PathBuf>,
+
+    /// The original files are kept, when they are processed in place.
+    /// They are always kept with the output directory
+    pub keep_original: bool,
+
+    /// Glob patterns of the processed files, relative to the tree root.
+    /// All files are processed, if none
+    pub include: Vec<String>,
+
+    /// Glob patterns of the skipped files and directories,
+    /// in the gitignore syntax like the ignore file
+    pub exclude: Vec<String>,
+
+    /// Number of the files, processed concurrently.
+    /// The number of the CPUs, if 0
+    pub jobs: usize,
+}
+
+/// Result of the bulk operation. A failed file does not abort the walk
+#[derive(Debug, Default)]
+pub struct BulkReport {
+    /// Files, processed successfully
+    pub processed: Vec<PathBuf>,
+
+    /// Files, which are not subject to the operation
+    pub skipped: Vec<PathBuf>,
+
+    /// Files and directories, which are failed
+    pub failed: Vec<(PathBuf, error::Error)>,
+}
+
+impl BulkReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for BulkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Processed: {}, skipped: {}, failed: {}",
+            self.processed.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+        for (path, e) in &self.failed {
+            writeln!(f, "Failed {}: {e}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Regular files of the tree in the sorted order, filtered by the options
+/// and the ignore files ([`IGNORE_FILE_NAME`]). The symbolic links
+/// are not followed, the output directory is not walked.
+/// The files and directories, which can not be read, are reported as failed
+pub fn walk_files(
+    root: &Path,
+    options: &BulkOptions,
+    report: &mut BulkReport,
+) -> error::Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        return Err(error::Error::new(
+            error::ErrorKind::FileNotFound,
+            format!(
+                "Path '{}' is not a directory",
+                root.display()
+            ),
+        ));
+    }
+
+    let include = include_glob_set(&options.include)?;
+    let mut overrides = OverrideBuilder::new(root);
+    for pattern in &options.exclude {
+        overrides
+            .add(&format!("!{pattern}"))
+            .map_err(invalid_pattern)?;
+    }
+    let output_dir = options.output_dir.clone();
+    let walk = WalkBuilder::new(root)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .overrides(
+            overrides
+                .build()
+                .map_err(invalid_pattern)?,
+        )
+        .filter_entry(move |entry| Some(entry.path()) != output_dir.as_deref())
+        .build();
+
+    let mut result = Vec::new();
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!(target: "bulk walk_files", "Path is not read: {e}");
+                let path = match &e {
+                    ignore::Error::WithPath { path, .. } => path.clone(),
+                    _ => root.to_path_buf(),
+                };
+                report.failed.push((
+                    path,
+                    error::Error::new(error::ErrorKind::IOError, e),
+                ));
+                continue;
+            }
+        };
+        let path = entry.path();
+        match entry.file_type() {
+            Some(file_type) if file_type.is_dir() => {}
+            Some(file_type)
+                if file_type.is_file()
+                    && entry.file_name() != IGNORE_FILE_NAME
+                    && (include.is_empty()
+                        || include.is_match(
+                            path.strip_prefix(root).unwrap_or(path),
+                        )) =>
+            {
+                result.push(path.to_path_buf())
+            }
+            _ => {
+                log::debug!(target: "bulk walk_files", "File {path:?} is skipped");
+                report.skipped.push(path.to_path_buf());
+            }
+        }
+    }
+
+    result.sort();
+    Ok(result)
+}
+
+/// Processes the files on the pool of [`BulkOptions::jobs`] threads.
+/// `process` returns false, if the file is skipped.
+/// The results are collected in the order of the files and the progress
+/// is the number of the completed files only, so neither depends
+/// on the scheduling
+pub fn process_files<F>(
+    files: Vec<PathBuf>,
+    options: &BulkOptions,
+    report: &mut BulkReport,
+    process: F,
+) -> error::Result<()>
+where
+    F: Fn(&Path) -> error::Result<bool> + Sync,
+{
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.jobs)
+        .build()
+        .map_err(|e| error::Error::new(error::ErrorKind::OtherError, e))?;
+    log::debug!(target: "bulk process_files", "Files: {}, jobs: {}", files.len(), pool.current_num_threads());
+
+    let total = files.len();
+    let done = AtomicUsize::new(0);
+    let results: Vec<error::Result<bool>> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|file_path| {
+                let result = process(file_path);
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                log::info!(target: "bulk process_files", "Progress: {done}/{total} files");
+                result
+            })
+            .collect()
+    });
+
+    for (file_path, result) in files.into_iter().zip(results) {
+        match result {
+            Ok(true) => report.processed.push(file_path),
+            Ok(false) => report.skipped.push(file_path),
+            Err(e) => {
+                log::warn!(target: "bulk process_files", "File {file_path:?} is failed: {e}");
+                report.failed.push((file_path, e));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn include_glob_set(patterns: &[String]) -> error::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(invalid_pattern)?);
+    }
+    builder.build().map_err(invalid_pattern)
+}
+
+fn invalid_pattern<E>(e: E) -> error::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    error::Error::new(error::ErrorKind::InvalidArgument, e)
+}
+
+/// Path of the file of the tree in the mirror directory
+pub fn mirror_path(
+    root: &Path,
+    file_path: &Path,
+    mirror_dir: &Path,
+) -> PathBuf {
+    match file_path.strip_prefix(root) {
+        Ok(relative) => mirror_dir.join(relative),
+        Err(_) => mirror_dir.join(
+            file_path
+                .file_name()
+                .unwrap_or_default(),
+        ),
+    }
+}
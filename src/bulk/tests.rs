@@ -0,0 +1,251 @@
+use crate::bulk::{
+    mirror_path, process_files, walk_files, BulkOptions, BulkReport,
+};
+use crate::error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+fn test_walk_files() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    fs::create_dir_all(root.join("b/c"))?;
+    fs::create_dir_all(root.join("out/d"))?;
+    for name in ["z.txt", "a.txt", "b/x.txt", "b/c/y.txt", "out/d/o.txt"] {
+        fs::write(root.join(name), name)?;
+    }
+
+    let mut report = BulkReport::default();
+    let options = BulkOptions {
+        output_dir: Some(root.join("out")),
+        ..BulkOptions::default()
+    };
+    let files = walk_files(root, &options, &mut report)?;
+    assert_eq!(
+        files,
+        ["a.txt", "b/c/y.txt", "b/x.txt", "z.txt"].map(|name| root.join(name))
+    );
+    assert!(report.is_success());
+    assert!(report.skipped.is_empty());
+
+    Ok(())
+}
+
+/// Walks the tree of the files, returns the relative paths
+fn walk_tree(
+    files: &[(&str, &str)],
+    options: &BulkOptions,
+) -> error::Result<(Vec<String>, BulkReport)> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    for (name, content) in files {
+        let path = root.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
+
+    let mut report = BulkReport::default();
+    let files = walk_files(root, options, &mut report)?
+        .iter()
+        .map(|path| {
+            path.strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect();
+    Ok((files, report))
+}
+
+const TREE: [(&str, &str); 6] = [
+    ("a.txt", ""),
+    ("a.enc", ""),
+    ("src/b.txt", ""),
+    ("src/c.rs", ""),
+    ("target/d.txt", ""),
+    (".git/config", ""),
+];
+
+#[test]
+fn test_walk_files_filter() -> error::Result<()> {
+    let (files, _) = walk_tree(
+        &TREE,
+        &BulkOptions {
+            include: vec!["*.txt".to_string()],
+            exclude: vec!["target/".to_string(), ".git".to_string()],
+            ..BulkOptions::default()
+        },
+    )?;
+    assert_eq!(files, ["a.txt", "src/b.txt"]);
+
+    let (files, report) = walk_tree(
+        &TREE,
+        &BulkOptions {
+            exclude: vec!["*.enc".to_string(), "/src/*.rs".to_string()],
+            ..BulkOptions::default()
+        },
+    )?;
+    assert_eq!(
+        files,
+        [".git/config", "a.txt", "src/b.txt", "target/d.txt"]
+    );
+    // The excluded files are not walked at all
+    assert!(report.skipped.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_walk_files_ignore_file() -> error::Result<()> {
+    let tree = [
+        TREE.as_slice(),
+        &[
+            (".rfeignore", "target/\n.git/\n*.enc\n"),
+            ("src/.rfeignore", "*.txt\n!keep.txt\n"),
+            ("src/keep.txt", ""),
+        ],
+    ]
+    .concat();
+
+    let (files, report) = walk_tree(&tree, &BulkOptions::default())?;
+    assert_eq!(
+        files,
+        ["a.txt", "src/c.rs", "src/keep.txt"]
+    );
+    // The ignore files are not processed
+    assert_eq!(report.skipped.len(), 2);
+
+    // The include globs do not override the ignore file
+    let (files, _) = walk_tree(
+        &tree,
+        &BulkOptions {
+            include: vec!["src/*".to_string()],
+            ..BulkOptions::default()
+        },
+    )?;
+    assert_eq!(files, ["src/c.rs", "src/keep.txt"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_walk_files_err() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let file_path = temp.path().join("a.txt");
+    fs::write(&file_path, "a")?;
+
+    let mut report = BulkReport::default();
+    let options = BulkOptions::default();
+    assert!(walk_files(&file_path, &options, &mut report).is_err());
+    assert!(walk_files(
+        &temp.path().join("none"),
+        &options,
+        &mut report
+    )
+    .is_err());
+
+    // Invalid glob
+    let options = BulkOptions {
+        include: vec!["a[".to_string()],
+        ..BulkOptions::default()
+    };
+    assert_eq!(
+        walk_files(temp.path(), &options, &mut report)
+            .err()
+            .unwrap()
+            .kind(),
+        error::ErrorKind::InvalidArgument
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_mirror_path() {
+    assert_eq!(
+        mirror_path(
+            Path::new("/src"),
+            Path::new("/src/a/b.txt"),
+            Path::new("/out")
+        ),
+        Path::new("/out/a/b.txt")
+    );
+    assert_eq!(
+        mirror_path(
+            Path::new("/src"),
+            Path::new("/other/b.txt"),
+            Path::new("/out")
+        ),
+        Path::new("/out/b.txt")
+    );
+}
+
+#[test]
+fn test_process_files() -> error::Result<()> {
+    let files: Vec<PathBuf> = (0..8)
+        .map(|i| PathBuf::from(format!("{i}.txt")))
+        .collect();
+    let process = |file_path: &Path| {
+        let i: u64 = file_path
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        // The first files are completed last
+        sleep(Duration::from_millis(5 * (8 - i)));
+        match i % 3 {
+            0 => Ok(true),
+            1 => Ok(false),
+            _ => Err(error::Error::new_const(
+                error::ErrorKind::OtherError,
+                &"Failed",
+            )),
+        }
+    };
+
+    let summary = |jobs: usize| -> error::Result<_> {
+        let mut report = BulkReport::default();
+        process_files(
+            files.clone(),
+            &BulkOptions {
+                jobs,
+                ..BulkOptions::default()
+            },
+            &mut report,
+            process,
+        )?;
+        Ok((
+            report.processed,
+            report.skipped,
+            report
+                .failed
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>(),
+        ))
+    };
+
+    let (processed, skipped, failed) = summary(4)?;
+    assert_eq!(
+        processed,
+        ["0.txt", "3.txt", "6.txt"].map(PathBuf::from)
+    );
+    assert_eq!(
+        skipped,
+        ["1.txt", "4.txt", "7.txt"].map(PathBuf::from)
+    );
+    assert_eq!(
+        failed,
+        ["2.txt", "5.txt"].map(PathBuf::from)
+    );
+    assert_eq!(
+        summary(1)?,
+        (processed, skipped, failed)
+    );
+
+    Ok(())
+}
@@ -1,37 +1,85 @@
-use crate::cipher::kind::Cipher;
-use crate::{not_implemented, EncryptedMeta};
+use crate::cipher::kind::{
+    stream, Cipher, RecoveryReport, StreamParams, TargetSelector,
+};
+use crate::EncryptedMeta;
+use aes_gcm_siv::{aead::NewAead, Aes256GcmSiv};
 use std::io::{Read, Write};
 
-#[allow(dead_code)]
+/// AES-256-GCM-SIV.
+/// Uses the first 7 bytes of the nonce,
+/// because the AEAD nonce is 12 bytes long and STREAM takes 5 of them
 pub struct AesGcm {
     size: Option<usize>,
+    params: StreamParams,
 }
 
 impl AesGcm {
-    pub fn new(size: Option<usize>) -> Self {
-        Self { size }
+    pub fn new(size: Option<usize>, params: StreamParams) -> Self {
+        Self { size, params }
     }
 }
 
 impl Cipher for AesGcm {
     fn encrypt(
         &self,
-        _: Box<dyn Read>,
-        _: Box<dyn Write>,
-        _: &[u8; 32],
-        _: &[u8],
-        _: &EncryptedMeta,
+        source: Box<dyn Read>,
+        target: Box<dyn Write>,
+        key: &[u8; 32],
+        nonce: &[u8],
+        enc_meta: &EncryptedMeta,
     ) -> crate::error::Result<()> {
-        Err(not_implemented!("AesGcm::encrypt"))
+        log::debug!(target: "cipher/kind/aesgcm AesGcm encrypt", "Begin");
+        let aead = Aes256GcmSiv::new(key.as_ref().into());
+
+        stream::encrypt(
+            aead,
+            &self.params,
+            self.size,
+            source,
+            target,
+            nonce,
+            enc_meta,
+        )
     }
 
-    fn decrypt(
+    fn decrypt_with(
         &self,
-        _: Box<dyn Read>,
-        _: Box<dyn Write>,
-        _: &[u8; 32],
-        _: &[u8],
+        source: Box<dyn Read>,
+        select: TargetSelector<'_>,
+        key: &[u8; 32],
+        nonce: &[u8],
     ) -> crate::error::Result<EncryptedMeta> {
-        Err(not_implemented!("AesGcm::decrypt"))
+        log::debug!(target: "cipher/kind/aesgcm AesGcm decrypt", "Begin");
+        let aead = Aes256GcmSiv::new(key.as_ref().into());
+
+        stream::decrypt(
+            aead,
+            &self.params,
+            self.size,
+            source,
+            select,
+            nonce,
+        )
+    }
+
+    fn recover(
+        &self,
+        source: Box<dyn Read>,
+        target: Box<dyn Write>,
+        key: &[u8; 32],
+        nonce: &[u8],
+        zero_fill: bool,
+    ) -> crate::error::Result<RecoveryReport> {
+        log::debug!(target: "cipher/kind/aesgcm AesGcm recover", "Begin");
+        let aead = Aes256GcmSiv::new(key.as_ref().into());
+
+        stream::recover(
+            aead,
+            &self.params,
+            source,
+            target,
+            nonce,
+            zero_fill,
+        )
     }
 }
@@ -1,8 +1,130 @@
+use crate::kdf::DerivedKeyType;
+use crate::meta::raw::RawMeta;
+use crate::meta::slots::WRAPPED_KEY_SIZE;
+use crate::parity::ParityParams;
 use crate::{error, CipherKind, EncryptedMeta};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::XChaCha20Poly1305;
+use core::fmt;
 use std::io;
+use std::ops::Range;
 
 mod aesgcm;
 mod chacha20;
+mod stream;
+mod wrap;
+
+/// Parameters of the chunked STREAM framing.
+/// Determined by the RawMeta version
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct StreamParams {
+    /// The last chunk is encrypted with the STREAM "last block" flag,
+    /// so the truncation is detected. Since RawMeta version 3
+    pub finalized: bool,
+
+    /// Authenticated with every chunk. Since RawMeta version 4
+    /// it is the plain text file prefix (MetaHeader and RawMeta bytes)
+    pub associated_data: Vec<u8>,
+
+    /// Plain text chunk size. Stored since RawMeta version 5
+    pub chunk_size: usize,
+
+    /// The header and the key are authenticated by the key slots
+    /// before the stream is decrypted. Since RawMeta version 6
+    pub header_authenticated: bool,
+
+    /// Position of the stream in the file, used by the error reports
+    pub stream_offset: u64,
+
+    /// Reed-Solomon parity of the cipher text chunks. Since RawMeta version 8
+    pub parity: Option<ParityParams>,
+
+    /// Length of the EncryptedMeta with its MetaHeader,
+    /// the content offset in the plain text. Since RawMeta version 9
+    pub meta_len: Option<u64>,
+}
+
+impl StreamParams {
+    /// `raw_meta_bytes` are the MetaHeader and RawMeta bytes,
+    /// exactly as they are stored in the file
+    pub fn new(raw_meta: &RawMeta, raw_meta_bytes: &[u8]) -> Self {
+        Self {
+            finalized: raw_meta.version >= 3,
+            associated_data: if raw_meta.version >= 4 {
+                raw_meta_bytes.to_vec()
+            } else {
+                vec![]
+            },
+            chunk_size: raw_meta.chunk_size as usize,
+            header_authenticated: raw_meta.version >= 6,
+            stream_offset: 0,
+            parity: raw_meta.parity,
+            meta_len: raw_meta.meta_len.map(u64::from),
+        }
+    }
+}
+
+/// Result of the best-effort decryption, see [`crate::try_recover_with`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecoveryReport {
+    /// `None` if the chunks with the EncryptedMeta are lost
+    pub enc_meta: Option<EncryptedMeta>,
+
+    pub chunk_count: u32,
+
+    /// Indices of the chunks, which do not authenticate
+    pub lost_chunks: Vec<u32>,
+
+    /// Lost byte ranges of the original file.
+    /// If the EncryptedMeta of RawMeta version 8 or older is lost,
+    /// its length is unknown, so the ranges are counted
+    /// from the beginning of the plain text stream
+    pub lost_ranges: Vec<Range<u64>>,
+
+    /// The final chunk is missing or followed by the other chunks
+    pub truncated: bool,
+}
+
+impl RecoveryReport {
+    pub fn is_complete(&self) -> bool {
+        self.lost_chunks.is_empty() && !self.truncated
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Chunks: {}, lost: {}",
+            self.chunk_count,
+            self.lost_chunks.len()
+        )?;
+        if self.enc_meta.is_none() {
+            writeln!(
+                f,
+                "The original file name and the content offset are lost"
+            )?;
+        }
+        if self.truncated {
+            writeln!(f, "The stream is truncated or extended")?;
+        }
+        for range in &self.lost_ranges {
+            writeln!(
+                f,
+                "Lost bytes {}..{} ({} bytes)",
+                range.start,
+                range.end,
+                range.end - range.start
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Chooses the target of the decrypted content by the EncryptedMeta,
+/// see [`Cipher::decrypt_with`]
+pub type TargetSelector<'a> =
+    Box<dyn FnOnce(&EncryptedMeta) -> error::Result<Box<dyn io::Write>> + 'a>;
 
 pub trait Cipher {
     fn encrypt(
@@ -20,19 +142,94 @@ pub trait Cipher {
         target: Box<dyn io::Write>,
         key: &[u8; 32],
         nonce: &[u8],
+    ) -> error::Result<EncryptedMeta> {
+        self.decrypt_with(
+            source,
+            Box::new(|_| Ok(target)),
+            key,
+            nonce,
+        )
+    }
+
+    /// Decrypts into the target, which is selected by the EncryptedMeta,
+    /// before any content is written
+    fn decrypt_with(
+        &self,
+        source: Box<dyn io::Read>,
+        select: TargetSelector<'_>,
+        key: &[u8; 32],
+        nonce: &[u8],
     ) -> error::Result<EncryptedMeta>;
+
+    /// Decrypts the authentic chunks, the lost content is zero-filled
+    /// or skipped
+    fn recover(
+        &self,
+        source: Box<dyn io::Read>,
+        target: Box<dyn io::Write>,
+        key: &[u8; 32],
+        nonce: &[u8],
+        zero_fill: bool,
+    ) -> error::Result<RecoveryReport>;
 }
 
 pub fn select_cipher(
     kind: CipherKind,
+    params: StreamParams,
     original_size: Option<usize>,
 ) -> Box<dyn Cipher> {
+    match kind {
+        CipherKind::ChaCha20Poly1305 => Box::from(chacha20::ChaCha20::new(
+            original_size,
+            params,
+        )) as Box<dyn Cipher>,
+        CipherKind::AesGcm => Box::from(aesgcm::AesGcm::new(
+            original_size,
+            params,
+        )) as Box<dyn Cipher>,
+    }
+}
+
+/// Nonce size, actually used by the cipher.
+/// The STREAM nonce is 5 bytes shorter than the AEAD nonce
+pub fn nonce_size(kind: CipherKind) -> usize {
+    match kind {
+        CipherKind::ChaCha20Poly1305 => {
+            stream::nonce_size::<XChaCha20Poly1305>()
+        }
+        CipherKind::AesGcm => stream::nonce_size::<Aes256GcmSiv>(),
+    }
+}
+
+/// Encrypts the data key by the key encryption key (KEK),
+/// see [`crate::meta::slots::KeySlot`]
+pub fn wrap_key(
+    kind: CipherKind,
+    kek: &DerivedKeyType,
+    aad: &[u8],
+    key: &DerivedKeyType,
+) -> error::Result<[u8; WRAPPED_KEY_SIZE]> {
+    match kind {
+        CipherKind::ChaCha20Poly1305 => {
+            wrap::wrap::<XChaCha20Poly1305>(kek, aad, key)
+        }
+        CipherKind::AesGcm => wrap::wrap::<Aes256GcmSiv>(kek, aad, key),
+    }
+}
+
+/// Returns `None` if the KEK or the associated data does not match
+pub fn unwrap_key(
+    kind: CipherKind,
+    kek: &DerivedKeyType,
+    aad: &[u8],
+    wrapped_key: &[u8; WRAPPED_KEY_SIZE],
+) -> Option<DerivedKeyType> {
     match kind {
         CipherKind::ChaCha20Poly1305 => {
-            Box::from(chacha20::ChaCha20::new(original_size)) as Box<dyn Cipher>
+            wrap::unwrap::<XChaCha20Poly1305>(kek, aad, wrapped_key)
         }
         CipherKind::AesGcm => {
-            Box::from(aesgcm::AesGcm::new(original_size)) as Box<dyn Cipher>
+            wrap::unwrap::<Aes256GcmSiv>(kek, aad, wrapped_key)
         }
     }
 }
@@ -0,0 +1,686 @@
+use crate::cipher::kind::{RecoveryReport, StreamParams, TargetSelector};
+use crate::error;
+use crate::file::read_full;
+use crate::meta::enc::ContentHasher;
+use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use crate::meta::header::MetaHeader;
+use crate::parity::{ParityEncoder, ParityReader};
+use crate::EncryptedMeta;
+use chacha20poly1305::aead::generic_array::typenum::{Unsigned, U5};
+use chacha20poly1305::aead::generic_array::{ArrayLength, GenericArray};
+use chacha20poly1305::aead::stream::{NewStream, StreamBE32, StreamPrimitive};
+use chacha20poly1305::aead::{AeadCore, AeadInPlace, NewAead, Payload};
+use rayon::prelude::*;
+use std::io::{Read, Write};
+use std::ops::{Range, Sub};
+use std::{io, mem};
+
+/// The nonce size of the STREAM (BE32), which is 5 bytes shorter
+/// than the AEAD nonce
+type StreamNonceSize<A> = <<A as AeadCore>::NonceSize as Sub<U5>>::Output;
+
+pub(super) fn nonce_size<A>() -> usize
+where
+    A: AeadCore,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    StreamNonceSize::<A>::to_usize()
+}
+
+fn stream_nonce<A>(
+    nonce: &[u8],
+) -> error::Result<&GenericArray<u8, StreamNonceSize<A>>>
+where
+    A: AeadInPlace + NewAead,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    let nonce_size = nonce_size::<A>();
+    if nonce.len() < nonce_size {
+        return Err(error::Error::new(
+            error::ErrorKind::InvalidArgument,
+            format!(
+                "Nonce is too short, expected: {}, real: {}",
+                nonce_size,
+                nonce.len()
+            ),
+        ));
+    }
+
+    Ok(GenericArray::from_slice(
+        &nonce[..nonce_size],
+    ))
+}
+
+/// Chunks, encrypted or decrypted in parallel per thread of the current
+/// rayon pool. They are written in order, so the output is the same
+/// as the sequential one
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// Limits the memory of the batch, because the chunks are up to
+/// `RawMeta::MAX_CHUNK_SIZE` bytes long. The batch has one chunk at least
+const MAX_BATCH_SIZE: usize = 64 * 1024 * 1024;
+
+fn batch_len(chunk_len: usize) -> usize {
+    (MAX_BATCH_SIZE / chunk_len.max(1)).clamp(
+        1,
+        rayon::current_num_threads() * CHUNKS_PER_THREAD,
+    )
+}
+
+/// The chunk of the batch: its position, whether it is the last one
+/// and its bytes
+struct BatchChunk {
+    position: u32,
+    is_last: bool,
+    data: Vec<u8>,
+}
+
+/// Reads up to [`batch_len`] chunks of `chunk_len` bytes.
+/// `next` is the chunk, read ahead, because the last chunk must be known
+/// before its processing. `position` is the position of the next chunk
+fn read_batch(
+    source: &mut dyn Read,
+    chunk_len: usize,
+    finalized: bool,
+    next: &mut Vec<u8>,
+    position: &mut u32,
+) -> error::Result<Vec<BatchChunk>> {
+    let batch_len = batch_len(chunk_len);
+    let mut batch = Vec::with_capacity(batch_len);
+    while batch.len() < batch_len {
+        let data = mem::take(next);
+        let is_full = data.len() == chunk_len;
+        if is_full {
+            *next = vec![0u8; chunk_len];
+            let read_count = read_full(source, next)?;
+            next.truncate(read_count);
+        }
+        let is_last = if finalized { next.is_empty() } else { !is_full };
+        batch.push(BatchChunk {
+            position: *position,
+            is_last,
+            data,
+        });
+        if is_last {
+            break;
+        }
+        *position = next_position(*position)?;
+    }
+    Ok(batch)
+}
+
+struct CipherProcessing<'a> {
+    select: Option<TargetSelector<'a>>,
+    target: Option<Box<dyn Write>>,
+    enc_header: Option<MetaHeader>,
+    enc_meta_data: Vec<u8>,
+    enc_meta: Option<EncryptedMeta>,
+    hasher: ContentHasher,
+}
+
+impl<'a> CipherProcessing<'a> {
+    fn new(select: TargetSelector<'a>) -> Self {
+        Self {
+            select: Some(select),
+            target: None,
+            enc_header: None,
+            enc_meta_data: Vec::with_capacity(128),
+            enc_meta: None,
+            hasher: ContentHasher::default(),
+        }
+    }
+
+    /// Parses the EncryptedMeta, once it is complete,
+    /// and selects the target of the content by it
+    fn select_target(&mut self) -> error::Result<()> {
+        let Some(select) = self.select.take() else {
+            return Ok(());
+        };
+        log::trace!(target: "cipher/kind/stream CipherProcessing select_target", "Encrypted meta buffer: {:?}", self.enc_meta_data);
+        let version = self
+            .enc_header
+            .map_or(1, |header| header.version);
+        let enc_meta =
+            EncryptedMeta::try_from_bytes(version, &self.enc_meta_data)?;
+        self.target = Some(select(&enc_meta)?);
+        self.enc_meta = Some(enc_meta);
+        Ok(())
+    }
+
+    /// Checks the content by its digest
+    fn finish(mut self) -> error::Result<EncryptedMeta> {
+        self.select_target()?;
+        let enc_meta = self
+            .enc_meta
+            .expect("The target is selected by the meta");
+
+        if let Some(expected) = &enc_meta.content {
+            let real = self.hasher.finish();
+            if *expected != real {
+                return Err(error::Error::new_digest_mismatch(
+                    expected, &real,
+                ));
+            }
+            log::debug!(target: "cipher/kind/stream CipherProcessing finish", "Content digest matches");
+        }
+        Ok(enc_meta)
+    }
+
+    fn process(&mut self, buffer: Vec<u8>) -> error::Result<()> {
+        let mut cursor = io::Cursor::new(buffer);
+
+        if self.enc_header.is_none() {
+            // The chunk size is always >= header size
+            let mut enc_header_data = vec![0u8; MetaHeader::size()];
+            cursor.read_exact(&mut enc_header_data)?;
+            log::trace!(target: "cipher/kind/stream CipherProcessing process", "Meta header buffer: {:?}", enc_header_data);
+
+            let header = (&enc_header_data).try_into()?;
+            check_meta_header(&header)?;
+            self.enc_header = Some(header);
+            log::debug!(target: "cipher/kind/stream CipherProcessing process", "Read meta header");
+            log::trace!(target: "cipher/kind/stream CipherProcessing process", "MetaHeader: {:?}", header);
+        }
+        let header = self
+            .enc_header
+            .expect("Got none header after none processing");
+
+        // The EncryptedMeta may be split between the chunks
+        let mut tmp_enc_meta_data =
+            vec![0u8; (header.size as usize) - self.enc_meta_data.len()];
+        let read_count = cursor.read(&mut tmp_enc_meta_data)?;
+        self.enc_meta_data
+            .extend_from_slice(&tmp_enc_meta_data[..read_count]);
+        if self.enc_meta_data.len() < header.size as usize {
+            return Ok(());
+        }
+        self.select_target()?;
+
+        let content = &cursor.get_ref()[cursor.position() as usize..];
+        self.hasher.write_all(content)?;
+        self.target
+            .as_mut()
+            .expect("The target is selected by the meta")
+            .write_all(content)?;
+
+        Ok(())
+    }
+}
+
+/// Chunked STREAM framing, shared by all the AEAD ciphers
+///
+/// # Binary structure
+///
+/// The plain text is the MetaHeader of the EncryptedMeta,
+/// the EncryptedMeta itself and the file content.
+/// It is split into the chunks of `StreamParams::chunk_size` bytes,
+/// each chunk is encrypted as a separate STREAM (BE32) message,
+/// so the cipher text chunk is `chunk_size + tag size` bytes long.
+///
+/// If the stream is finalized, the last chunk is encrypted
+/// with the "last block" flag and is `1..=chunk_size` bytes long.
+/// Otherwise (legacy), the last chunk is always shorter than `chunk_size`
+/// (may be empty) and the truncation can not be detected.
+pub(super) fn encrypt<A>(
+    aead: A,
+    params: &StreamParams,
+    size: Option<usize>,
+    source: Box<dyn Read>,
+    mut target: Box<dyn Write>,
+    nonce: &[u8],
+    enc_meta: &EncryptedMeta,
+) -> error::Result<()>
+where
+    A: AeadInPlace + NewAead + Sync,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    let stream = StreamBE32::from_aead(aead, stream_nonce::<A>(nonce)?);
+
+    let mut glob_len = 0;
+
+    let header = MetaHeader {
+        size: enc_meta.len() as u64,
+        magic: MetaHeader::MAGIC,
+        version: EncryptedMeta::version(),
+    };
+    log::trace!(target: "cipher/kind/stream encrypt", "MetaHeader: {:?}",header);
+
+    let vec_enc = enc_meta.to_vec();
+    let meta_vector: Vec<u8> = header
+        .to_vec()
+        .into_iter()
+        .chain(vec_enc)
+        .collect();
+    let mut plain_source = io::Cursor::new(meta_vector).chain(source);
+
+    let mut parity = params.parity.map(|parity| {
+        ParityEncoder::new(
+            parity,
+            params.chunk_size + <A as AeadCore>::TagSize::to_usize(),
+        )
+    });
+
+    let mut position = 0u32;
+    let chunk_size = params.chunk_size;
+    let mut next = vec![0u8; chunk_size];
+    let read_count = read_full(&mut plain_source, &mut next)?;
+    next.truncate(read_count);
+
+    loop {
+        let batch = read_batch(
+            &mut plain_source,
+            chunk_size,
+            params.finalized,
+            &mut next,
+            &mut position,
+        )?;
+        for chunk in &batch {
+            log::debug!(target: "cipher/kind/stream encrypt","Plain text length: {}", chunk.data.len());
+            glob_len += chunk.data.len();
+            match size {
+                None => {
+                    log::debug!(target: "cipher/kind/stream encrypt", "Encrypting {:>6}", glob_len);
+                }
+                Some(size) => {
+                    log::debug!(target: "cipher/kind/stream encrypt", "Encrypting {:>6}/{:>6}", glob_len, size,);
+                }
+            }
+        }
+
+        let ciphertexts = batch
+            .par_iter()
+            .map(|chunk| {
+                stream.encrypt(
+                    chunk.position,
+                    params.finalized && chunk.is_last,
+                    Payload {
+                        msg: &chunk.data,
+                        aad: &params.associated_data,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for ciphertext in &ciphertexts {
+            log::trace!(target: "cipher/kind/stream encrypt","Ciphertext: {:?}", ciphertext);
+
+            // TODO: maybe replace by BufWriter
+            match &mut parity {
+                Some(parity) => parity.write_chunk(ciphertext, &mut target)?,
+                None => target.write_all(ciphertext)?,
+            }
+            log::debug!(target: "cipher/kind/stream encrypt","Ciphertext block written into the file");
+        }
+        if batch
+            .last()
+            .is_none_or(|chunk| chunk.is_last)
+        {
+            break;
+        }
+    }
+    if let Some(parity) = parity {
+        parity.finish(&mut target)?;
+    }
+
+    Ok(())
+}
+
+pub(super) fn decrypt<A>(
+    aead: A,
+    params: &StreamParams,
+    size: Option<usize>,
+    source: Box<dyn Read>,
+    select: TargetSelector<'_>,
+    nonce: &[u8],
+) -> error::Result<EncryptedMeta>
+where
+    A: AeadInPlace + NewAead + Sync,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    let stream = StreamBE32::from_aead(aead, stream_nonce::<A>(nonce)?);
+
+    let buffer_len = params.chunk_size + <A as AeadCore>::TagSize::to_usize();
+    let mut source = parity_source(&stream, params, buffer_len, source)?;
+    let mut glob_len = 0usize;
+
+    let mut processing = CipherProcessing::new(select);
+
+    let mut position = 0u32;
+    let mut next = vec![0u8; buffer_len];
+    let read_count = read_full(&mut source, &mut next)?;
+    next.truncate(read_count);
+
+    loop {
+        let batch = read_batch(
+            &mut source,
+            buffer_len,
+            params.finalized,
+            &mut next,
+            &mut position,
+        )?;
+        let plain_texts: Vec<_> = batch
+            .par_iter()
+            .map(|chunk| {
+                stream.decrypt(
+                    chunk.position,
+                    params.finalized && chunk.is_last,
+                    Payload {
+                        msg: &chunk.data,
+                        aad: &params.associated_data,
+                    },
+                )
+            })
+            .collect();
+
+        for (chunk, plain_text) in batch.iter().zip(plain_texts) {
+            let position = chunk.position;
+            let is_last = chunk.is_last;
+            log::trace!(target: "cipher/kind/stream decrypt","Buffer to decrypt: {:?}", chunk.data);
+            let payload = || Payload {
+                msg: &chunk.data,
+                aad: &params.associated_data,
+            };
+
+            let chunk_offset = params.stream_offset + glob_len as u64;
+            // The previous chunk is decrypted by the same key and
+            // the same associated data
+            let header_authenticated = params.header_authenticated
+                || (position > 0 && !params.associated_data.is_empty());
+            glob_len += chunk.data.len();
+
+            match size {
+                None => {
+                    log::debug!(target: "cipher/kind/stream decrypt", "Decrypting {:>6}", glob_len);
+                }
+                Some(size) => {
+                    log::debug!(target: "cipher/kind/stream decrypt", "Decrypting {:>6}/{:>6}", glob_len, size,);
+                }
+            }
+
+            let plain_text = match plain_text {
+                Ok(plain_text) => plain_text,
+                Err(_) => {
+                    // The chunk is authentic, but it is not expected here
+                    let err = if params.finalized
+                        && stream
+                            .decrypt(position, !is_last, payload())
+                            .is_ok()
+                    {
+                        error::Error::new_stream_truncated(position, is_last)
+                    } else {
+                        error::Error::new_chunk_corrupted(
+                            position,
+                            header_authenticated || position > 0,
+                        )
+                    };
+                    return Err(err
+                        .with_chunk(position, chunk_offset)
+                        .with_header_authenticated(header_authenticated));
+                }
+            };
+            log::debug!(target: "cipher/kind/stream decrypt","Plain text length: {}", plain_text.len());
+
+            processing.process(plain_text)?;
+        }
+        if batch
+            .last()
+            .is_none_or(|chunk| chunk.is_last)
+        {
+            break;
+        }
+    }
+
+    processing.finish()
+}
+
+/// The EncryptedMeta is buffered, so its size is limited
+fn check_meta_header(header: &MetaHeader) -> error::Result<()> {
+    if header.size > EncryptedMeta::MAX_SIZE {
+        return Err(
+            MetaError::from(MetaErrorKind::WrongEncryptedVecSize).into(),
+        );
+    }
+    Ok(())
+}
+
+/// Plain text of the recovered stream, written without the EncryptedMeta
+struct RecoverProcessing {
+    target: Box<dyn Write>,
+    zero_fill: bool,
+    /// MetaHeader and EncryptedMeta length, stored in the RawMeta
+    /// (since version 9) or known after the first chunk
+    meta_len: Option<u64>,
+    meta_version: u8,
+    enc_meta_data: Vec<u8>,
+    meta_lost: bool,
+    report: RecoveryReport,
+}
+
+impl RecoverProcessing {
+    /// Content range of the plain text stream range
+    fn content_range(&self, start: u64, end: u64) -> Range<u64> {
+        let meta_len = self.meta_len.unwrap_or(0);
+        start.max(meta_len) - meta_len..end.max(meta_len) - meta_len
+    }
+
+    fn process(&mut self, start: u64, plain_text: &[u8]) -> error::Result<()> {
+        if start == 0 {
+            let header: MetaHeader =
+                (&plain_text[..MetaHeader::size()].to_vec()).try_into()?;
+            check_meta_header(&header)?;
+            self.meta_len
+                .get_or_insert(MetaHeader::size() as u64 + header.size);
+            self.meta_version = header.version;
+        }
+
+        let end = start + plain_text.len() as u64;
+        // The chunk bytes before the content
+        let split = (self
+            .meta_len
+            .unwrap_or(0)
+            .clamp(start, end)
+            - start) as usize;
+        if !self.meta_lost {
+            let meta_begin =
+                (MetaHeader::size() as u64).clamp(start, end) - start;
+            self.enc_meta_data
+                .extend_from_slice(&plain_text[meta_begin as usize..split]);
+        }
+        self.target
+            .write_all(&plain_text[split..])?;
+
+        Ok(())
+    }
+
+    fn process_lost(
+        &mut self,
+        chunk_index: u32,
+        start: u64,
+        len: u64,
+    ) -> error::Result<()> {
+        let content = self.content_range(start, start + len);
+        if self
+            .meta_len
+            .is_none_or(|meta_len| start < meta_len)
+        {
+            self.meta_lost = true;
+        }
+        self.report
+            .lost_chunks
+            .push(chunk_index);
+
+        if content.is_empty() {
+            return Ok(());
+        }
+        if self.zero_fill {
+            io::copy(
+                &mut io::repeat(0).take(content.end - content.start),
+                &mut self.target,
+            )?;
+        }
+        match self.report.lost_ranges.last_mut() {
+            Some(last) if last.end == content.start => last.end = content.end,
+            _ => self.report.lost_ranges.push(content),
+        }
+
+        Ok(())
+    }
+}
+
+/// Decrypts every chunk, which authenticates, see [`encrypt`].
+/// The chunks are located by their size, so a damaged chunk
+/// does not affect the others. The content of the lost chunks
+/// is zero-filled (`zero_fill`) or skipped
+pub(super) fn recover<A>(
+    aead: A,
+    params: &StreamParams,
+    source: Box<dyn Read>,
+    target: Box<dyn Write>,
+    nonce: &[u8],
+    zero_fill: bool,
+) -> error::Result<RecoveryReport>
+where
+    A: AeadInPlace + NewAead,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    let stream = StreamBE32::from_aead(aead, stream_nonce::<A>(nonce)?);
+
+    let tag_size = <A as AeadCore>::TagSize::to_usize();
+    let buffer_len = params.chunk_size + tag_size;
+    let mut source = parity_source(&stream, params, buffer_len, source)?;
+    let mut processing = RecoverProcessing {
+        target,
+        zero_fill,
+        meta_len: params.meta_len,
+        meta_version: 1,
+        enc_meta_data: Vec::new(),
+        meta_lost: false,
+        report: RecoveryReport::default(),
+    };
+
+    let mut position = 0u32;
+    let mut plain_len = 0u64;
+    let mut buffer = vec![0u8; buffer_len];
+    let mut next_buffer = vec![0u8; buffer_len];
+    let mut read_count = read_full(&mut source, &mut buffer)?;
+
+    loop {
+        let next_read_count = if read_count == buffer_len {
+            read_full(&mut source, &mut next_buffer)?
+        } else {
+            0
+        };
+        let is_last = if params.finalized {
+            next_read_count == 0
+        } else {
+            read_count != buffer_len
+        };
+
+        let payload = || Payload {
+            msg: &buffer[..read_count],
+            aad: &params.associated_data,
+        };
+        let plain_text = stream
+            .decrypt(
+                position,
+                params.finalized && is_last,
+                payload(),
+            )
+            .ok()
+            .or_else(|| {
+                // The chunk is authentic, but the stream is truncated or extended
+                let plain_text = stream
+                    .decrypt(position, !is_last, payload())
+                    .ok()
+                    .filter(|_| params.finalized)?;
+                processing.report.truncated = true;
+                Some(plain_text)
+            });
+
+        match plain_text {
+            Some(plain_text)
+                if position > 0 || plain_text.len() >= MetaHeader::size() =>
+            {
+                processing.process(plain_len, &plain_text)?;
+                plain_len += plain_text.len() as u64;
+            }
+            _ => {
+                log::warn!(target: "cipher/kind/stream recover", "Chunk #{} is lost", position);
+                let len = read_count.saturating_sub(tag_size) as u64;
+                processing.process_lost(position, plain_len, len)?;
+                plain_len += len;
+            }
+        }
+        processing.report.chunk_count = position + 1;
+        if is_last {
+            break;
+        }
+
+        position = next_position(position)?;
+        mem::swap(&mut buffer, &mut next_buffer);
+        read_count = next_read_count;
+    }
+
+    let mut report = processing.report;
+    if !processing.meta_lost {
+        report.enc_meta = EncryptedMeta::try_from_bytes(
+            processing.meta_version,
+            &processing.enc_meta_data,
+        )
+        .ok();
+    }
+    Ok(report)
+}
+
+/// Repairs the cipher text chunks by the parity, if the stream has it.
+/// The chunk is verified by its decryption
+fn parity_source<'a, A>(
+    stream: &'a StreamBE32<A>,
+    params: &'a StreamParams,
+    buffer_len: usize,
+    source: Box<dyn Read>,
+) -> error::Result<Box<dyn Read + 'a>>
+where
+    A: AeadInPlace + NewAead,
+    A::NonceSize: Sub<U5>,
+    StreamNonceSize<A>: ArrayLength<u8>,
+{
+    let parity = match params.parity {
+        Some(parity) => parity,
+        None => return Ok(source),
+    };
+
+    let verify = move |position: u32, is_last: bool, chunk: &[u8]| {
+        let payload = Payload {
+            msg: chunk,
+            aad: &params.associated_data,
+        };
+        stream
+            .decrypt(
+                position,
+                params.finalized && is_last,
+                payload,
+            )
+            .is_ok()
+    };
+    Ok(Box::from(ParityReader::new(
+        source,
+        parity,
+        buffer_len,
+        Box::from(verify),
+    )?))
+}
+
+fn next_position(position: u32) -> error::Result<u32> {
+    position.checked_add(1).ok_or_else(|| {
+        error::Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"Too many chunks in the encrypted stream",
+        )
+    })
+}
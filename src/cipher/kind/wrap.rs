@@ -0,0 +1,50 @@
+use crate::error;
+use crate::kdf::DerivedKeyType;
+use crate::meta::slots::WRAPPED_KEY_SIZE;
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+
+/// Encrypts the data key by the key encryption key (KEK).
+///
+/// The all-zero nonce is used, because every KEK is used once:
+/// it is derived with a fresh salt or an ephemeral key.
+/// The unsalted [`crate::kdf::KdfKind::Sha256`] is rejected for the slots
+pub(super) fn wrap<A>(
+    kek: &DerivedKeyType,
+    aad: &[u8],
+    key: &DerivedKeyType,
+) -> error::Result<[u8; WRAPPED_KEY_SIZE]>
+where
+    A: Aead + NewAead,
+{
+    let aead = A::new(GenericArray::from_slice(kek));
+    let wrapped = aead.encrypt(
+        &GenericArray::default(),
+        Payload { msg: key, aad },
+    )?;
+
+    Ok(wrapped.as_slice().try_into()?)
+}
+
+/// Returns `None` if the KEK or the associated data does not match
+pub(super) fn unwrap<A>(
+    kek: &DerivedKeyType,
+    aad: &[u8],
+    wrapped_key: &[u8; WRAPPED_KEY_SIZE],
+) -> Option<DerivedKeyType>
+where
+    A: Aead + NewAead,
+{
+    let aead = A::new(GenericArray::from_slice(kek));
+    let key = aead
+        .decrypt(
+            &GenericArray::default(),
+            Payload {
+                msg: wrapped_key,
+                aad,
+            },
+        )
+        .ok()?;
+
+    key.as_slice().try_into().ok()
+}
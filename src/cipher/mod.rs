@@ -1,10 +1,14 @@
+use crate::error;
 use crate::meta::error::ErrorKind as MetaErrorKind;
 use crate::meta::error::MetaError;
+use std::str::FromStr;
 
 pub mod kind;
+pub mod nonce;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum CipherKind {
+    #[default]
     ChaCha20Poly1305,
     AesGcm,
 }
@@ -25,6 +29,35 @@ impl CipherKind {
             AesGcm => "Cipher AesGcm",
         }
     }
+
+    /// Name, used by the CLI
+    pub fn to_name(self) -> &'static str {
+        use CipherKind::*;
+        match self {
+            ChaCha20Poly1305 => "chacha20poly1305",
+            AesGcm => "aesgcm",
+        }
+    }
+}
+
+impl FromStr for CipherKind {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chacha20poly1305" | "chacha20" => Ok(CipherKind::ChaCha20Poly1305),
+            "aesgcm" | "aes-gcm-siv" => Ok(CipherKind::AesGcm),
+            _ => Err(error::Error::new(
+                error::ErrorKind::InvalidArgument,
+                format!(
+                    "Unknown cipher '{}', expected: {} or {}",
+                    s,
+                    CipherKind::ChaCha20Poly1305.to_name(),
+                    CipherKind::AesGcm.to_name(),
+                ),
+            )),
+        }
+    }
 }
 
 impl TryInto<CipherKind> for u8 {
@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod tests;
+
+use crate::cipher::kind::nonce_size;
+use crate::cipher::CipherKind;
+use crate::meta::raw::META_RAW_NONCE_SIZE;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Source of the nonce bytes
+pub trait NonceGenerator {
+    /// Fills the whole `nonce`
+    fn fill_nonce(&mut self, nonce: &mut [u8]);
+}
+
+/// Uniformly distributed bytes of the operating system CSPRNG
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomNonceGenerator;
+
+impl NonceGenerator for RandomNonceGenerator {
+    fn fill_nonce(&mut self, nonce: &mut [u8]) {
+        OsRng.fill_bytes(nonce);
+    }
+}
+
+/// Generates the nonce to be stored inside the RawMeta.
+///
+/// Only the bytes, used by the cipher (see [`nonce_size`]), are generated,
+/// the rest of `META_RAW_NONCE_SIZE` bytes are zeros
+pub fn generate_nonce(
+    kind: CipherKind,
+    generator: &mut dyn NonceGenerator,
+) -> [u8; META_RAW_NONCE_SIZE] {
+    let mut nonce = [0u8; META_RAW_NONCE_SIZE];
+    generator.fill_nonce(&mut nonce[..nonce_size(kind)]);
+
+    log::debug!(target: "cipher/nonce generate_nonce", "Generated {} nonce bytes for {}", nonce_size(kind), kind.to_str());
+    nonce
+}
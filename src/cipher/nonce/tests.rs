@@ -0,0 +1,68 @@
+use crate::cipher::kind::nonce_size;
+use crate::cipher::nonce::{
+    generate_nonce, NonceGenerator, RandomNonceGenerator,
+};
+use crate::cipher::CipherKind;
+use crate::meta::raw::META_RAW_NONCE_SIZE;
+
+struct FixedNonceGenerator;
+
+impl NonceGenerator for FixedNonceGenerator {
+    fn fill_nonce(&mut self, nonce: &mut [u8]) {
+        nonce.fill(0xFF);
+    }
+}
+
+#[test]
+fn test_nonce_size() {
+    assert_eq!(
+        nonce_size(CipherKind::ChaCha20Poly1305),
+        META_RAW_NONCE_SIZE
+    );
+    assert_eq!(nonce_size(CipherKind::AesGcm), 7);
+}
+
+#[test]
+fn test_generate_nonce_sized() {
+    let nonce = generate_nonce(
+        CipherKind::ChaCha20Poly1305,
+        &mut FixedNonceGenerator,
+    );
+    assert_eq!(nonce, [0xFFu8; META_RAW_NONCE_SIZE]);
+
+    let nonce = generate_nonce(
+        CipherKind::AesGcm,
+        &mut FixedNonceGenerator,
+    );
+    assert_eq!(nonce[..7], [0xFFu8; 7]);
+    assert_eq!(
+        nonce[7..],
+        [0u8; META_RAW_NONCE_SIZE - 7]
+    );
+}
+
+#[test]
+fn test_generate_nonce_random() {
+    let first = generate_nonce(
+        CipherKind::ChaCha20Poly1305,
+        &mut RandomNonceGenerator,
+    );
+    let second = generate_nonce(
+        CipherKind::ChaCha20Poly1305,
+        &mut RandomNonceGenerator,
+    );
+    assert_ne!(first, second);
+
+    // Alphanumeric characters only would never produce these bytes
+    let bytes = (0..64)
+        .flat_map(|_| {
+            generate_nonce(
+                CipherKind::ChaCha20Poly1305,
+                &mut RandomNonceGenerator,
+            )
+        })
+        .collect::<Vec<u8>>();
+    assert!(bytes
+        .iter()
+        .any(|b| !b.is_ascii_alphanumeric()));
+}
@@ -1,14 +1,43 @@
+use crate::cipher::CipherKind;
+use crate::encryption::FilenamePolicy;
 use clap::Parser;
 use std::ffi::OsString;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone)]
 pub struct AppData {
-    #[clap(help = "Path to the file", required = true)]
-    pub filepath: String,
+    #[clap(help = "Path to the file", required = true, parse(from_os_str))]
+    pub filepath: PathBuf,
 
     #[clap(short = 'k', long = "key", help = "Key")]
     pub key: Option<String>,
 
+    #[clap(
+        long = "keyfile",
+        help = "Keyfile, used instead of the key or together with it"
+    )]
+    pub keyfile: Option<String>,
+
+    #[clap(
+        long = "combine-key",
+        help = "Require both the keyfile and the key (asked, if not given)"
+    )]
+    pub combine_key: bool,
+
+    #[clap(
+        short = 'r',
+        long = "recipient",
+        help = "Public key or the recipients file to encrypt to, can be repeated"
+    )]
+    pub recipients: Vec<String>,
+
+    #[clap(
+        short = 'i',
+        long = "identity",
+        help = "Identity file or OpenSSH private key to decrypt with, can be repeated"
+    )]
+    pub identities: Vec<String>,
+
     #[clap(short = 'p', long = "preview", help = "Preview-only mode")]
     pub preview: Option<bool>,
 
@@ -17,6 +46,128 @@ pub struct AppData {
 
     #[clap(long = "keep", help = "Do not delete original file")]
     pub keep_original: bool,
+
+    #[clap(
+        long = "no-restore-attrs",
+        help = "Do not restore the original permissions, timestamps and extended attributes (decryption only)"
+    )]
+    pub no_restore_attributes: bool,
+
+    #[clap(
+        long = "restore-owner",
+        help = "Restore the original owner and group too (decryption only)"
+    )]
+    pub restore_owner: bool,
+
+    #[clap(
+        long = "restore-acls",
+        help = "Restore the stored POSIX ACLs too (decryption only)"
+    )]
+    pub restore_acls: bool,
+
+    #[clap(
+        long = "filename-policy",
+        help = "Unsafe original file name: reject (default), basename or rename (decryption only)"
+    )]
+    pub filename_policy: Option<FilenamePolicy>,
+
+    #[clap(
+        long = "recover",
+        help = "Decrypt the authentic chunks of the damaged file, zero-filling the lost ones (the file is kept)"
+    )]
+    pub recover: bool,
+
+    #[clap(
+        long = "skip-lost",
+        help = "Skip the lost content instead of zero-filling it (recovery only)"
+    )]
+    pub skip_lost: bool,
+
+    #[clap(
+        long = "verify",
+        help = "Decrypt in memory and check the content digest, do not write the plain text"
+    )]
+    pub verify: bool,
+
+    #[clap(
+        long = "list",
+        help = "List the entries of the archive and check them, do not unpack"
+    )]
+    pub list: bool,
+
+    #[clap(
+        long = "extract",
+        help = "Extract the single entry of the archive by its path (the archive is kept)",
+        parse(from_os_str)
+    )]
+    pub extract: Option<OsString>,
+
+    #[clap(
+        long = "cipher",
+        help = "Cipher: chacha20poly1305 (default) or aesgcm (encryption only)"
+    )]
+    pub cipher: Option<CipherKind>,
+
+    #[clap(
+        long = "kdf-memory",
+        help = "Argon2id memory cost in KiB (encryption only)"
+    )]
+    pub kdf_memory: Option<u32>,
+
+    #[clap(
+        long = "kdf-time",
+        help = "Argon2id number of passes (encryption only)"
+    )]
+    pub kdf_time: Option<u32>,
+
+    #[clap(
+        long = "kdf-parallelism",
+        help = "Argon2id number of lanes (encryption only)"
+    )]
+    pub kdf_parallelism: Option<u32>,
+
+    #[clap(
+        long = "chunk-size",
+        help = "Plain text chunk size in bytes (encryption only)"
+    )]
+    pub chunk_size: Option<u32>,
+
+    #[clap(
+        long = "parity",
+        help = "Reed-Solomon parity shards per 16 chunks, repairing as many damaged chunks (encryption only)"
+    )]
+    pub parity: Option<u8>,
+
+    #[clap(
+        long = "xattrs",
+        help = "Store the extended attributes and the ACLs of the file (encryption only)"
+    )]
+    pub xattrs: bool,
+
+    #[clap(
+        long = "include",
+        help = "Glob of the files to process or pack, can be repeated (directories only)"
+    )]
+    pub include: Vec<String>,
+
+    #[clap(
+        long = "exclude",
+        help = "Gitignore pattern of the files to skip, can be repeated (directories only)"
+    )]
+    pub exclude: Vec<String>,
+
+    #[clap(
+        short = 'j',
+        long = "jobs",
+        help = "Number of the threads for the files of the directory and the chunks of the file, the number of the CPUs by default"
+    )]
+    pub jobs: Option<usize>,
+
+    #[clap(
+        long = "inspect",
+        help = "Print the encrypted file header, do not decrypt"
+    )]
+    pub inspect: bool,
 }
 
 pub fn get_arguments<I, T>(itr: I) -> AppData
@@ -1,12 +1,25 @@
 use crate::app::context::{
-    get_context_preview, set_context_key_hash, AppContext,
+    get_context_archive_options, get_context_bulk_options,
+    get_context_decryption_options, get_context_encryption_options,
+    get_context_identities, get_context_recipients, is_recipient_key_arg,
+    parse_identity_arg, parse_recipient_arg, set_context_key, user_key,
+    AppContext,
 };
+use crate::bulk::BulkOptions;
 use crate::cli::runtime::{
     CommandProcessor, CommandProcessorContext, HintOption,
 };
-use crate::encryption::{try_detect_file_type, DetectedFileType};
+use crate::encryption::{try_detect_file_type, try_inspect, DetectedFileType};
 use crate::error::{Error, ErrorKind, Result};
-use crate::{error, try_decrypt, try_encrypt};
+use crate::keyfile::Keyfile;
+use crate::keyslot::{Identity, Recipient};
+use crate::recipient::write_new_identity;
+use crate::{
+    error, try_add_key_with, try_decrypt_dir, try_decrypt_with_options,
+    try_encrypt_dir, try_encrypt_to, try_extract, try_list_archive, try_pack,
+    try_recover_with, try_rekey_with, try_remove_key_with, try_unpack,
+    try_verify_with,
+};
 use path_absolutize::Absolutize;
 use std::ffi::OsStr;
 use std::fs;
@@ -120,7 +133,7 @@ impl CommandProcessor<AppContext> for CmdSetKey {
         _command: &str,
         _arguments: &[String],
     ) -> Result<()> {
-        set_context_key_hash(ctx)?;
+        set_context_key(ctx)?;
         Ok(())
     }
 }
@@ -140,7 +153,59 @@ impl CommandProcessor<AppContext> for CmdUnsetKey {
         _command: &str,
         _arguments: &[String],
     ) -> Result<()> {
-        ctx.key_hash = None;
+        ctx.key = None;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdSetKeyfile {}
+
+impl CommandProcessor<AppContext> for CmdSetKeyfile {
+    command_processor_template!("set-keyfile");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "KEYFILE_NAME (combined with the key, if it is set)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 argument",
+            ));
+        }
+
+        // The path is kept absolute, so `cd` does not break it
+        let path = PathBuf::from(&ctx.cli_current_path).join(&arguments[0]);
+        Keyfile::read(&path)?;
+        ctx.data.keyfile = Some(path.display().to_string());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdUnsetKeyfile {}
+
+impl CommandProcessor<AppContext> for CmdUnsetKeyfile {
+    command_processor_template!("unset-keyfile");
+    command_processor_nohint!();
+    command_processor_nohelp_args!();
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        _arguments: &[String],
+    ) -> Result<()> {
+        ctx.data.keyfile = None;
         Ok(())
     }
 }
@@ -225,6 +290,162 @@ impl CommandProcessor<AppContext> for CmdUnsetKeepOriginal {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct CmdSetCipher {}
+
+impl CommandProcessor<AppContext> for CmdSetCipher {
+    command_processor_template!("set-cipher");
+    command_processor_nohint!();
+    command_processor_help_args!("chacha20poly1305|aesgcm");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 argument",
+            ));
+        }
+
+        ctx.data.cipher = Some(arguments[0].parse()?);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdAddRecipient {}
+
+impl CommandProcessor<AppContext> for CmdAddRecipient {
+    command_processor_template!("add-recipient");
+    command_processor_filehint!();
+    command_processor_help_args!("PUBLIC_KEY|RECIPIENTS_FILE_NAME");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 argument",
+            ));
+        }
+
+        parse_recipient_arg(ctx, &arguments[0])?;
+        let recipient = if is_recipient_key_arg(&arguments[0]) {
+            arguments[0].clone()
+        } else {
+            // The path is kept absolute, so `cd` does not break it
+            PathBuf::from(&ctx.cli_current_path)
+                .join(&arguments[0])
+                .display()
+                .to_string()
+        };
+        ctx.data.recipients.push(recipient);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdAddIdentity {}
+
+impl CommandProcessor<AppContext> for CmdAddIdentity {
+    command_processor_template!("add-identity");
+    command_processor_filehint!();
+    command_processor_help_args!("IDENTITY_FILE_NAME");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 argument",
+            ));
+        }
+
+        // The path is kept absolute, so `cd` does not break it
+        let path = PathBuf::from(&ctx.cli_current_path).join(&arguments[0]);
+        parse_identity_arg(ctx, &arguments[0])?;
+        ctx.data
+            .identities
+            .push(path.display().to_string());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdUnsetRecipients {}
+
+impl CommandProcessor<AppContext> for CmdUnsetRecipients {
+    command_processor_template!("unset-recipients");
+    command_processor_nohint!();
+    command_processor_nohelp_args!();
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        _arguments: &[String],
+    ) -> Result<()> {
+        ctx.data.recipients.clear();
+        ctx.data.identities.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdKeygen {}
+
+impl CommandProcessor<AppContext> for CmdKeygen {
+    command_processor_template!("keygen");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "IDENTITY_FILE_NAME (the recipient is written to IDENTITY_FILE_NAME.pub)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 argument",
+            ));
+        }
+        let raw_path = PathBuf::from(&ctx.cli_current_path).join(&arguments[0]);
+        let file_path = raw_path.absolutize()?;
+
+        let pub_path = write_new_identity(&file_path)?;
+        log::info!(
+            target: "CmdKeygen",
+            "Identity: {}, recipient: {}",
+            file_path.display(),
+            pub_path.display()
+        );
+        ctx.term
+            .write_line(fs::read_to_string(&pub_path)?.trim())?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CmdGetAllParameters {}
 
@@ -250,16 +471,72 @@ impl CommandProcessor<AppContext> for CmdGetAllParameters {
                 ctx.data.preview.unwrap_or(false)
             )],
             [format!(
-                "key hash: {}",
-                match ctx.key_hash {
+                "key: {}",
+                match ctx.key {
                     Some(_) => "Set",
                     None => "Unset",
                 }
             )],
+            [format!(
+                "keyfile: {}",
+                ctx.data
+                    .keyfile
+                    .as_deref()
+                    .unwrap_or("Unset")
+            )],
+            [format!(
+                "recipients: {}",
+                ctx.data.recipients.join(", ")
+            )],
+            [format!(
+                "identities: {}",
+                ctx.data.identities.join(", ")
+            )],
             [format!(
                 "keep_original: {}",
                 ctx.data.keep_original
             )],
+            [format!(
+                "restore_attributes: {}",
+                !ctx.data.no_restore_attributes
+            )],
+            [format!(
+                "restore_owner: {}",
+                ctx.data.restore_owner
+            )],
+            [format!(
+                "restore_acls: {}",
+                ctx.data.restore_acls
+            )],
+            [format!(
+                "filename_policy: {}",
+                ctx.data
+                    .filename_policy
+                    .unwrap_or_default()
+                    .to_name()
+            )],
+            [format!(
+                "cipher: {}",
+                ctx.data
+                    .cipher
+                    .unwrap_or_default()
+                    .to_name()
+            )],
+            [format!(
+                "include: {}",
+                ctx.data.include.join(", ")
+            )],
+            [format!(
+                "exclude: {}",
+                ctx.data.exclude.join(", ")
+            )],
+            [format!(
+                "jobs: {}",
+                match ctx.data.jobs {
+                    Some(jobs) => jobs.to_string(),
+                    None => "Number of the CPUs".to_string(),
+                }
+            )],
         ];
         for arg in args {
             ctx.term.write_line(arg[0].as_str())?;
@@ -520,19 +797,22 @@ impl CommandProcessor<AppContext> for CmdEncrypt {
             ),
         };
 
-        try_encrypt(
+        let recipients = get_context_recipients(ctx)?;
+        if recipients.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"No key, no keyfile and no recipients",
+            ));
+        }
+
+        try_encrypt_to(
             &file_path,
             match &out_path {
                 None => None,
                 Some(p) => Some(p),
             },
-            match ctx.key_hash {
-                None => Err(Error::new_const(
-                    error::ErrorKind::InvalidArgument,
-                    &"No key hash",
-                )),
-                Some(v) => Ok(v),
-            }?,
+            &recipients,
+            &get_context_encryption_options(ctx),
         )?;
 
         if !ctx.data.keep_original {
@@ -571,7 +851,7 @@ impl CommandProcessor<AppContext> for CmdDecrypt {
         let file_path = raw_path.absolutize()?;
         log::info!(target: "CmdDecrypt", "Decrypting file: {}", file_path.display());
 
-        let preview = get_context_preview(ctx)?;
+        let options = get_context_decryption_options(ctx)?;
         if !file_path.is_file() {
             return Err(Error::new(
                 ErrorKind::FileNotFound,
@@ -593,18 +873,12 @@ impl CommandProcessor<AppContext> for CmdDecrypt {
             ));
         }
 
-        try_decrypt(
+        try_decrypt_with_options(
             &file_path,
-            match ctx.key_hash {
-                None => Err(Error::new_const(
-                    error::ErrorKind::InvalidArgument,
-                    &"No key hash",
-                )),
-                Some(v) => Ok(v),
-            }?,
-            preview,
+            &get_unlock_identities(ctx)?,
+            &options,
         )?;
-        if !ctx.data.keep_original && !preview {
+        if !ctx.data.keep_original && !options.preview {
             log::info!(
                 "Original file '{}' will be removed",
                 file_path.display()
@@ -615,19 +889,532 @@ impl CommandProcessor<AppContext> for CmdDecrypt {
     }
 }
 
+/// The directory and the optional mirror output directory
+fn get_dir_arguments(
+    ctx: &AppContext,
+    arguments: &[String],
+) -> Result<BulkOptions> {
+    if arguments.is_empty() {
+        return Err(Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"Expected 1 or 2 arguments",
+        ));
+    }
+    let output_dir = match arguments.get(1) {
+        None => None,
+        Some(value) => Some(get_path_argument(ctx, value)?),
+    };
+    Ok(get_context_bulk_options(
+        ctx, output_dir,
+    ))
+}
+
+/// The path relative to the current directory
+fn get_path_argument(ctx: &AppContext, value: &str) -> Result<PathBuf> {
+    let r_path = PathBuf::from(&ctx.cli_current_path).join(value);
+    Ok(r_path.absolutize()?.to_path_buf())
+}
+
+fn get_dir_path(ctx: &AppContext, arguments: &[String]) -> Result<PathBuf> {
+    let raw_path = PathBuf::from(&ctx.cli_current_path).join(&arguments[0]);
+    let dir_path = raw_path.absolutize()?.to_path_buf();
+    if !dir_path.is_dir() {
+        return Err(Error::new(
+            ErrorKind::FileNotFound,
+            format!(
+                "Path '{}' is not a directory",
+                dir_path.display()
+            ),
+        ));
+    }
+    Ok(dir_path)
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdEncryptDir {}
+
+impl CommandProcessor<AppContext> for CmdEncryptDir {
+    command_processor_template!("encrypt-dir");
+    command_processor_filehint!();
+    command_processor_help_args!("DIR_PATH [OUT_DIR_PATH]");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let bulk = get_dir_arguments(ctx, arguments)?;
+        let dir_path = get_dir_path(ctx, arguments)?;
+
+        let recipients = get_context_recipients(ctx)?;
+        if recipients.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"No key, no keyfile and no recipients",
+            ));
+        }
+
+        log::info!(target: "CmdEncryptDir", "Encrypting directory: {}", dir_path.display());
+        let report = try_encrypt_dir(
+            &dir_path,
+            &recipients,
+            &get_context_encryption_options(ctx),
+            &bulk,
+        )?;
+        ctx.term
+            .write_str(&report.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdDecryptDir {}
+
+impl CommandProcessor<AppContext> for CmdDecryptDir {
+    command_processor_template!("decrypt-dir");
+    command_processor_filehint!();
+    command_processor_help_args!("DIR_PATH [OUT_DIR_PATH]");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let bulk = get_dir_arguments(ctx, arguments)?;
+        let dir_path = get_dir_path(ctx, arguments)?;
+
+        log::info!(target: "CmdDecryptDir", "Decrypting directory: {}", dir_path.display());
+        let report = try_decrypt_dir(
+            &dir_path,
+            &get_unlock_identities(ctx)?,
+            &get_context_decryption_options(ctx)?,
+            &bulk,
+        )?;
+        ctx.term
+            .write_str(&report.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdPack {}
+
+impl CommandProcessor<AppContext> for CmdPack {
+    command_processor_template!("pack");
+    command_processor_filehint!();
+    command_processor_help_args!("DIR_PATH [ARCHIVE_PATH]");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        if arguments.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 1 or 2 arguments",
+            ));
+        }
+        let dir_path = get_dir_path(ctx, arguments)?;
+        let out_file_path = match arguments.get(1) {
+            None => None,
+            Some(value) => Some(get_path_argument(ctx, value)?),
+        };
+
+        let recipients = get_context_recipients(ctx)?;
+        if recipients.is_empty() {
+            return Err(Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"No key, no keyfile and no recipients",
+            ));
+        }
+
+        log::info!(target: "CmdPack", "Packing directory: {}", dir_path.display());
+        let report = try_pack(
+            &dir_path,
+            out_file_path.as_deref(),
+            &recipients,
+            &get_context_encryption_options(ctx),
+            &get_context_bulk_options(ctx, None),
+        )?;
+        ctx.term
+            .write_str(&report.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdUnpack {}
+
+impl CommandProcessor<AppContext> for CmdUnpack {
+    command_processor_template!("unpack");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "ARCHIVE_PATH [OUT_DIR_PATH] (the archive is kept)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let out_dir = match arguments.get(1) {
+            None => None,
+            Some(value) => Some(get_path_argument(ctx, value)?),
+        };
+
+        log::info!(target: "CmdUnpack", "Unpacking archive: {}", file_path.display());
+        let index = try_unpack(
+            &file_path,
+            out_dir.as_deref(),
+            &get_unlock_identities(ctx)?,
+            &get_context_archive_options(ctx),
+        )?;
+        ctx.term.write_str(&format!(
+            "Unpacked entries: {}\n",
+            index.entries.len()
+        ))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdList {}
+
+impl CommandProcessor<AppContext> for CmdList {
+    command_processor_template!("list");
+    command_processor_filehint!();
+    command_processor_help_args!("ARCHIVE_PATH (nothing is written)");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+
+        log::info!(target: "CmdList", "Listing archive: {}", file_path.display());
+        let index = try_list_archive(&file_path, &get_unlock_identities(ctx)?)?;
+        ctx.term.write_str(&index.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdExtract {}
+
+impl CommandProcessor<AppContext> for CmdExtract {
+    command_processor_template!("extract");
+    command_processor_filehint!();
+    command_processor_help_args!("ARCHIVE_PATH ENTRY_PATH [OUT_DIR_PATH]");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let entry_path = arguments.get(1).ok_or_else(|| {
+            Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Expected 2 or 3 arguments",
+            )
+        })?;
+        let out_dir = match arguments.get(2) {
+            None => None,
+            Some(value) => Some(get_path_argument(ctx, value)?),
+        };
+
+        log::info!(target: "CmdExtract", "Extracting entry {entry_path} of archive: {}", file_path.display());
+        let extracted = try_extract(
+            &file_path,
+            entry_path.as_bytes(),
+            out_dir.as_deref(),
+            &get_unlock_identities(ctx)?,
+            &get_context_archive_options(ctx),
+        )?;
+        ctx.term.write_str(&format!(
+            "Extracted into {}\n",
+            extracted.display()
+        ))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdRecover {}
+
+impl CommandProcessor<AppContext> for CmdRecover {
+    command_processor_template!("recover");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "ENCRYPTED_FILE_NAME [skip] (the file is kept)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let zero_fill = match arguments.get(1).map(String::as_str) {
+            None => true,
+            Some("skip") => false,
+            Some(_) => {
+                return Err(Error::new_const(
+                    error::ErrorKind::InvalidArgument,
+                    &"Expected 'skip' as the second argument",
+                ))
+            }
+        };
+
+        log::info!(target: "CmdRecover", "Recovering file: {}", file_path.display());
+        let report = try_recover_with(
+            &file_path,
+            &get_unlock_identities(ctx)?,
+            zero_fill,
+        )?;
+        ctx.term
+            .write_str(&report.to_string())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdVerify {}
+
+impl CommandProcessor<AppContext> for CmdVerify {
+    command_processor_template!("verify");
+    command_processor_filehint!();
+    command_processor_help_args!("ENCRYPTED_FILE_NAME (nothing is written)");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+
+        log::info!(target: "CmdVerify", "Verifying file: {}", file_path.display());
+        let enc_meta =
+            try_verify_with(&file_path, &get_unlock_identities(ctx)?)?;
+        ctx.term.write_str(&format!(
+            "Verified successfully\n{enc_meta}"
+        ))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdInspect {}
+
+impl CommandProcessor<AppContext> for CmdInspect {
+    command_processor_template!("inspect");
+    command_processor_filehint!();
+    command_processor_help_args!("ENCRYPTED_FILE_NAME");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let inspection = try_inspect(&file_path)?;
+        ctx.term
+            .write_str(&inspection.to_string())?;
+        Ok(())
+    }
+}
+
+/// Resolves the path of the existing encrypted file
+fn get_encrypted_file_path(
+    ctx: &AppContext,
+    arguments: &[String],
+) -> Result<PathBuf> {
+    if arguments.is_empty() {
+        return Err(Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"Expected 1 argument",
+        ));
+    }
+    let raw_path = PathBuf::from(&ctx.cli_current_path).join(&arguments[0]);
+    let file_path = raw_path.absolutize()?.to_path_buf();
+
+    if !file_path.is_file() {
+        return Err(Error::new(
+            ErrorKind::FileNotFound,
+            format!(
+                "Path '{}' is not a file",
+                file_path.display()
+            ),
+        ));
+    }
+    if try_detect_file_type(&file_path)? != DetectedFileType::Encrypted {
+        return Err(Error::new(
+            ErrorKind::EncryptedMetaDecodeError,
+            format!(
+                "Path '{}' does not look like an encrypted file",
+                file_path.display()
+            ),
+        ));
+    }
+
+    Ok(file_path)
+}
+
+/// The key, the keyfile and the identities, which unlock the file
+fn get_unlock_identities(ctx: &AppContext) -> Result<Vec<Identity>> {
+    let identities = get_context_identities(ctx)?;
+    if identities.is_empty() {
+        return Err(Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"No key, no keyfile and no identities",
+        ));
+    }
+    Ok(identities)
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdAddKey {}
+
+impl CommandProcessor<AppContext> for CmdAddKey {
+    command_processor_template!("add-key");
+    command_processor_filehint!();
+    command_processor_help_args!("ENCRYPTED_FILE_NAME");
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let identities = get_unlock_identities(ctx)?;
+
+        ctx.term
+            .write_line("Enter the key to add")?;
+        let new_key = user_key()?;
+
+        log::info!(target: "CmdAddKey", "Adding the key slot: {}", file_path.display());
+        try_add_key_with(
+            &file_path,
+            &identities,
+            &Recipient::Password(new_key),
+            &get_context_encryption_options(ctx).kdf,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdRemoveKey {}
+
+impl CommandProcessor<AppContext> for CmdRemoveKey {
+    command_processor_template!("remove-key");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "ENCRYPTED_FILE_NAME (removes the current key)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+
+        log::info!(target: "CmdRemoveKey", "Removing the key slot: {}", file_path.display());
+        try_remove_key_with(&file_path, &get_unlock_identities(ctx)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CmdRekey {}
+
+impl CommandProcessor<AppContext> for CmdRekey {
+    command_processor_template!("rekey");
+    command_processor_filehint!();
+    command_processor_help_args!(
+        "ENCRYPTED_FILE_NAME (replaces the current key)"
+    );
+
+    fn process_command(
+        &self,
+        ctx: &mut AppContext,
+        _cmd_context: &CommandProcessorContext<AppContext>,
+        _command: &str,
+        arguments: &[String],
+    ) -> Result<()> {
+        let file_path = get_encrypted_file_path(ctx, arguments)?;
+        let identities = get_unlock_identities(ctx)?;
+
+        ctx.term
+            .write_line("Enter the new key")?;
+        let new_key = user_key()?;
+
+        log::info!(target: "CmdRekey", "Replacing the key slot: {}", file_path.display());
+        try_rekey_with(
+            &file_path,
+            &identities,
+            &Recipient::Password(new_key),
+            &get_context_encryption_options(ctx).kdf,
+        )
+    }
+}
+
 pub fn register_all_commands(
     cmd_context: &mut CommandProcessorContext<AppContext>,
 ) {
-    let commands: [Box<dyn CommandProcessor<AppContext>>; 16] = [
+    let commands: [Box<dyn CommandProcessor<AppContext>>; 35] = [
         Box::from(CmdSetKey::new()),
         Box::from(CmdUnsetKey::new()),
+        Box::from(CmdSetKeyfile::new()),
+        Box::from(CmdUnsetKeyfile::new()),
         Box::from(CmdSetPreview::new()),
         Box::from(CmdUnsetPreview::new()),
         Box::from(CmdSetKeepOriginal::new()),
         Box::from(CmdUnsetKeepOriginal::new()),
+        Box::from(CmdSetCipher::new()),
+        Box::from(CmdAddRecipient::new()),
+        Box::from(CmdAddIdentity::new()),
+        Box::from(CmdUnsetRecipients::new()),
+        Box::from(CmdKeygen::new()),
         Box::from(CmdGetAllParameters::new()),
         Box::from(CmdEncrypt::new()),
         Box::from(CmdDecrypt::new()),
+        Box::from(CmdEncryptDir::new()),
+        Box::from(CmdDecryptDir::new()),
+        Box::from(CmdPack::new()),
+        Box::from(CmdUnpack::new()),
+        Box::from(CmdList::new()),
+        Box::from(CmdExtract::new()),
+        Box::from(CmdRecover::new()),
+        Box::from(CmdVerify::new()),
+        Box::from(CmdInspect::new()),
+        Box::from(CmdAddKey::new()),
+        Box::from(CmdRemoveKey::new()),
+        Box::from(CmdRekey::new()),
         Box::from(CmdHistory::new()),
         Box::from(CmdLs::new()),
         Box::from(CmdCd::new()),
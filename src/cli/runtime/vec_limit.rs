@@ -53,7 +53,7 @@ impl<T> VecLimited<T> {
         self.vec.is_empty()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
         self.vec.iter()
     }
 }
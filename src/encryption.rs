@@ -1,10 +1,205 @@
+use crate::cipher::CipherKind;
 use crate::error;
-use crate::meta::error::MetaError;
+use crate::file::{os_string_from_bytes, RestorePolicy};
+use crate::kdf::KdfParams;
 use crate::meta::header::MetaHeader;
 use crate::meta::raw::RawMeta;
+use crate::meta::slots::{KeySlot, KeySlots};
+use crate::parity::ParityParams;
+use core::fmt;
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Parameters of the newly encrypted files
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct EncryptionOptions {
+    /// Cipher, used for the content encryption
+    pub cipher_kind: CipherKind,
+
+    /// Key derivation function costs. The salt is generated per file
+    pub kdf: KdfParams,
+
+    /// Plain text chunk size in bytes, see `RawMeta::is_chunk_size_valid`
+    pub chunk_size: u32,
+
+    /// Reed-Solomon parity of the encrypted chunks, none by default
+    pub parity: Option<ParityParams>,
+
+    /// Store the extended attributes and the ACLs of the file
+    pub xattrs: bool,
+}
+
+impl Default for EncryptionOptions {
+    fn default() -> Self {
+        Self {
+            cipher_kind: CipherKind::default(),
+            kdf: KdfParams::default(),
+            chunk_size: RawMeta::DEFAULT_CHUNK_SIZE,
+            parity: None,
+            xattrs: false,
+        }
+    }
+}
+
+/// Parameters of the decryption
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DecryptionOptions {
+    /// The content is printed, the file is not written
+    pub preview: bool,
+
+    /// Restore the original permissions, timestamps and the `user.*`
+    /// extended attributes, see [`crate::meta::enc::FileAttributes`]
+    pub restore_attributes: bool,
+
+    /// Restore the original owner too, off by default
+    pub restore_owner: bool,
+
+    /// Restore the stored POSIX ACLs too, off by default
+    pub restore_acls: bool,
+
+    /// What to do with the unsafe original filename
+    pub filename_policy: FilenamePolicy,
+}
+
+impl Default for DecryptionOptions {
+    fn default() -> Self {
+        Self {
+            preview: false,
+            restore_attributes: true,
+            restore_owner: false,
+            restore_acls: false,
+            filename_policy: FilenamePolicy::default(),
+        }
+    }
+}
+
+impl DecryptionOptions {
+    /// The attributes to restore, none if they are not restored at all
+    pub fn restore_policy(&self) -> Option<RestorePolicy> {
+        self.restore_attributes
+            .then_some(RestorePolicy {
+                owner: self.restore_owner,
+                acls: self.restore_acls,
+            })
+    }
+}
+
+/// What to do, if the original filename, stored in the encrypted file,
+/// is not a plain name, see [`is_filename_safe`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum FilenamePolicy {
+    /// Fail the decryption
+    #[default]
+    Reject,
+
+    /// Keep the last component of the path, if it is safe.
+    /// Otherwise rename the file
+    Basename,
+
+    /// Name the file after the encrypted one, with the `decrypted` extension
+    Rename,
+}
+
+impl FilenamePolicy {
+    /// Name, used by the CLI
+    pub fn to_name(self) -> &'static str {
+        use FilenamePolicy::*;
+        match self {
+            Reject => "reject",
+            Basename => "basename",
+            Rename => "rename",
+        }
+    }
+}
+
+impl FromStr for FilenamePolicy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use FilenamePolicy::*;
+        [Reject, Basename, Rename]
+            .into_iter()
+            .find(|policy| policy.to_name() == s.to_lowercase())
+            .ok_or_else(|| {
+                error::Error::new(
+                    error::ErrorKind::InvalidArgument,
+                    format!(
+                        "Unknown filename policy '{}', expected: {}, {} or {}",
+                        s,
+                        Reject.to_name(),
+                        Basename.to_name(),
+                        Rename.to_name(),
+                    ),
+                )
+            })
+    }
+}
+
+/// Whether the original filename is a plain name, which does not leave
+/// the directory of the encrypted file. The separators of all platforms
+/// are rejected, so the file is safe to decrypt anywhere
+pub fn is_filename_safe(filename: &[u8]) -> bool {
+    safe_file_name(filename).is_some()
+}
+
+/// The file name of the original filename bytes, if it is safe,
+/// see [`is_filename_safe`]
+pub fn safe_file_name(filename: &[u8]) -> Option<OsString> {
+    if filename
+        .iter()
+        .any(|c| matches!(c, b'/' | b'\\' | b'\0'))
+    {
+        return None;
+    }
+    let name = os_string_from_bytes(filename)?;
+    let mut components = Path::new(&name).components();
+    (matches!(
+        components.next(),
+        Some(Component::Normal(_))
+    ) && components.next().is_none())
+    .then_some(name)
+}
+
+/// Path of the encrypted file: the `.enc` extension is appended,
+/// so the files, which differ by the extension only, do not collide
+pub fn encrypted_file_path(file_path: &Path) -> PathBuf {
+    let mut result = file_path.as_os_str().to_os_string();
+    result.push(".enc");
+    result.into()
+}
+
+/// Path of the decrypted file next to the encrypted one.
+/// The original filename comes from the file, so it is checked by the policy
+pub fn decrypted_file_path(
+    file_path: &Path,
+    filename: &[u8],
+    policy: FilenamePolicy,
+) -> error::Result<PathBuf> {
+    if let Some(name) = safe_file_name(filename) {
+        return Ok(file_path.with_file_name(name));
+    }
+    let lossy = String::from_utf8_lossy(filename);
+    log::warn!(target: "encryption decrypted_file_path", "Unsafe original file name {lossy:?}, policy: {}", policy.to_name());
+
+    let renamed = || file_path.with_extension("decrypted");
+    match policy {
+        FilenamePolicy::Reject => Err(error::Error::new(
+            error::ErrorKind::UnsafeFilename,
+            format!("Unsafe original file name {lossy:?}"),
+        )),
+        FilenamePolicy::Basename => Ok(filename
+            .rsplit(|c| matches!(c, b'/' | b'\\'))
+            .next()
+            .and_then(safe_file_name)
+            .map_or_else(renamed, |name| {
+                file_path.with_file_name(name)
+            })),
+        FilenamePolicy::Rename => Ok(renamed()),
+    }
+}
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum DetectedFileType {
@@ -46,43 +241,197 @@ pub fn try_detect_file_type(
 }
 
 pub fn get_raw_meta(file: &mut dyn Read) -> error::Result<RawMeta> {
+    get_raw_meta_with_bytes(file).map(|(meta, _)| meta)
+}
+
+/// Also returns the read MetaHeader and RawMeta bytes as is.
+/// They are authenticated as the associated data since RawMeta version 4
+pub fn get_raw_meta_with_bytes(
+    file: &mut dyn Read,
+) -> error::Result<(RawMeta, Vec<u8>)> {
     let mut hdr_buff = vec![0u8; MetaHeader::size()];
     file.read_exact(&mut hdr_buff)?;
     log::debug!(target: "encryption get_raw_meta", "Read meta header into Vec<u8>");
     log::trace!(target: "encryption get_raw_meta", "Meta header buffer: {hdr_buff:?}");
 
-    let header: MetaHeader = hdr_buff.try_into()?;
+    let header: MetaHeader = (&hdr_buff).try_into()?;
     log::trace!(target: "encryption get_raw_meta", "MetaHeader: {header:?}");
 
+    if !RawMeta::is_version_supported(header.version)
+        || header.size as usize != RawMeta::size(header.version)
+    {
+        return Err(error::Error::new(
+            error::ErrorKind::RawMetaDecodeError,
+            format!(
+                "Unsupported raw meta version {} of size {}",
+                header.version, header.size
+            ),
+        ));
+    }
+
     let mut raw_buff = vec![0u8; header.size as usize];
     file.read_exact(&mut raw_buff)?;
     log::debug!(target: "encryption get_raw_meta", "Read raw meta into Vec<u8>");
     log::trace!(target: "encryption get_raw_meta", "Raw meta buffer: {raw_buff:?}");
 
-    raw_buff
-        .try_into()
-        .map_err(|e: MetaError| e.into())
+    let meta = RawMeta::try_from_bytes(header.version, &raw_buff)?;
+    Ok((meta, [hdr_buff, raw_buff].concat()))
 }
 
-pub fn add_raw_meta(
-    meta: &RawMeta,
-    target_file: &mut dyn Write,
-) -> error::Result<()> {
+/// MetaHeader and RawMeta bytes, see [`get_raw_meta_with_bytes`]
+pub fn raw_meta_to_bytes(meta: &RawMeta) -> Vec<u8> {
     let raw_meta_hdr = MetaHeader {
         size: meta.len() as u64,
-        version: RawMeta::version(),
+        version: meta.version,
         magic: MetaHeader::MAGIC,
     };
     let raw_meta_hdr_vec = raw_meta_hdr.to_bytes();
     let raw_meta_vec = meta.to_bytes();
-    log::trace!(target: "encryption add_raw_meta", "Meta header buffer: {raw_meta_hdr_vec:?}");
-    log::trace!(target: "encryption add_raw_meta", "Raw meta buffer: {raw_meta_vec:?}");
+    log::trace!(target: "encryption raw_meta_to_bytes", "Meta header buffer: {raw_meta_hdr_vec:?}");
+    log::trace!(target: "encryption raw_meta_to_bytes", "Raw meta buffer: {raw_meta_vec:?}");
 
-    target_file.write_all(&raw_meta_hdr_vec)?;
-    log::debug!(target: "encryption add_raw_meta", "Raw meta header written");
+    [raw_meta_hdr_vec.to_vec(), raw_meta_vec].concat()
+}
 
-    target_file.write_all(&raw_meta_vec)?;
+/// Returns the written bytes, see [`raw_meta_to_bytes`]
+pub fn add_raw_meta(
+    meta: &RawMeta,
+    target_file: &mut dyn Write,
+) -> error::Result<Vec<u8>> {
+    let bytes = raw_meta_to_bytes(meta);
+
+    target_file.write_all(&bytes)?;
     log::debug!(target: "encryption add_raw_meta", "Raw meta written");
 
+    Ok(bytes)
+}
+
+/// Key slots follow the RawMeta since its version 6
+pub fn get_key_slots(file: &mut dyn Read) -> error::Result<KeySlots> {
+    let mut hdr_buff = vec![0u8; MetaHeader::size()];
+    file.read_exact(&mut hdr_buff)?;
+    log::trace!(target: "encryption get_key_slots", "Meta header buffer: {hdr_buff:?}");
+
+    let header: MetaHeader = (&hdr_buff).try_into()?;
+    if !header.is_magic_valid() || header.size as usize > KeySlots::MAX_SIZE {
+        return Err(error::Error::new(
+            error::ErrorKind::RawMetaDecodeError,
+            format!(
+                "Invalid key slots header of size {}",
+                header.size
+            ),
+        ));
+    }
+
+    let mut slots_buff = vec![0u8; header.size as usize];
+    file.read_exact(&mut slots_buff)?;
+    log::debug!(target: "encryption get_key_slots", "Read key slots into Vec<u8>");
+
+    Ok(KeySlots::try_from_bytes(
+        header.version,
+        &slots_buff,
+    )?)
+}
+
+pub fn add_key_slots(
+    slots: &KeySlots,
+    target_file: &mut dyn Write,
+) -> error::Result<()> {
+    let slots_hdr = MetaHeader {
+        size: slots.len() as u64,
+        version: KeySlots::version(),
+        magic: MetaHeader::MAGIC,
+    };
+
+    target_file.write_all(&slots_hdr.to_bytes())?;
+    target_file.write_all(&slots.to_bytes()?)?;
+    log::debug!(target: "encryption add_key_slots", "Key slots written");
+
     Ok(())
 }
+
+/// The plain text header of the encrypted file, see [`try_inspect`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileInspection {
+    pub raw_meta: RawMeta,
+
+    /// Since RawMeta version 6
+    pub key_slots: Option<KeySlots>,
+
+    pub file_len: u64,
+}
+
+/// Reads the header of the encrypted file without the key
+pub fn try_inspect(file_path: &Path) -> error::Result<FileInspection> {
+    let mut file = File::open(file_path)?;
+    let raw_meta = get_raw_meta(&mut file)?;
+    let key_slots = if raw_meta.kdf.is_none() {
+        Some(get_key_slots(&mut file)?)
+    } else {
+        None
+    };
+
+    Ok(FileInspection {
+        raw_meta,
+        key_slots,
+        file_len: file.metadata()?.len(),
+    })
+}
+
+impl fmt::Display for FileInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw_meta = &self.raw_meta;
+        writeln!(
+            f,
+            "Format version: {}",
+            raw_meta.version
+        )?;
+        writeln!(
+            f,
+            "Cipher: {}",
+            raw_meta.cipher_kind.to_name()
+        )?;
+        writeln!(f, "Chunk size: {}", raw_meta.chunk_size)?;
+        writeln!(f, "File size: {}", self.file_len)?;
+        writeln!(
+            f,
+            "Key check value: {}",
+            if raw_meta.key_check.is_some() {
+                "yes"
+            } else {
+                "no"
+            }
+        )?;
+        match raw_meta.parity {
+            Some(parity) => writeln!(
+                f,
+                "Parity: {} shards per {} chunks",
+                parity.parity_shards, parity.data_shards
+            )?,
+            None => writeln!(f, "Parity: no")?,
+        }
+        if let Some(meta_len) = raw_meta.meta_len {
+            writeln!(f, "Meta size: {meta_len}")?;
+        }
+        match (&raw_meta.kdf, &self.key_slots) {
+            (Some(kdf), _) => writeln!(f, "Key derivation: {kdf:?}")?,
+            (None, Some(key_slots)) => {
+                writeln!(
+                    f,
+                    "Key slots: {}",
+                    key_slots.slots.len()
+                )?;
+                for (index, slot) in key_slots.slots.iter().enumerate() {
+                    let kind = match slot {
+                        KeySlot::Password { factors, .. } => factors.to_str(),
+                        KeySlot::X25519 { .. } => "x25519",
+                        KeySlot::Stanza { stanza_type, .. } => stanza_type,
+                    };
+                    writeln!(f, "  #{index}: {kind}")?;
+                }
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
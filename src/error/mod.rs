@@ -2,10 +2,12 @@ mod macros;
 #[cfg(test)]
 mod tests;
 
+use crate::meta::enc::ContentDigest;
 use crate::meta::error::MetaError;
 use chacha20poly1305::aead;
 use core::fmt;
 use std::array::TryFromSliceError;
+use std::path::{Path, PathBuf};
 use std::str;
 use std::{error, io, result};
 
@@ -25,6 +27,7 @@ enum Repr {
     Simple(ErrorKind),
     SimpleMessage(ErrorKind, &'static &'static str),
     Custom(Box<Custom>),
+    Decrypt(Box<DecryptError>),
 }
 
 type GenericError = dyn error::Error + Send + Sync;
@@ -36,6 +39,29 @@ struct Custom {
     error: Box<GenericError>,
 }
 
+/// The error, which is located in the encrypted file
+#[derive(Debug)]
+struct DecryptError {
+    error: Error,
+    context: DecryptContext,
+}
+
+/// Where the decryption has failed. Unknown fields are `None`
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DecryptContext {
+    pub file_path: Option<PathBuf>,
+
+    /// STREAM chunk index, starting from 0
+    pub chunk_index: Option<u32>,
+
+    /// Offset of the chunk cipher text in the file
+    pub byte_offset: Option<u64>,
+
+    /// The header (and the key) is authenticated, so the failure
+    /// is not caused by the wrong key or the modified header
+    pub header_authenticated: Option<bool>,
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum ErrorKind {
     FileNotFound,
@@ -56,6 +82,12 @@ pub enum ErrorKind {
     IOError,
     InvalidArgument,
     FormatError,
+    KeyDerivationError,
+    StreamTruncated,
+    ChunkCorrupted,
+    WrongPasswordOrCorrupted,
+    DigestMismatch,
+    UnsafeFilename,
     OtherError,
 }
 
@@ -81,6 +113,12 @@ impl ErrorKind {
             IOError => "IO Error",
             FormatError => "Format Error",
             InvalidArgument => "Invalid Argument Error",
+            KeyDerivationError => "Key derivation error",
+            StreamTruncated => "Encrypted stream is truncated or extended",
+            ChunkCorrupted => "Encrypted chunk is corrupted",
+            WrongPasswordOrCorrupted => "Wrong password or corrupted file",
+            DigestMismatch => "Decrypted content does not match its digest",
+            UnsafeFilename => "Unsafe original file name",
             OtherError => "Unknown error",
         }
     }
@@ -138,6 +176,17 @@ impl From<aead::Error> for Error {
     }
 }
 
+impl From<argon2::Error> for Error {
+    fn from(err: argon2::Error) -> Self {
+        Error {
+            repr: Repr::Custom(Box::from(Custom {
+                kind: ErrorKind::KeyDerivationError,
+                error: Box::from(err),
+            })),
+        }
+    }
+}
+
 impl From<str::Utf8Error> for Error {
     fn from(err: str::Utf8Error) -> Self {
         Error {
@@ -189,6 +238,7 @@ impl Error {
             Repr::Simple(..) => None,
             Repr::SimpleMessage(..) => None,
             Repr::Custom(c) => Some(&*c.error),
+            Repr::Decrypt(d) => d.error.get_ref(),
         }
     }
 
@@ -199,6 +249,7 @@ impl Error {
             Repr::Simple(..) => None,
             Repr::SimpleMessage(..) => None,
             Repr::Custom(c) => Some(c.error),
+            Repr::Decrypt(d) => d.error.into_inner(),
         }
     }
 
@@ -209,6 +260,7 @@ impl Error {
             Repr::Simple(..) => None,
             Repr::SimpleMessage(..) => None,
             Repr::Custom(ref mut c) => Some(&mut *c.error),
+            Repr::Decrypt(ref mut d) => d.error.get_mut(),
         }
     }
 
@@ -219,10 +271,60 @@ impl Error {
             Repr::Simple(kind) => kind,
             Repr::SimpleMessage(kind, _) => kind,
             Repr::Custom(ref c) => c.kind,
+            Repr::Decrypt(ref d) => d.error.kind(),
+        }
+    }
+
+    /// Location of the decryption error, see [`Error::with_decrypt_context`]
+    pub fn decrypt_context(&self) -> Option<&DecryptContext> {
+        match &self.repr {
+            Repr::Decrypt(d) => Some(&d.context),
+            _ => None,
+        }
+    }
+
+    /// Attaches the location in the encrypted file to the error,
+    /// keeping the error kind and message
+    #[must_use]
+    pub fn with_decrypt_context<F>(self, update: F) -> Self
+    where
+        F: FnOnce(&mut DecryptContext),
+    {
+        let mut decrypt_error = match self.repr {
+            Repr::Decrypt(d) => d,
+            repr => Box::from(DecryptError {
+                error: Error { repr },
+                context: DecryptContext::default(),
+            }),
+        };
+        update(&mut decrypt_error.context);
+
+        Error {
+            repr: Repr::Decrypt(decrypt_error),
         }
     }
 
-    ///
+    #[must_use]
+    pub fn with_file_path(self, path: &Path) -> Self {
+        self.with_decrypt_context(|context| {
+            context.file_path = Some(path.to_path_buf())
+        })
+    }
+
+    #[must_use]
+    pub fn with_chunk(self, chunk_index: u32, byte_offset: u64) -> Self {
+        self.with_decrypt_context(|context| {
+            context.chunk_index = Some(chunk_index);
+            context.byte_offset = Some(byte_offset);
+        })
+    }
+
+    #[must_use]
+    pub fn with_header_authenticated(self, header_authenticated: bool) -> Self {
+        self.with_decrypt_context(|context| {
+            context.header_authenticated = Some(header_authenticated)
+        })
+    }
 
     pub fn new_file_not_found(filename: &str) -> Self {
         Self::new(
@@ -238,6 +340,59 @@ impl Error {
         )
     }
 
+    pub fn new_stream_truncated(chunk_index: u32, is_last: bool) -> Self {
+        Self::new(
+            ErrorKind::StreamTruncated,
+            if is_last {
+                format!(
+                    "Encrypted stream is truncated after the chunk #{}",
+                    chunk_index
+                )
+            } else {
+                format!(
+                    "Encrypted stream has trailing data after the final chunk #{}",
+                    chunk_index
+                )
+            },
+        )
+    }
+
+    /// The chunk does not authenticate by the right key.
+    /// If the key is not verified yet, the key may be wrong too
+    pub fn new_chunk_corrupted(chunk_index: u32, key_verified: bool) -> Self {
+        if key_verified {
+            Self::new(
+                ErrorKind::ChunkCorrupted,
+                format!(
+                    "Encrypted chunk #{} is corrupted",
+                    chunk_index
+                ),
+            )
+        } else {
+            Self::new(
+                ErrorKind::WrongPasswordOrCorrupted,
+                format!(
+                    "Encrypted chunk #{} does not authenticate, the key is wrong or the file is corrupted",
+                    chunk_index
+                ),
+            )
+        }
+    }
+
+    /// The decrypted content differs from the digest, stored in the EncryptedMeta
+    pub fn new_digest_mismatch(
+        expected: &ContentDigest,
+        real: &ContentDigest,
+    ) -> Self {
+        Self::new(
+            ErrorKind::DigestMismatch,
+            format!(
+                "Decrypted content does not match its digest, expected: {}, real: {}",
+                expected, real
+            ),
+        )
+    }
+
     pub fn new_encrypted_meta_size_mismatch(
         expected_size: u16,
         real_size: u16,
@@ -271,6 +426,7 @@ impl fmt::Debug for Repr {
                 .field("message", &message)
                 .finish(),
             Repr::Custom(c) => fmt::Debug::fmt(&c, fmt),
+            Repr::Decrypt(d) => fmt::Debug::fmt(&d, fmt),
         }
     }
 }
@@ -281,7 +437,40 @@ impl fmt::Display for Error {
             Repr::Simple(kind) => write!(fmt, "{}", kind.to_str()),
             Repr::SimpleMessage(_, &msg) => msg.fmt(fmt),
             Repr::Custom(ref c) => c.error.fmt(fmt),
+            Repr::Decrypt(ref d) => {
+                write!(fmt, "{}", d.error)?;
+                if d.context != DecryptContext::default() {
+                    write!(fmt, " ({})", d.context)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecryptContext {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::with_capacity(3);
+        if let Some(path) = &self.file_path {
+            parts.push(format!("file '{}'", path.display()));
         }
+        match (self.chunk_index, self.byte_offset) {
+            (Some(index), Some(offset)) => parts.push(format!(
+                "chunk #{index} at byte {offset}"
+            )),
+            (Some(index), None) => parts.push(format!("chunk #{index}")),
+            (None, Some(offset)) => parts.push(format!("byte {offset}")),
+            (None, None) => {}
+        }
+        match self.header_authenticated {
+            Some(true) => parts.push("the header is authentic".to_string()),
+            Some(false) => {
+                parts.push("the header is not authenticated".to_string())
+            }
+            None => {}
+        }
+
+        write!(fmt, "{}", parts.join(", "))
     }
 }
 
@@ -291,6 +480,7 @@ impl error::Error for Error {
             Repr::Simple(..) => None,
             Repr::SimpleMessage(..) => None,
             Repr::Custom(ref c) => c.error.source(),
+            Repr::Decrypt(ref d) => d.error.source(),
         }
     }
 }
@@ -1,6 +1,7 @@
-use super::{Error, ErrorKind, Result};
+use super::{DecryptContext, Error, ErrorKind, Result};
 use crate::error::{Custom, Repr};
 use std::mem::size_of;
+use std::path::{Path, PathBuf};
 use std::{error, fmt};
 
 #[test]
@@ -22,15 +23,14 @@ fn test_debug_error() {
         })),
     };
 
-    let expected = format!(
-        "Custom {{ \
+    let expected = "Custom { \
         kind: FileNotFound, \
-        error: Error {{ \
+        error: Error { \
             kind: IOError, \
             message: \"File thefile.txt not found\" \
-        }} \
-        }}"
-    );
+        } \
+        }"
+    .to_string();
 
     assert_eq!(format!("{:?}", err), expected);
 }
@@ -81,9 +81,55 @@ fn test_result() {
         ))
     }
 
-    assert_eq!(inner_fun().is_err(), true);
+    assert!(inner_fun().is_err());
     assert_eq!(
         inner_fun().expect_err("").kind(),
         ErrorKind::WrongPassword
     );
 }
+
+#[test]
+fn test_decrypt_context() {
+    let err = Error::new_chunk_corrupted(3, true);
+    assert!(err.decrypt_context().is_none());
+
+    let err = err
+        .with_chunk(3, 196_700)
+        .with_header_authenticated(true)
+        .with_file_path(Path::new("/tmp/file.enc"));
+    assert_eq!(err.kind(), ErrorKind::ChunkCorrupted);
+    assert_eq!(
+        err.decrypt_context(),
+        Some(&DecryptContext {
+            file_path: Some(PathBuf::from("/tmp/file.enc")),
+            chunk_index: Some(3),
+            byte_offset: Some(196_700),
+            header_authenticated: Some(true),
+        })
+    );
+    assert_eq!(
+        err.to_string(),
+        "Encrypted chunk #3 is corrupted \
+        (file '/tmp/file.enc', chunk #3 at byte 196700, the header is authentic)"
+    );
+    assert_eq!(
+        "Encrypted chunk #3 is corrupted",
+        err.into_inner().unwrap().to_string()
+    );
+}
+
+#[test]
+fn test_decrypt_context_simple() {
+    let err =
+        Error::from(ErrorKind::WrongPassword).with_header_authenticated(false);
+    assert_eq!(err.kind(), ErrorKind::WrongPassword);
+    assert_eq!(
+        err.to_string(),
+        "Wrong password (the header is not authenticated)"
+    );
+
+    assert_eq!(
+        Error::new_chunk_corrupted(0, false).kind(),
+        ErrorKind::WrongPasswordOrCorrupted
+    );
+}
@@ -1,7 +1,11 @@
-use std::fs::{File, OpenOptions};
+use crate::meta::enc::{
+    ContentDigest, ContentHasher, ExtendedAttribute, FileAttributes,
+};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, FileTimes, OpenOptions};
 use std::io;
 use std::io::{ErrorKind, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[allow(dead_code)]
 pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
@@ -14,6 +18,68 @@ pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
     Ok(buffer)
 }
 
+/// Reads the file, which digest is computed in advance, hashing it again.
+/// The content, which differs from the digest or outgrows it,
+/// fails the reading, so the stored digest always matches what is read
+pub struct CheckedReader {
+    path: PathBuf,
+    file: File,
+    content: ContentDigest,
+    read: u64,
+    hasher: Option<ContentHasher>,
+}
+
+impl CheckedReader {
+    pub fn new(path: &Path, file: File, content: ContentDigest) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            file,
+            content,
+            read: 0,
+            hasher: Some(ContentHasher::default()),
+        }
+    }
+
+    fn changed(&self) -> io::Error {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "File {:?} is changed while reading",
+                self.path
+            ),
+        )
+    }
+}
+
+impl Read for CheckedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(hasher) = &mut self.hasher else {
+            return Ok(0);
+        };
+        let count = self.file.read(buf)?;
+        if count > 0 {
+            self.read += count as u64;
+            if self.read > self.content.size {
+                return Err(self.changed());
+            }
+            hasher.write_all(&buf[..count])?;
+            return Ok(count);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let hasher = self
+            .hasher
+            .take()
+            .expect("The file is not read to the end");
+        if hasher.finish() != self.content {
+            return Err(self.changed());
+        }
+        Ok(0)
+    }
+}
+
 pub trait OpenOrCreate {
     fn open_or_create(path: &Path) -> io::Result<File>;
     fn open_read_only(path: &Path) -> io::Result<File>;
@@ -23,18 +89,16 @@ pub trait OpenOrCreate {
 
 impl OpenOrCreate for File {
     fn open_or_create(path: &Path) -> io::Result<File> {
-        if path.exists() {
-            // TODO: needed to loop for encryption in dir
-            if !path.is_file() {
-                return Err(io::Error::new(
-                    ErrorKind::InvalidData,
-                    "",
-                ));
-            }
+        // The directories are walked by `bulk::walk_files`
+        if path.exists() && !path.is_file() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "",
+            ));
         }
 
         if path.is_file() {
-            println!("Opening the file {0:?}", path);
+            log::debug!(target: "file open_or_create", "Opening the file {path:?}");
 
             return OpenOptions::new()
                 .create_new(false)
@@ -43,35 +107,52 @@ impl OpenOrCreate for File {
                 .open(path);
         }
 
-        return OpenOptions::new()
+        OpenOptions::new()
             .create_new(true)
             .write(true)
             .append(false)
-            .open(path);
+            .open(path)
     }
 
     fn open_read_only(path: &Path) -> io::Result<File> {
-        return OpenOptions::new()
+        OpenOptions::new()
             .create_new(false)
             .write(false)
             .read(true)
             .append(false)
-            .open(path);
+            .open(path)
     }
 
     fn open_write(path: &Path) -> io::Result<File> {
-        return OpenOptions::new()
+        OpenOptions::new()
             .write(true)
             .append(false)
-            .open(path);
+            .open(path)
     }
 
     fn open_append(path: &Path) -> io::Result<File> {
-        return OpenOptions::new()
-            .write(true)
+        OpenOptions::new()
             .append(true)
-            .open(path);
+            .open(path)
+    }
+}
+
+/// Reads until the buffer is full or the EOF is reached.
+/// Returns the number of bytes read
+pub fn read_full(
+    source: &mut dyn Read,
+    buffer: &mut [u8],
+) -> io::Result<usize> {
+    let mut read_count = 0usize;
+    while read_count < buffer.len() {
+        match source.read(&mut buffer[read_count..]) {
+            Ok(0) => break,
+            Ok(count) => read_count += count,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
+    Ok(read_count)
 }
 
 #[allow(dead_code)]
@@ -102,3 +183,172 @@ impl GetFileDirectory for Path {
         Ok(file_dir)
     }
 }
+
+/// Raw bytes of the file name. Any name is representable on Unix,
+/// only the valid Unicode on the other platforms
+#[cfg(unix)]
+pub fn os_str_to_bytes(name: &OsStr) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(name.as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+pub fn os_str_to_bytes(name: &OsStr) -> Option<Vec<u8>> {
+    name.to_str()
+        .map(|name| name.as_bytes().to_vec())
+}
+
+/// The file name of the raw bytes, see [`os_str_to_bytes`]
+#[cfg(unix)]
+pub fn os_string_from_bytes(name: &[u8]) -> Option<OsString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(OsStr::from_bytes(name).to_os_string())
+}
+
+#[cfg(not(unix))]
+pub fn os_string_from_bytes(name: &[u8]) -> Option<OsString> {
+    std::str::from_utf8(name)
+        .ok()
+        .map(OsString::from)
+}
+
+/// Reads the extended attributes of the file, including the ACLs.
+/// There are none, if the file system does not support them
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path) -> io::Result<Vec<ExtendedAttribute>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        Err(e) if e.kind() == ErrorKind::Unsupported => {
+            log::warn!(target: "file read_xattrs", "Extended attributes are not supported: {e}");
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut result = Vec::new();
+    for name in names {
+        if name.len() > u8::MAX as usize || result.len() == u16::MAX as usize {
+            log::warn!(target: "file read_xattrs", "Extended attribute {name:?} is skipped");
+            continue;
+        }
+        // The attribute may be removed meanwhile
+        if let Some(value) = xattr::get(path, &name)? {
+            result.push(ExtendedAttribute {
+                name: name.as_bytes().to_vec(),
+                value,
+            });
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(not(unix))]
+pub fn read_xattrs(_path: &Path) -> io::Result<Vec<ExtendedAttribute>> {
+    log::warn!(target: "file read_xattrs", "Extended attributes are not supported");
+    Ok(Vec::new())
+}
+
+/// Which of the privileged attributes are restored. The attributes come
+/// from the encrypted file, which anyone may have made, so these are opt-in
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RestorePolicy {
+    /// Change the owner and the group of the file
+    pub owner: bool,
+
+    /// Restore the POSIX ACLs besides the `user.*` extended attributes
+    pub acls: bool,
+}
+
+const ACL_XATTRS: [&[u8]; 2] =
+    [b"system.posix_acl_access", b"system.posix_acl_default"];
+
+impl RestorePolicy {
+    /// Only the `user.*` namespace and the explicitly requested ACLs,
+    /// never `security.*`, `trusted.*` and the rest of `system.*`
+    pub fn is_xattr_restored(&self, name: &[u8]) -> bool {
+        name.starts_with(b"user.") || (self.acls && ACL_XATTRS.contains(&name))
+    }
+}
+
+/// Restores the known attributes of the file, as far as permitted.
+/// The mode loses the setuid, setgid and sticky bits.
+/// Returns the warnings about the attributes, which are not restored
+pub fn restore_attributes(
+    path: &Path,
+    attributes: &FileAttributes,
+    xattrs: &[ExtendedAttribute],
+    policy: RestorePolicy,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    #[cfg(unix)]
+    if let Some((uid, gid)) = attributes
+        .owner
+        .filter(|_| policy.owner)
+    {
+        if let Err(e) = std::os::unix::fs::chown(path, Some(uid), Some(gid)) {
+            warnings.push(format!(
+                "Owner {uid}:{gid} of {path:?} is not restored: {e}"
+            ));
+        }
+    }
+
+    // Before the mode, which may forbid writing them
+    #[cfg(unix)]
+    for xattr in xattrs {
+        use std::os::unix::ffi::OsStrExt;
+        let name = std::ffi::OsStr::from_bytes(&xattr.name);
+        if !policy.is_xattr_restored(&xattr.name) {
+            warnings.push(format!(
+                "Extended attribute {name:?} of {path:?} is not restored: not permitted"
+            ));
+            continue;
+        }
+        if let Err(e) = xattr::set(path, name, &xattr.value) {
+            warnings.push(format!(
+                "Extended attribute {name:?} of {path:?} is not restored: {e}"
+            ));
+        }
+    }
+    #[cfg(not(unix))]
+    if !xattrs.is_empty() {
+        warnings.push(format!(
+            "Extended attributes of {path:?} are not restored: not supported"
+        ));
+    }
+
+    let mut times = FileTimes::new();
+    if let Some(modified) = attributes.modified {
+        times = times.set_modified(modified);
+    }
+    if let Some(accessed) = attributes.accessed {
+        times = times.set_accessed(accessed);
+    }
+    if attributes.modified.is_some() || attributes.accessed.is_some() {
+        if let Err(e) = File::open(path).and_then(|file| file.set_times(times))
+        {
+            warnings.push(format!(
+                "Timestamps of {path:?} are not restored: {e}"
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = attributes.mode.map(|mode| mode & 0o777) {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = std::fs::set_permissions(
+            path,
+            std::fs::Permissions::from_mode(mode),
+        ) {
+            warnings.push(format!(
+                "Mode {mode:o} of {path:?} is not restored: {e}"
+            ));
+        }
+    }
+
+    warnings
+}
@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error;
+use crate::meta::error::ErrorKind as MetaErrorKind;
+use crate::meta::error::MetaError;
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{thread_rng, RngCore};
+
+pub const KDF_SALT_SIZE: usize = 16usize;
+pub const KDF_KEY_SIZE: usize = 32usize;
+
+pub type DerivedKeyType = [u8; KDF_KEY_SIZE];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum KdfKind {
+    /// Single unsalted SHA-256. Used by version 1 files only:
+    /// the key slots need a unique key per slot, see [`KdfKind::is_salted`]
+    Sha256,
+    Argon2id,
+}
+
+impl KdfKind {
+    pub fn to_u8(self) -> u8 {
+        use KdfKind::*;
+        match self {
+            Sha256 => 0,
+            Argon2id => 1,
+        }
+    }
+
+    /// The derived key is unique per salt, so it may wrap a key
+    /// with the fixed nonce
+    pub const fn is_salted(self) -> bool {
+        match self {
+            KdfKind::Sha256 => false,
+            KdfKind::Argon2id => true,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        use KdfKind::*;
+        match self {
+            Sha256 => "KDF SHA-256",
+            Argon2id => "KDF Argon2id",
+        }
+    }
+}
+
+impl TryInto<KdfKind> for u8 {
+    type Error = MetaError;
+
+    fn try_into(self) -> Result<KdfKind, Self::Error> {
+        match self {
+            0 => Ok(KdfKind::Sha256),
+            1 => Ok(KdfKind::Argon2id),
+            _ => Err(MetaErrorKind::WrongRawKdfKind.into()),
+        }
+    }
+}
+
+/// Key derivation function parameters.
+/// Stored inside the RawMeta, so the decryptor uses the same costs
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct KdfParams {
+    pub kind: KdfKind,
+
+    /// Memory cost in KiB
+    pub m_cost: u32,
+
+    /// Time cost (number of passes)
+    pub t_cost: u32,
+
+    /// Degree of parallelism (number of lanes)
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    pub const DEFAULT_M_COST: u32 = 19 * 1024;
+    pub const DEFAULT_T_COST: u32 = 2;
+    pub const DEFAULT_P_COST: u32 = 1;
+
+    /// The costs are read from the header before the key is checked,
+    /// so they are bounded: 1 GiB, 16 passes and 16 lanes at most
+    pub const MAX_M_COST: u32 = 1024 * 1024;
+    pub const MAX_T_COST: u32 = 16;
+    pub const MAX_P_COST: u32 = 16;
+
+    /// Parameters of the files, created before Argon2id was introduced
+    pub const fn legacy() -> Self {
+        Self {
+            kind: KdfKind::Sha256,
+            m_cost: 0,
+            t_cost: 0,
+            p_cost: 0,
+        }
+    }
+
+    /// The costs of Argon2id do not exceed the maximums,
+    /// the minimums are checked by the derivation
+    pub const fn is_valid(&self) -> bool {
+        match self.kind {
+            KdfKind::Sha256 => true,
+            KdfKind::Argon2id => {
+                self.m_cost <= Self::MAX_M_COST
+                    && self.t_cost <= Self::MAX_T_COST
+                    && self.p_cost <= Self::MAX_P_COST
+            }
+        }
+    }
+
+    pub fn derive_key(
+        &self,
+        key: &[u8],
+        salt: &[u8],
+    ) -> error::Result<DerivedKeyType> {
+        log::debug!(target: "kdf KdfParams derive_key", "Deriving the key using {}", self.kind.to_str());
+
+        match self.kind {
+            KdfKind::Sha256 => crate::get_hash(key),
+            KdfKind::Argon2id => {
+                let params = Params::new(
+                    self.m_cost,
+                    self.t_cost,
+                    self.p_cost,
+                    Some(KDF_KEY_SIZE),
+                )?;
+                let argon2 = Argon2::new(
+                    Algorithm::Argon2id,
+                    Version::V0x13,
+                    params,
+                );
+
+                let mut result = [0u8; KDF_KEY_SIZE];
+                argon2.hash_password_into(key, salt, &mut result)?;
+                Ok(result)
+            }
+        }
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            kind: KdfKind::Argon2id,
+            m_cost: Self::DEFAULT_M_COST,
+            t_cost: Self::DEFAULT_T_COST,
+            p_cost: Self::DEFAULT_P_COST,
+        }
+    }
+}
+
+pub fn generate_salt() -> [u8; KDF_SALT_SIZE] {
+    let mut salt = [0u8; KDF_SALT_SIZE];
+    thread_rng().fill_bytes(&mut salt);
+    salt
+}
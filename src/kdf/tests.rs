@@ -0,0 +1,104 @@
+use crate::error;
+use crate::error::ErrorKind;
+use crate::kdf::{KdfKind, KdfParams, KDF_SALT_SIZE};
+use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use hex_literal::hex;
+
+const SALT: [u8; KDF_SALT_SIZE] = [7u8; KDF_SALT_SIZE];
+
+const PARAMS: KdfParams = KdfParams {
+    kind: KdfKind::Argon2id,
+    m_cost: 64,
+    t_cost: 1,
+    p_cost: 1,
+};
+
+#[test]
+fn test_kind_from_u8() -> error::Result<()> {
+    let kind: KdfKind = KdfKind::Argon2id.to_u8().try_into()?;
+    assert_eq!(kind, KdfKind::Argon2id);
+
+    let result: Result<KdfKind, MetaError> = 0xFFu8.try_into();
+    assert_eq!(
+        result.err().unwrap().repr,
+        MetaErrorKind::WrongRawKdfKind
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_legacy_matches_get_hash() -> error::Result<()> {
+    let key = KdfParams::legacy().derive_key(b"FM7348mwmw73t", &SALT)?;
+
+    assert_eq!(
+        key,
+        hex!(
+            "66afe59af310865bc544c9d7a19ded0b1f8e6a1e797c3a1215a33175cae4023c"
+        )
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_argon2id_salted() -> error::Result<()> {
+    let key = PARAMS.derive_key(b"amongus", &SALT)?;
+
+    assert_eq!(
+        key,
+        PARAMS.derive_key(b"amongus", &SALT)?
+    );
+    assert_ne!(
+        key,
+        PARAMS.derive_key(b"amongus", &[8u8; 16])?
+    );
+    assert_ne!(
+        key,
+        PARAMS.derive_key(b"amogus", &SALT)?
+    );
+    assert_ne!(
+        key,
+        KdfParams::legacy().derive_key(b"amongus", &SALT)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_argon2id_wrong_params() {
+    let params = KdfParams {
+        p_cost: 0,
+        ..PARAMS
+    };
+
+    let result = params.derive_key(b"amongus", &SALT);
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::KeyDerivationError
+    );
+}
+
+#[test]
+fn test_is_valid() {
+    assert!(PARAMS.is_valid());
+    assert!(KdfParams::default().is_valid());
+    assert!(KdfParams::legacy().is_valid());
+
+    for params in [
+        KdfParams {
+            m_cost: KdfParams::MAX_M_COST + 1,
+            ..PARAMS
+        },
+        KdfParams {
+            t_cost: KdfParams::MAX_T_COST + 1,
+            ..PARAMS
+        },
+        KdfParams {
+            p_cost: KdfParams::MAX_P_COST + 1,
+            ..PARAMS
+        },
+    ] {
+        assert!(!params.is_valid(), "{params:?}");
+    }
+}
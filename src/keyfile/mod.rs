@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error;
+use crate::meta::slots::KeyFactors;
+use core::fmt;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub const KEYFILE_DIGEST_SIZE: usize = 32;
+
+/// Keyfile, the second key factor. Any non-empty file is accepted,
+/// the key is derived from the SHA-256 of its content,
+/// so the keyfile itself is never copied into memory as a whole
+#[derive(Clone, Eq, PartialEq)]
+pub struct Keyfile {
+    digest: [u8; KEYFILE_DIGEST_SIZE],
+}
+
+impl Keyfile {
+    pub fn from_bytes(content: &[u8]) -> error::Result<Self> {
+        Self::from_reader(&mut &content[..])
+    }
+
+    pub fn read(path: &Path) -> error::Result<Self> {
+        Self::from_reader(&mut File::open(path)?)
+    }
+
+    fn from_reader(source: &mut dyn io::Read) -> error::Result<Self> {
+        let mut hasher = Sha256::new();
+        if io::copy(source, &mut hasher)? == 0 {
+            return Err(error::Error::new_const(
+                error::ErrorKind::InvalidArgument,
+                &"Keyfile is empty",
+            ));
+        }
+
+        Ok(Self {
+            digest: hasher.finalize().into(),
+        })
+    }
+
+    pub fn digest(&self) -> &[u8; KEYFILE_DIGEST_SIZE] {
+        &self.digest
+    }
+}
+
+impl fmt::Debug for Keyfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Keyfile")
+    }
+}
+
+/// The secret, the password slot key is derived from.
+///
+/// The password alone is used as is, so the slots,
+/// created before the keyfiles were introduced, are unlocked as before
+pub fn key_factors_secret(
+    password: Option<&str>,
+    keyfile: Option<&Keyfile>,
+) -> Option<(KeyFactors, Vec<u8>)> {
+    match (password, keyfile) {
+        (Some(password), None) => Some((
+            KeyFactors::Password,
+            password.as_bytes().to_vec(),
+        )),
+        (None, Some(keyfile)) => Some((
+            KeyFactors::Keyfile,
+            keyfile.digest.to_vec(),
+        )),
+        (Some(password), Some(keyfile)) => Some((
+            KeyFactors::PasswordKeyfile,
+            [
+                Sha256::digest(password.as_bytes()).as_slice(),
+                &keyfile.digest,
+            ]
+            .concat(),
+        )),
+        (None, None) => None,
+    }
+}
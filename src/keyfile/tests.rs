@@ -0,0 +1,59 @@
+use crate::error;
+use crate::error::ErrorKind;
+use crate::keyfile::{key_factors_secret, Keyfile};
+use crate::meta::slots::KeyFactors;
+use hex_literal::hex;
+
+#[test]
+fn test_keyfile() -> error::Result<()> {
+    let keyfile = Keyfile::from_bytes(b"amongus")?;
+    assert_eq!(
+        keyfile.digest(),
+        &hex!(
+            "fc9973ac763a10a7a5aad8b98dcbec13e788657a7a7a9066175caea889b432fd"
+        )
+    );
+
+    let result = Keyfile::from_bytes(&[]);
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_key_factors_secret() -> error::Result<()> {
+    let keyfile = Keyfile::from_bytes(b"keyfile")?;
+
+    assert_eq!(
+        key_factors_secret(Some("amongus"), None),
+        Some((
+            KeyFactors::Password,
+            b"amongus".to_vec()
+        ))
+    );
+    assert_eq!(
+        key_factors_secret(None, Some(&keyfile)),
+        Some((
+            KeyFactors::Keyfile,
+            keyfile.digest().to_vec()
+        ))
+    );
+
+    let (factors, secret) =
+        key_factors_secret(Some("amongus"), Some(&keyfile)).unwrap();
+    assert_eq!(factors, KeyFactors::PasswordKeyfile);
+    assert_eq!(secret.len(), 64);
+    assert_ne!(
+        key_factors_secret(Some("amogus"), Some(&keyfile))
+            .unwrap()
+            .1,
+        secret
+    );
+
+    assert_eq!(key_factors_secret(None, None), None);
+
+    Ok(())
+}
@@ -0,0 +1,496 @@
+#[cfg(test)]
+mod tests;
+
+use crate::cipher::kind::{unwrap_key, wrap_key};
+use crate::cipher::CipherKind;
+use crate::encryption::{get_key_slots, get_raw_meta_with_bytes};
+use crate::error;
+use crate::kdf::{generate_salt, DerivedKeyType, KdfParams, KDF_KEY_SIZE};
+use crate::keyfile::{key_factors_secret, Keyfile};
+use crate::meta::raw::{RawMeta, KEY_CHECK_SIZE};
+use crate::meta::slots::{KeyFactors, KeySlot, KeySlots};
+use crate::recipient::{
+    SshEd25519Identity, SshEd25519Recipient, X25519Identity, X25519Recipient,
+};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io::Read;
+use std::str::FromStr;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
+
+/// Random per-file key, which encrypts the content.
+/// Wrapped by the key slots since RawMeta version 6
+pub type DataKey = DerivedKeyType;
+
+/// Stanzas do not depend on the file cipher
+const STANZA_CIPHER_KIND: CipherKind = CipherKind::ChaCha20Poly1305;
+
+const KEY_CHECK_INFO: &[u8] = b"rust-file-encryptor key check";
+const X25519_KEK_INFO: &[u8] = b"rust-file-encryptor x25519";
+const SSH_ED25519_KEK_INFO: &[u8] = b"rust-file-encryptor ssh-ed25519";
+
+/// Who is able to decrypt the newly encrypted file.
+/// A key slot is created for every recipient
+#[derive(Clone)]
+pub enum Recipient {
+    Password(String),
+    Keyfile(Keyfile),
+    PasswordKeyfile(String, Keyfile),
+    X25519(X25519Recipient),
+    SshEd25519(SshEd25519Recipient),
+}
+
+/// What unlocks the key slots
+#[derive(Clone)]
+pub enum Identity {
+    Password(String),
+    Keyfile(Keyfile),
+    PasswordKeyfile(String, Keyfile),
+    X25519(X25519Identity),
+    SshEd25519(SshEd25519Identity),
+}
+
+impl Recipient {
+    /// The password slot secret, see [`key_factors_secret`]
+    pub fn key_factors(&self) -> Option<(KeyFactors, Vec<u8>)> {
+        match self {
+            Recipient::Password(password) => {
+                key_factors_secret(Some(password), None)
+            }
+            Recipient::Keyfile(keyfile) => {
+                key_factors_secret(None, Some(keyfile))
+            }
+            Recipient::PasswordKeyfile(password, keyfile) => {
+                key_factors_secret(Some(password), Some(keyfile))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Identity {
+    /// The password slot secret, see [`key_factors_secret`]
+    pub fn key_factors(&self) -> Option<(KeyFactors, Vec<u8>)> {
+        match self {
+            Identity::Password(password) => {
+                key_factors_secret(Some(password), None)
+            }
+            Identity::Keyfile(keyfile) => {
+                key_factors_secret(None, Some(keyfile))
+            }
+            Identity::PasswordKeyfile(password, keyfile) => {
+                key_factors_secret(Some(password), Some(keyfile))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses the public key recipient: X25519 or OpenSSH ed25519
+impl FromStr for Recipient {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with(X25519Recipient::PREFIX) {
+            Ok(Recipient::X25519(s.parse()?))
+        } else if s.starts_with(SshEd25519Recipient::KEY_TYPE) {
+            Ok(Recipient::SshEd25519(s.parse()?))
+        } else {
+            Err(error::Error::new(
+                error::ErrorKind::InvalidArgument,
+                format!(
+                    "Unsupported recipient, expected '{}...' or '{} ...'",
+                    X25519Recipient::PREFIX,
+                    SshEd25519Recipient::KEY_TYPE
+                ),
+            ))
+        }
+    }
+}
+
+pub fn generate_data_key() -> DataKey {
+    let mut key = [0u8; KDF_KEY_SIZE];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// Check value of the data key, stored in the RawMeta since version 7.
+/// One-way, so it does not reveal the key
+pub fn key_check_value(data_key: &DataKey) -> [u8; KEY_CHECK_SIZE] {
+    let mut result = [0u8; KEY_CHECK_SIZE];
+    Hkdf::<Sha256>::new(None, data_key)
+        .expand(KEY_CHECK_INFO, &mut result)
+        .expect("Wrong HKDF output size");
+    result
+}
+
+/// Wraps the data key by the key, derived from the password
+/// (or the other key factors secret) with a fresh salt.
+///
+/// `aad` is the MetaHeader and RawMeta bytes of the file,
+/// so the slot can not be moved to another file.
+/// The KDF must be salted, the wrapping nonce is fixed
+pub fn new_password_slot(
+    kind: CipherKind,
+    aad: &[u8],
+    factors: KeyFactors,
+    secret: &[u8],
+    kdf: &KdfParams,
+    data_key: &DataKey,
+) -> error::Result<KeySlot> {
+    if !kdf.kind.is_salted() {
+        return Err(error::Error::new(
+            error::ErrorKind::InvalidArgument,
+            format!(
+                "{} can not be used for the key slots",
+                kdf.kind.to_str()
+            ),
+        ));
+    }
+    let salt = generate_salt();
+    let kek = kdf.derive_key(secret, &salt)?;
+
+    Ok(KeySlot::Password {
+        kdf: *kdf,
+        factors,
+        salt,
+        wrapped_key: wrap_key(kind, &kek, aad, data_key)?,
+    })
+}
+
+/// HKDF-SHA256 of the shared secret,
+/// salted by the ephemeral and the recipient public keys
+fn x25519_kek(
+    shared_secret: &SharedSecret,
+    ephemeral_public: &PublicKey,
+    recipient_public: &PublicKey,
+    info: &[u8],
+) -> Option<DerivedKeyType> {
+    if !shared_secret.was_contributory() {
+        return None;
+    }
+
+    let salt = [
+        ephemeral_public.as_bytes().as_slice(),
+        recipient_public.as_bytes(),
+    ]
+    .concat();
+    let mut kek = [0u8; KDF_KEY_SIZE];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(info, &mut kek)
+        .expect("Wrong HKDF output size");
+
+    Some(kek)
+}
+
+/// Wraps the data key by the key, agreed with the recipient
+/// using a fresh ephemeral key
+pub fn new_x25519_slot(
+    kind: CipherKind,
+    aad: &[u8],
+    recipient: &X25519Recipient,
+    data_key: &DataKey,
+) -> error::Result<KeySlot> {
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral_secret);
+    let shared_secret = ephemeral_secret.diffie_hellman(&recipient.0);
+
+    let kek = x25519_kek(
+        &shared_secret,
+        &ephemeral_public,
+        &recipient.0,
+        X25519_KEK_INFO,
+    )
+    .ok_or_else(|| {
+        error::Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"Invalid X25519 recipient",
+        )
+    })?;
+
+    Ok(KeySlot::X25519 {
+        ephemeral_public: ephemeral_public.to_bytes(),
+        wrapped_key: wrap_key(kind, &kek, aad, data_key)?,
+    })
+}
+
+/// Wraps the data key into the `ssh-ed25519` stanza.
+///
+/// Stanza arguments are the recipient tag (see [`SshEd25519Recipient::tag`])
+/// and the ephemeral X25519 public key, the body is the wrapped data key
+pub fn new_ssh_ed25519_slot(
+    aad: &[u8],
+    recipient: &SshEd25519Recipient,
+    data_key: &DataKey,
+) -> error::Result<KeySlot> {
+    let recipient_public = recipient.to_x25519()?;
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral_secret);
+    let shared_secret = ephemeral_secret.diffie_hellman(&recipient_public);
+
+    let kek = x25519_kek(
+        &shared_secret,
+        &ephemeral_public,
+        &recipient_public,
+        SSH_ED25519_KEK_INFO,
+    )
+    .ok_or_else(|| {
+        error::Error::new_const(
+            error::ErrorKind::InvalidArgument,
+            &"Invalid ssh-ed25519 recipient",
+        )
+    })?;
+
+    Ok(KeySlot::Stanza {
+        stanza_type: SshEd25519Recipient::KEY_TYPE.to_string(),
+        args: [recipient.tag().as_slice(), ephemeral_public.as_bytes()]
+            .concat(),
+        body: wrap_key(STANZA_CIPHER_KIND, &kek, aad, data_key)?.to_vec(),
+    })
+}
+
+fn unlock_ssh_ed25519_stanza(
+    args: &[u8],
+    body: &[u8],
+    aad: &[u8],
+    identity: &SshEd25519Identity,
+) -> error::Result<Option<DataKey>> {
+    let recipient = identity.recipient();
+    let (tag, ephemeral_public) = args.split_at(args.len().min(4));
+    if tag != recipient.tag() {
+        return Ok(None);
+    }
+    let (Ok(ephemeral_public), Ok(wrapped_key)) = (
+        <[u8; 32]>::try_from(ephemeral_public),
+        body.try_into(),
+    ) else {
+        log::warn!(target: "keyslot unlock_slot", "Malformed ssh-ed25519 stanza");
+        return Ok(None);
+    };
+
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let shared_secret = identity
+        .to_x25519()
+        .diffie_hellman(&ephemeral_public);
+
+    Ok(x25519_kek(
+        &shared_secret,
+        &ephemeral_public,
+        &recipient.to_x25519()?,
+        SSH_ED25519_KEK_INFO,
+    )
+    .and_then(|kek| {
+        unwrap_key(
+            STANZA_CIPHER_KIND,
+            &kek,
+            aad,
+            wrapped_key,
+        )
+    }))
+}
+
+pub fn new_slot(
+    kind: CipherKind,
+    aad: &[u8],
+    recipient: &Recipient,
+    kdf: &KdfParams,
+    data_key: &DataKey,
+) -> error::Result<KeySlot> {
+    match recipient {
+        Recipient::Password(_)
+        | Recipient::Keyfile(_)
+        | Recipient::PasswordKeyfile(..) => {
+            let (factors, secret) = recipient
+                .key_factors()
+                .expect("Wrong key factors");
+            new_password_slot(
+                kind, aad, factors, &secret, kdf, data_key,
+            )
+        }
+        Recipient::X25519(recipient) => {
+            new_x25519_slot(kind, aad, recipient, data_key)
+        }
+        Recipient::SshEd25519(recipient) => {
+            new_ssh_ed25519_slot(aad, recipient, data_key)
+        }
+    }
+}
+
+/// Returns `None` if the slot is not unlocked by the identity
+pub fn unlock_slot(
+    slot: &KeySlot,
+    kind: CipherKind,
+    aad: &[u8],
+    identity: &Identity,
+) -> error::Result<Option<DataKey>> {
+    match (slot, identity) {
+        (
+            KeySlot::Password {
+                kdf,
+                factors,
+                salt,
+                wrapped_key,
+            },
+            identity,
+        ) => match identity.key_factors() {
+            // The slot of the other factors is skipped without the KDF run
+            Some((identity_factors, secret))
+                if identity_factors == *factors =>
+            {
+                let kek = kdf.derive_key(&secret, salt)?;
+                Ok(unwrap_key(kind, &kek, aad, wrapped_key))
+            }
+            _ => Ok(None),
+        },
+        (
+            KeySlot::X25519 {
+                ephemeral_public,
+                wrapped_key,
+            },
+            Identity::X25519(identity),
+        ) => {
+            let ephemeral_public = PublicKey::from(*ephemeral_public);
+            let shared_secret = identity
+                .0
+                .diffie_hellman(&ephemeral_public);
+
+            Ok(x25519_kek(
+                &shared_secret,
+                &ephemeral_public,
+                &identity.recipient().0,
+                X25519_KEK_INFO,
+            )
+            .and_then(|kek| unwrap_key(kind, &kek, aad, wrapped_key)))
+        }
+        (
+            KeySlot::Stanza {
+                stanza_type,
+                args,
+                body,
+            },
+            Identity::SshEd25519(identity),
+        ) if stanza_type == SshEd25519Recipient::KEY_TYPE => {
+            unlock_ssh_ed25519_stanza(args, body, aad, identity)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Tries all the slots one by one with every identity.
+/// Returns the index of the unlocked slot and the data key
+pub fn unlock_key_slots(
+    slots: &KeySlots,
+    kind: CipherKind,
+    aad: &[u8],
+    identities: &[Identity],
+) -> error::Result<(usize, DataKey)> {
+    for (index, slot) in slots.slots.iter().enumerate() {
+        log::debug!(target: "keyslot unlock_key_slots", "Trying the key slot #{index}");
+        for identity in identities {
+            if let Some(data_key) = unlock_slot(slot, kind, aad, identity)? {
+                log::debug!(target: "keyslot unlock_key_slots", "Key slot #{index} is unlocked");
+                return Ok((index, data_key));
+            }
+        }
+    }
+
+    Err(error::Error::new_const(
+        error::ErrorKind::WrongPassword,
+        &"No key slot is unlocked by the key",
+    ))
+}
+
+/// Unlocks the key slots of the file, see [`unlock_key_slots`].
+/// The data key is verified by the key check value, if the RawMeta has it,
+/// so the file content is never decrypted by a damaged key slot
+pub fn unlock_file_key_slots(
+    raw_meta: &RawMeta,
+    raw_meta_bytes: &[u8],
+    slots: &KeySlots,
+    identities: &[Identity],
+) -> error::Result<(usize, DataKey)> {
+    let (index, data_key) = unlock_key_slots(
+        slots,
+        raw_meta.cipher_kind,
+        raw_meta_bytes,
+        identities,
+    )?;
+
+    match raw_meta.key_check {
+        Some(key_check) if key_check != key_check_value(&data_key) => {
+            Err(error::Error::new(
+                error::ErrorKind::RawMetaDecodeError,
+                format!("Key slot #{index} does not match the key check value"),
+            ))
+        }
+        _ => Ok((index, data_key)),
+    }
+}
+
+/// File header with the unlocked data key
+#[derive(Clone, Debug)]
+pub struct UnlockedHeader {
+    pub raw_meta: RawMeta,
+
+    /// MetaHeader and RawMeta bytes as is
+    pub raw_meta_bytes: Vec<u8>,
+
+    pub slots: KeySlots,
+
+    /// Index of the slot, unlocked by the key
+    pub unlocked_index: usize,
+
+    pub data_key: DataKey,
+}
+
+impl UnlockedHeader {
+    /// Reads the header up to the encrypted stream
+    pub fn read(
+        source: &mut dyn Read,
+        identities: &[Identity],
+    ) -> error::Result<Self> {
+        let (raw_meta, raw_meta_bytes) = get_raw_meta_with_bytes(source)?;
+        if raw_meta.kdf.is_some() {
+            return Err(error::Error::new(
+                error::ErrorKind::InvalidArgument,
+                format!(
+                    "File format version {} has no key slots, re-encrypt the file to upgrade",
+                    raw_meta.version
+                ),
+            ));
+        }
+
+        let slots = get_key_slots(source)?;
+        let (unlocked_index, data_key) = unlock_file_key_slots(
+            &raw_meta,
+            &raw_meta_bytes,
+            &slots,
+            identities,
+        )?;
+
+        Ok(Self {
+            raw_meta,
+            raw_meta_bytes,
+            slots,
+            unlocked_index,
+            data_key,
+        })
+    }
+
+    pub fn new_slot(
+        &self,
+        recipient: &Recipient,
+        kdf: &KdfParams,
+    ) -> error::Result<KeySlot> {
+        new_slot(
+            self.raw_meta.cipher_kind,
+            &self.raw_meta_bytes,
+            recipient,
+            kdf,
+            &self.data_key,
+        )
+    }
+}
@@ -0,0 +1,365 @@
+use crate::cipher::CipherKind;
+use crate::error;
+use crate::error::ErrorKind;
+use crate::kdf::{KdfKind, KdfParams, KDF_SALT_SIZE};
+use crate::keyfile::Keyfile;
+use crate::keyslot::{
+    generate_data_key, key_check_value, new_password_slot, new_slot,
+    new_ssh_ed25519_slot, new_x25519_slot, unlock_file_key_slots,
+    unlock_key_slots, unlock_slot, Identity, Recipient,
+};
+use crate::meta::raw::{RawMeta, META_RAW_NONCE_SIZE};
+use crate::meta::slots::{KeyFactors, KeySlot, KeySlots};
+use crate::recipient::{SshEd25519Identity, X25519Identity};
+
+const PARAMS: KdfParams = KdfParams {
+    kind: KdfKind::Argon2id,
+    m_cost: 64,
+    t_cost: 1,
+    p_cost: 1,
+};
+
+const AAD: &[u8] = b"header";
+
+fn password(password: &str) -> Identity {
+    Identity::Password(password.to_string())
+}
+
+#[test]
+fn test_password_slot() -> error::Result<()> {
+    for kind in [CipherKind::ChaCha20Poly1305, CipherKind::AesGcm] {
+        let data_key = generate_data_key();
+        let slot = new_password_slot(
+            kind,
+            AAD,
+            KeyFactors::Password,
+            b"amongus",
+            &PARAMS,
+            &data_key,
+        )?;
+
+        assert_eq!(
+            unlock_slot(&slot, kind, AAD, &password("amongus"))?,
+            Some(data_key)
+        );
+        assert_eq!(
+            unlock_slot(&slot, kind, AAD, &password("amogus"))?,
+            None
+        );
+        assert_eq!(
+            unlock_slot(
+                &slot,
+                kind,
+                b"another header",
+                &password("amongus")
+            )?,
+            None
+        );
+    }
+
+    // The unsalted KDF would reuse the key with the fixed nonce
+    let result = new_password_slot(
+        CipherKind::ChaCha20Poly1305,
+        AAD,
+        KeyFactors::Password,
+        b"amongus",
+        &KdfParams::legacy(),
+        &generate_data_key(),
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_keyfile_slot() -> error::Result<()> {
+    let kind = CipherKind::ChaCha20Poly1305;
+    let keyfile = Keyfile::from_bytes(b"keyfile")?;
+    let data_key = generate_data_key();
+
+    let keyfile_slot = new_slot(
+        kind,
+        AAD,
+        &Recipient::Keyfile(keyfile.clone()),
+        &PARAMS,
+        &data_key,
+    )?;
+    let combined_slot = new_slot(
+        kind,
+        AAD,
+        &Recipient::PasswordKeyfile("amongus".to_string(), keyfile.clone()),
+        &PARAMS,
+        &data_key,
+    )?;
+
+    assert_eq!(
+        unlock_slot(
+            &keyfile_slot,
+            kind,
+            AAD,
+            &Identity::Keyfile(keyfile.clone())
+        )?,
+        Some(data_key)
+    );
+    assert_eq!(
+        unlock_slot(
+            &combined_slot,
+            kind,
+            AAD,
+            &Identity::PasswordKeyfile("amongus".to_string(), keyfile.clone())
+        )?,
+        Some(data_key)
+    );
+
+    // Both factors are required by the combined slot
+    for identity in [
+        password("amongus"),
+        Identity::Keyfile(keyfile.clone()),
+        Identity::PasswordKeyfile("amogus".to_string(), keyfile),
+        Identity::PasswordKeyfile(
+            "amongus".to_string(),
+            Keyfile::from_bytes(b"another keyfile")?,
+        ),
+    ] {
+        assert_eq!(
+            unlock_slot(&combined_slot, kind, AAD, &identity)?,
+            None
+        );
+    }
+    assert_eq!(
+        unlock_slot(
+            &keyfile_slot,
+            kind,
+            AAD,
+            &password("amongus")
+        )?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_x25519_slot() -> error::Result<()> {
+    for kind in [CipherKind::ChaCha20Poly1305, CipherKind::AesGcm] {
+        let identity = X25519Identity::generate();
+        let data_key = generate_data_key();
+        let slot = new_x25519_slot(
+            kind,
+            AAD,
+            &identity.recipient(),
+            &data_key,
+        )?;
+
+        assert_eq!(
+            unlock_slot(
+                &slot,
+                kind,
+                AAD,
+                &Identity::X25519(identity.clone())
+            )?,
+            Some(data_key)
+        );
+        assert_eq!(
+            unlock_slot(
+                &slot,
+                kind,
+                AAD,
+                &Identity::X25519(X25519Identity::generate())
+            )?,
+            None
+        );
+        assert_eq!(
+            unlock_slot(
+                &slot,
+                kind,
+                b"another header",
+                &Identity::X25519(identity)
+            )?,
+            None
+        );
+        assert_eq!(
+            unlock_slot(&slot, kind, AAD, &password("amongus"))?,
+            None
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ssh_ed25519_slot() -> error::Result<()> {
+    let identity: SshEd25519Identity =
+        include_str!("../../tests/general/ssh/id_ed25519").parse()?;
+    let data_key = generate_data_key();
+    let slot = new_ssh_ed25519_slot(AAD, &identity.recipient(), &data_key)?;
+
+    let KeySlot::Stanza { stanza_type, .. } = &slot else {
+        panic!("Expected the stanza, got {slot:?}");
+    };
+    assert_eq!(stanza_type, "ssh-ed25519");
+
+    // The stanza does not depend on the file cipher
+    for kind in [CipherKind::ChaCha20Poly1305, CipherKind::AesGcm] {
+        assert_eq!(
+            unlock_slot(
+                &slot,
+                kind,
+                AAD,
+                &Identity::SshEd25519(identity.clone())
+            )?,
+            Some(data_key)
+        );
+    }
+    assert_eq!(
+        unlock_slot(
+            &slot,
+            CipherKind::ChaCha20Poly1305,
+            b"another header",
+            &Identity::SshEd25519(identity)
+        )?,
+        None
+    );
+    assert_eq!(
+        unlock_slot(
+            &slot,
+            CipherKind::ChaCha20Poly1305,
+            AAD,
+            &Identity::X25519(X25519Identity::generate())
+        )?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_unknown_stanza() -> error::Result<()> {
+    let slot = KeySlot::Stanza {
+        stanza_type: "unknown".to_string(),
+        args: Vec::new(),
+        body: Vec::new(),
+    };
+    let identity: SshEd25519Identity =
+        include_str!("../../tests/general/ssh/id_ed25519").parse()?;
+
+    assert_eq!(
+        unlock_slot(
+            &slot,
+            CipherKind::ChaCha20Poly1305,
+            AAD,
+            &Identity::SshEd25519(identity)
+        )?,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_unlock_key_slots() -> error::Result<()> {
+    let kind = CipherKind::ChaCha20Poly1305;
+    let identity = X25519Identity::generate();
+    let data_key = generate_data_key();
+    let slots = KeySlots {
+        slots: [
+            Recipient::Password("first".to_string()),
+            Recipient::Password("second".to_string()),
+            Recipient::X25519(identity.recipient()),
+        ]
+        .iter()
+        .map(|recipient| new_slot(kind, AAD, recipient, &PARAMS, &data_key))
+        .collect::<error::Result<_>>()?,
+    };
+
+    assert_eq!(
+        unlock_key_slots(&slots, kind, AAD, &[password("second")])?,
+        (1, data_key)
+    );
+    assert_eq!(
+        unlock_key_slots(
+            &slots,
+            kind,
+            AAD,
+            &[password("third"), Identity::X25519(identity)]
+        )?,
+        (2, data_key)
+    );
+    assert_eq!(
+        unlock_key_slots(&slots, kind, AAD, &[password("third")])
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::WrongPassword
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_key_check() -> error::Result<()> {
+    let kind = CipherKind::ChaCha20Poly1305;
+    let data_key = generate_data_key();
+    let mut raw_meta = RawMeta {
+        version: RawMeta::latest_version(),
+        cipher_kind: kind,
+        nonce: [0u8; META_RAW_NONCE_SIZE],
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        chunk_size: RawMeta::DEFAULT_CHUNK_SIZE,
+        key_check: Some(key_check_value(&data_key)),
+        parity: None,
+        meta_len: None,
+    };
+    let slots = KeySlots {
+        slots: vec![new_slot(
+            kind,
+            AAD,
+            &Recipient::Password("amongus".to_string()),
+            &PARAMS,
+            &data_key,
+        )?],
+    };
+
+    assert_eq!(
+        unlock_file_key_slots(
+            &raw_meta,
+            AAD,
+            &slots,
+            &[password("amongus")]
+        )?,
+        (0, data_key)
+    );
+    assert_eq!(
+        unlock_file_key_slots(
+            &raw_meta,
+            AAD,
+            &slots,
+            &[password("amogus")]
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::WrongPassword
+    );
+
+    // The slot, which wraps another key
+    raw_meta.key_check = Some(key_check_value(&generate_data_key()));
+    assert_eq!(
+        unlock_file_key_slots(
+            &raw_meta,
+            AAD,
+            &slots,
+            &[password("amongus")]
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::RawMetaDecodeError
+    );
+
+    Ok(())
+}
@@ -4,36 +4,65 @@ pub mod encryption;
 pub mod error;
 
 pub mod app;
+pub mod archive;
+pub mod bulk;
 pub mod cli;
 pub mod file;
+pub mod kdf;
+pub mod keyfile;
+pub mod keyslot;
 pub mod meta;
+pub mod parity;
+pub mod recipient;
 
-use arrayref::array_ref;
 use std::convert::TryInto;
 use std::ffi::OsStr;
-use std::fs::{remove_file, File};
+use std::fs::{remove_file, File, OpenOptions};
 use std::io::Write;
-use std::path::Path;
-use std::{fs, io, iter};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
-use rand::{thread_rng, Rng};
-
-use crate::cipher::kind::select_cipher;
+use crate::archive::{read_index, ArchiveWriter, PackReader, UnpackTarget};
+use crate::bulk::{
+    mirror_path, process_files, walk_files, BulkOptions, BulkReport,
+};
+use crate::cipher::kind::{
+    select_cipher, RecoveryReport, StreamParams, TargetSelector,
+};
+use crate::cipher::nonce::{generate_nonce, RandomNonceGenerator};
 use crate::cipher::CipherKind;
-use crate::encryption::{add_raw_meta, get_raw_meta};
+use crate::encryption::{
+    add_key_slots, add_raw_meta, decrypted_file_path, encrypted_file_path,
+    get_raw_meta_with_bytes, raw_meta_to_bytes, safe_file_name,
+    try_detect_file_type, DecryptionOptions, DetectedFileType,
+    EncryptionOptions,
+};
 use crate::error::ErrorKind;
-use crate::file::OpenOrCreate;
-use meta::enc::EncryptedMeta;
+use crate::file::{
+    os_str_to_bytes, read_xattrs, restore_attributes, CheckedReader,
+    OpenOrCreate,
+};
+use crate::kdf::{KdfParams, KDF_SALT_SIZE};
+use crate::keyslot::{
+    generate_data_key, key_check_value, new_slot, unlock_file_key_slots,
+    DataKey, Identity, Recipient, UnlockedHeader,
+};
+use crate::meta::header::MetaHeader;
+use crate::meta::index::ArchiveIndex;
+use crate::meta::slots::KeySlots;
+use meta::enc::{ContentDigest, EncryptedMeta, FileAttributes};
 use meta::raw::RawMeta;
-use rand::distributions::Alphanumeric;
 use sha2::{Digest, Sha256};
+use std::io::{Read, Seek};
 
 extern crate core;
 extern crate log;
 
-pub fn get_hash(key: &str) -> error::Result<[u8; 32]> {
+/// Legacy key hashing. Used by version 1 files only,
+/// see [`kdf::KdfParams::derive_key`] for the actual key derivation
+pub fn get_hash(key: impl AsRef<[u8]>) -> error::Result<[u8; 32]> {
     let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
+    hasher.update(key.as_ref());
 
     let hashed_key: [u8; 32] = hasher
         .finalize()
@@ -42,156 +71,1133 @@ pub fn get_hash(key: &str) -> error::Result<[u8; 32]> {
     Ok(hashed_key)
 }
 
+/// Returns the content key: derived from the password itself
+/// before RawMeta version 6, or unwrapped by the key slot otherwise.
+/// The key slots are read from the source, if present
+fn get_data_key(
+    source: &mut dyn Read,
+    raw_meta: &RawMeta,
+    raw_meta_bytes: &[u8],
+    identities: &[Identity],
+) -> error::Result<DataKey> {
+    match raw_meta.kdf {
+        Some(kdf) => {
+            let password = identities
+                .iter()
+                .find_map(|identity| match identity {
+                    Identity::Password(password) => Some(password),
+                    _ => None,
+                })
+                .ok_or_else(|| {
+                    error::Error::new_const(
+                        ErrorKind::WrongPassword,
+                        &"The file is encrypted by the password only",
+                    )
+                })?;
+            kdf.derive_key(password.as_bytes(), &raw_meta.salt)
+        }
+        None => {
+            let slots = encryption::get_key_slots(source)?;
+            let (_, data_key) = unlock_file_key_slots(
+                raw_meta,
+                raw_meta_bytes,
+                &slots,
+                identities,
+            )?;
+            Ok(data_key)
+        }
+    }
+}
+
+/// Decrypts the file by the password, see [`try_decrypt_with`]
 pub fn try_decrypt(
     file_path: &Path,
-    hash_from_key: [u8; 32],
+    key: &str,
+    preview: bool,
+) -> error::Result<()> {
+    try_decrypt_with(
+        file_path,
+        &[Identity::Password(key.to_string())],
+        preview,
+    )
+}
+
+/// Decrypts the file by any of the identities, see [`try_decrypt_with_options`]
+pub fn try_decrypt_with(
+    file_path: &Path,
+    identities: &[Identity],
     preview: bool,
 ) -> error::Result<()> {
-    let (meta, target_path) = {
-        let target_file_path = &file_path.with_extension(
+    try_decrypt_with_options(
+        file_path,
+        identities,
+        &DecryptionOptions {
+            preview,
+            ..DecryptionOptions::default()
+        },
+    )
+}
+
+/// Decrypts the file by any of the identities next to the encrypted one,
+/// see [`try_decrypt_into`]
+pub fn try_decrypt_with_options(
+    file_path: &Path,
+    identities: &[Identity],
+    options: &DecryptionOptions,
+) -> error::Result<()> {
+    try_decrypt_into(file_path, None, identities, options)
+}
+
+/// Decrypts the file by any of the identities into the output directory,
+/// the one of the encrypted file by default.
+/// The original file attributes are restored, if the options allow it
+pub fn try_decrypt_into(
+    file_path: &Path,
+    out_dir: Option<&Path>,
+    identities: &[Identity],
+    options: &DecryptionOptions,
+) -> error::Result<()> {
+    let preview = options.preview;
+    // The decrypted file is named after the encrypted one,
+    // as it is in the output directory
+    let target_base = match out_dir {
+        None => file_path.to_path_buf(),
+        Some(out_dir) => out_dir.join(
             file_path
-                .extension()
-                .unwrap_or_else(|| OsStr::new(""))
-                .to_str()
-                .unwrap_or("")
-                .to_string()
-                + ".tmp-enc",
-        );
+                .file_name()
+                .unwrap_or_else(|| OsStr::new("")),
+        ),
+    };
+    let target_file_path = &target_base.with_extension(
+        file_path
+            .extension()
+            .unwrap_or_else(|| OsStr::new(""))
+            .to_str()
+            .unwrap_or("")
+            .to_string()
+            + ".tmp-enc",
+    );
 
-        let target: Box<dyn Write> = if preview {
-            println!("\n----------------- [ cut here ] -----------------");
-            Box::from(io::stdout()) as Box<dyn Write>
-        } else {
-            let file = File::open_or_create(target_file_path)?;
+    // The key is checked before the target is selected,
+    // so the wrong key leaves nothing behind. The archive is unpacked
+    // (or listed on the preview) on the fly, its content is not written as is
+    let mut target = DecryptedTarget::None;
+    let result = decrypt_with(
+        file_path,
+        identities,
+        Box::new(|enc_meta| {
+            if preview {
+                println!("\n----------------- [ cut here ] -----------------");
+            }
+            let writer: Box<dyn Write> = match (enc_meta.is_archive, preview) {
+                (true, true) => {
+                    let writer = ArchiveWriter::new(UnpackTarget::List, None);
+                    target = DecryptedTarget::Listing(writer.clone());
+                    Box::new(writer)
+                }
+                (true, false) => {
+                    let unpacking =
+                        Unpacking::start(file_path, out_dir, options)?;
+                    let writer = unpacking.writer.clone();
+                    target = DecryptedTarget::Archive(unpacking);
+                    Box::new(writer)
+                }
+                (false, true) => Box::new(io::stdout()),
+                (false, false) => {
+                    let file = create_new(target_file_path)?;
+                    target = DecryptedTarget::File;
+                    Box::new(file)
+                }
+            };
+            Ok(writer)
+        }),
+    );
 
-            Box::from(file) as Box<dyn Write>
-        };
+    let (meta, real_target_path) = match target {
+        DecryptedTarget::Listing(writer) => {
+            let index = archive_result(result, &writer)
+                .and_then(|_| writer.finish())
+                .map_err(|e| e.with_file_path(file_path))?;
+            print!("{index}");
+            println!("\n------------ [ end of the content ] ------------\n");
+            return Ok(());
+        }
+        DecryptedTarget::Archive(unpacking) => {
+            return unpacking
+                .finish(file_path, options, result)
+                .map(|_| ());
+        }
+        DecryptedTarget::File => {
+            let placed = result.and_then(|enc_meta| {
+                let real_target = decrypted_file_path(
+                    &target_base,
+                    &enc_meta.filename,
+                    options.filename_policy,
+                )?;
+                rename_new(target_file_path, &real_target)?;
+                Ok((enc_meta, real_target))
+            });
+            match placed {
+                Ok(placed) => placed,
+                Err(e) => {
+                    remove_file(target_file_path)?;
+                    return Err(e.with_file_path(file_path));
+                }
+            }
+        }
+        DecryptedTarget::None => {
+            // The filename is checked on the preview too
+            result
+                .and_then(|enc_meta| {
+                    decrypted_file_path(
+                        &target_base,
+                        &enc_meta.filename,
+                        options.filename_policy,
+                    )
+                })
+                .map_err(|e| e.with_file_path(file_path))?;
+            if preview {
+                println!(
+                    "\n------------ [ end of the content ] ------------\n"
+                );
+            }
+            return Ok(());
+        }
+    };
 
-        let mut source = File::open_read_only(file_path)?;
-        let raw_meta = get_raw_meta(&mut source)?;
+    log::info!(target: "lib try_decrypt", "Decrypted into {real_target_path:?}");
+    if let Some(policy) = options.restore_policy() {
+        for warning in restore_attributes(
+            &real_target_path,
+            &meta.attributes,
+            &meta.xattrs,
+            policy,
+        ) {
+            log::warn!(target: "lib try_decrypt", "{warning}");
+        }
+    }
 
-        let file_len = source.metadata()?.len() as usize;
-        let cipher = select_cipher(
-            raw_meta.cipher_kind,
-            Some(file_len - raw_meta.len()),
-        );
+    Ok(())
+}
 
-        let enc_meta = cipher.decrypt(
-            Box::from(source),
-            target,
-            &hash_from_key,
-            &raw_meta.nonce,
-        )?;
+/// Where [`try_decrypt_into`] writes the content, once the meta is decrypted
+enum DecryptedTarget {
+    /// Not selected yet, or the content is previewed
+    None,
+    File,
+    Archive(Unpacking),
+    Listing(ArchiveWriter),
+}
+
+/// Decrypts the file without writing the plain text anywhere.
+/// Every chunk is authenticated and the content is checked by its digest,
+/// if the file has one (since EncryptedMeta version 2)
+pub fn try_verify_with(
+    file_path: &Path,
+    identities: &[Identity],
+) -> error::Result<EncryptedMeta> {
+    decrypt_to(
+        file_path,
+        identities,
+        Box::new(io::sink()),
+    )
+    .map_err(|e| e.with_file_path(file_path))
+}
+
+/// Decrypts the file by any of the identities into the target
+fn decrypt_to(
+    file_path: &Path,
+    identities: &[Identity],
+    target: Box<dyn Write>,
+) -> error::Result<EncryptedMeta> {
+    decrypt_with(
+        file_path,
+        identities,
+        Box::new(|_| Ok(target)),
+    )
+}
+
+/// Decrypts the file by any of the identities into the target,
+/// selected by the EncryptedMeta, see [`cipher::kind::Cipher::decrypt_with`]
+fn decrypt_with(
+    file_path: &Path,
+    identities: &[Identity],
+    select: TargetSelector<'_>,
+) -> error::Result<EncryptedMeta> {
+    let mut source = File::open_read_only(file_path)?;
+    let (raw_meta, raw_meta_bytes) = get_raw_meta_with_bytes(&mut source)?;
+    let data_key = get_data_key(
+        &mut source,
+        &raw_meta,
+        &raw_meta_bytes,
+        identities,
+    )
+    .map_err(|e| e.with_file_path(file_path))?;
+
+    let file_len = source.metadata()?.len() as usize;
+    let mut params = StreamParams::new(&raw_meta, &raw_meta_bytes);
+    params.stream_offset = source.stream_position()?;
+    if !params.finalized || params.associated_data.is_empty() {
+        log::warn!(target: "lib decrypt_with", "File uses the legacy format version {}, the truncation or the header modification can not be detected. Re-encrypt the file to upgrade", raw_meta.version);
+    }
+
+    let cipher = select_cipher(
+        raw_meta.cipher_kind,
+        params,
+        Some(file_len - source.stream_position()? as usize),
+    );
+
+    cipher.decrypt_with(
+        Box::from(source),
+        select,
+        &data_key,
+        &raw_meta.nonce,
+    )
+}
 
-        let real_target = file_path.with_file_name(&enc_meta.filename);
-        if real_target.exists() {
+/// Decrypts every chunk of the damaged file, which still authenticates,
+/// see [`cipher::kind::Cipher::recover`]. The original file is kept.
+/// If the original file name is lost or unsafe, the content is written
+/// into the file with the `recovered` extension
+pub fn try_recover_with(
+    file_path: &Path,
+    identities: &[Identity],
+    zero_fill: bool,
+) -> error::Result<RecoveryReport> {
+    let target_file_path = &file_path.with_extension("recover-tmp-enc");
+
+    let mut source = File::open_read_only(file_path)?;
+    let (raw_meta, raw_meta_bytes) = get_raw_meta_with_bytes(&mut source)?;
+    let data_key = get_data_key(
+        &mut source,
+        &raw_meta,
+        &raw_meta_bytes,
+        identities,
+    )
+    .map_err(|e| e.with_file_path(file_path))?;
+
+    let mut params = StreamParams::new(&raw_meta, &raw_meta_bytes);
+    params.stream_offset = source.stream_position()?;
+    let cipher = select_cipher(raw_meta.cipher_kind, params, None);
+
+    let target = create_new(target_file_path)?;
+    let report = match cipher.recover(
+        Box::from(source),
+        Box::from(target),
+        &data_key,
+        &raw_meta.nonce,
+        zero_fill,
+    ) {
+        Ok(report) => report,
+        Err(e) => {
             remove_file(target_file_path)?;
-            return Err(error::Error::new_file_already_exist(
-                real_target.to_str().unwrap_or(""),
-            ));
-        }
-        if preview {
-            println!("\n------------ [ end of the content ] ------------\n");
+            return Err(e.with_file_path(file_path));
         }
-
-        (enc_meta, target_file_path.clone())
     };
 
-    if !preview {
-        let real_target_path = file_path.with_file_name(meta.filename);
-        println!(
-            "Target {:?}, real target {:?}",
-            target_path, real_target_path
+    let real_target_path = report
+        .enc_meta
+        .as_ref()
+        .and_then(|enc_meta| safe_file_name(&enc_meta.filename))
+        .map_or_else(
+            || file_path.with_extension("recovered"),
+            |name| file_path.with_file_name(name),
         );
-        fs::rename(target_path, real_target_path)?;
+    if let Err(e) = rename_new(target_file_path, &real_target_path) {
+        remove_file(target_file_path)?;
+        return Err(e);
     }
 
-    // Err(error::Error::new_const(
-    //     ErrorKind::OtherError,
-    //     &"Not implemented",
-    // ))
-
-    Ok(())
+    Ok(report)
 }
 
+/// Encrypts the file by the password, see [`try_encrypt_to`]
 pub fn try_encrypt(
     file_path: &Path,
     out_file_path: Option<&Path>,
-    hash_from_key: [u8; 32],
+    key: &str,
+    options: &EncryptionOptions,
 ) -> error::Result<()> {
-    let fallback_target_file_path = file_path.with_extension("enc");
+    try_encrypt_to(
+        file_path,
+        out_file_path,
+        &[Recipient::Password(key.to_string())],
+        options,
+    )
+}
+
+/// Encrypts the file, so any of the recipients is able to decrypt it
+pub fn try_encrypt_to(
+    file_path: &Path,
+    out_file_path: Option<&Path>,
+    recipients: &[Recipient],
+    options: &EncryptionOptions,
+) -> error::Result<()> {
+    let fallback_target_file_path = encrypted_file_path(file_path);
     let target_file_path = match out_file_path {
         None => fallback_target_file_path.as_path(),
         Some(p) => p,
     };
 
-    println!("Target file path: {target_file_path:?}");
+    log::info!(target: "lib try_encrypt", "Target file path: {target_file_path:?}");
+
+    let mut source_file = File::open(file_path)?;
+    let source_metadata = source_file.metadata()?;
+    let file_len = source_metadata.len() as usize;
 
-    let mut rng = thread_rng();
-    let rand_string = iter::repeat(())
-        .map(|()| rng.sample(Alphanumeric))
-        .map(u8::from)
-        .take(19)
-        .collect::<Vec<u8>>();
+    let filename = file_path.file_name().ok_or_else(|| {
+        error::Error::new_const(ErrorKind::OtherError, &"Internal")
+    })?;
+    let filename = os_str_to_bytes(filename).ok_or_else(|| {
+        error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "File name {filename:?} is not valid Unicode, it is not supported on this platform"
+            ),
+        )
+    })?;
+    // The source is read twice, because the digest
+    // precedes the content. The second pass checks it
+    let content = ContentDigest::from_reader(&mut source_file)?;
+    source_file.rewind()?;
+    let source = CheckedReader::new(file_path, source_file, content);
+    let enc_meta = EncryptedMeta {
+        filename,
+        content: Some(content),
+        attributes: FileAttributes::from_metadata(&source_metadata),
+        xattrs: if options.xattrs {
+            read_xattrs(file_path)?
+        } else {
+            Vec::new()
+        },
+        is_archive: false,
+    };
 
-    let nonce = array_ref![rand_string.as_slice(), 0, 19];
+    encrypt_source(
+        Box::new(source),
+        file_len,
+        target_file_path,
+        recipients,
+        options,
+        &enc_meta,
+    )
+}
+
+/// Writes the header and the encrypted source of `source_len` bytes
+/// into the new target file
+fn encrypt_source(
+    source: Box<dyn Read>,
+    source_len: usize,
+    target_file_path: &Path,
+    recipients: &[Recipient],
+    options: &EncryptionOptions,
+    enc_meta: &EncryptedMeta,
+) -> error::Result<()> {
+    if recipients.is_empty() {
+        return Err(error::Error::new_const(
+            ErrorKind::InvalidArgument,
+            &"No recipients",
+        ));
+    }
+
+    if !RawMeta::is_chunk_size_valid(options.chunk_size) {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "Chunk size must be in range {}..={}, got: {}",
+                RawMeta::MIN_CHUNK_SIZE,
+                RawMeta::MAX_CHUNK_SIZE,
+                options.chunk_size
+            ),
+        ));
+    }
+    if !options.kdf.is_valid() {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "Argon2id costs must be at most {} KiB, {} passes and {} lanes",
+                KdfParams::MAX_M_COST,
+                KdfParams::MAX_T_COST,
+                KdfParams::MAX_P_COST
+            ),
+        ));
+    }
+    let meta_len = MetaHeader::size() + enc_meta.len();
+    if enc_meta.len() as u64 > EncryptedMeta::MAX_SIZE {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "Encrypted meta must be at most {} bytes, got: {}",
+                EncryptedMeta::MAX_SIZE,
+                enc_meta.len()
+            ),
+        ));
+    }
+    if let Some(parity) = options.parity.filter(|p| !p.is_valid()) {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "Parity shards must be in range 1..={}, got: {}",
+                256 - parity.data_shards as usize,
+                parity.parity_shards
+            ),
+        ));
+    }
+
+    let nonce = generate_nonce(
+        options.cipher_kind,
+        &mut RandomNonceGenerator,
+    );
     log::debug!(target: "lib try_encrypt", "Generated nonce");
     log::trace!(target: "lib try_encrypt", "Nonce: {nonce:?}");
 
-    {
-        let source_file = File::open(file_path)?;
-        let file_len = source_file.metadata()?.len() as usize;
+    let data_key = generate_data_key();
+    let raw_meta = RawMeta {
+        version: RawMeta::latest_version(),
+        cipher_kind: options.cipher_kind,
+        nonce,
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        chunk_size: options.chunk_size,
+        key_check: Some(key_check_value(&data_key)),
+        parity: options.parity,
+        meta_len: Some(meta_len as u32),
+    };
+    let raw_meta_bytes = raw_meta_to_bytes(&raw_meta);
 
-        if target_file_path.exists() {
-            return Err(error::Error::new_file_already_exist(
-                target_file_path.to_str().unwrap_or(""),
-            ));
-        }
+    let key_slots = KeySlots {
+        slots: recipients
+            .iter()
+            .map(|recipient| {
+                new_slot(
+                    raw_meta.cipher_kind,
+                    &raw_meta_bytes,
+                    recipient,
+                    &options.kdf,
+                    &data_key,
+                )
+            })
+            .collect::<error::Result<_>>()?,
+    };
+    check_key_slots_size(&key_slots)?;
+    log::debug!(target: "lib try_encrypt", "Data key wrapped");
+
+    let mut dist_file = create_new(target_file_path)?;
+
+    let cipher = select_cipher(
+        raw_meta.cipher_kind,
+        StreamParams::new(&raw_meta, &raw_meta_bytes),
+        Some(source_len),
+    );
+    let result = add_raw_meta(&raw_meta, &mut dist_file)
+        .and_then(|_| add_key_slots(&key_slots, &mut dist_file))
+        .and_then(|_| {
+            cipher.encrypt(
+                source,
+                Box::new(dist_file),
+                &data_key,
+                &raw_meta.nonce,
+                enc_meta,
+            )
+        });
+    // The partial target is not decryptable
+    if result.is_err() {
+        remove_file(target_file_path)?;
+    }
+    result
+}
+
+/// The larger key slots are not read back, see [`get_key_slots`]
+fn check_key_slots_size(key_slots: &KeySlots) -> error::Result<()> {
+    if key_slots.len() > KeySlots::MAX_SIZE {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "Key slots must be at most {} bytes, got: {}",
+                KeySlots::MAX_SIZE,
+                key_slots.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the new file. The existing one is never reused,
+/// so two writers can not share a target
+fn create_new(path: &Path) -> error::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| already_exist_or(e, path))
+}
 
+/// Moves the file to the path, which must not exist.
+/// Unlike [`fs::rename`], the existing file is never replaced
+fn rename_new(from: &Path, to: &Path) -> error::Result<()> {
+    rename_new_with(from, to, |from, to| {
+        fs::hard_link(from, to)
+    })
+}
+
+/// The file systems without the hard links (FAT, many SMB mounts)
+/// fall back to the existence check and [`fs::rename`],
+/// which may replace the file, created in between
+fn rename_new_with(
+    from: &Path,
+    to: &Path,
+    hard_link: fn(&Path, &Path) -> io::Result<()>,
+) -> error::Result<()> {
+    match hard_link(from, to) {
+        Ok(()) => {
+            remove_file(from)?;
+            Ok(())
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+            ) =>
         {
-            // Truncate the file
-            let dist_file = File::open_or_create(target_file_path)?;
-            dist_file.set_len(0)?;
+            log::debug!(target: "lib rename_new", "Hard link is not supported: {e}");
+            match fs::symlink_metadata(to) {
+                Ok(_) => Err(error::Error::new_file_already_exist(
+                    to.to_str().unwrap_or(""),
+                )),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    fs::rename(from, to)?;
+                    Ok(())
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+        Err(e) => Err(already_exist_or(e, to)),
+    }
+}
+
+fn already_exist_or(e: io::Error, path: &Path) -> error::Error {
+    match e.kind() {
+        io::ErrorKind::AlreadyExists => {
+            error::Error::new_file_already_exist(path.to_str().unwrap_or(""))
+        }
+        _ => e.into(),
+    }
+}
+
+/// Encrypts every regular file of the directory tree, see [`try_encrypt_to`].
+/// The files, which are encrypted already, are skipped.
+/// The failed files are collected in the report, the walk goes on
+pub fn try_encrypt_dir(
+    root: &Path,
+    recipients: &[Recipient],
+    options: &EncryptionOptions,
+    bulk: &BulkOptions,
+) -> error::Result<BulkReport> {
+    let mut report = BulkReport::default();
+    let files = walk_files(root, bulk, &mut report)?;
+    process_files(files, bulk, &mut report, |file_path| {
+        if try_detect_file_type(file_path)? == DetectedFileType::Encrypted {
+            return Ok(false);
+        }
+        match &bulk.output_dir {
+            None => {
+                try_encrypt_to(file_path, None, recipients, options)?;
+                if !bulk.keep_original {
+                    remove_file(file_path)?;
+                }
+            }
+            Some(output_dir) => {
+                let out_file_path = encrypted_file_path(&mirror_path(
+                    root, file_path, output_dir,
+                ));
+                if let Some(parent) = out_file_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                try_encrypt_to(
+                    file_path,
+                    Some(&out_file_path),
+                    recipients,
+                    options,
+                )?;
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(report)
+}
+
+/// Decrypts every encrypted file of the directory tree, see [`try_decrypt_into`].
+/// The other files are skipped. The preview is not supported.
+/// The failed files are collected in the report, the walk goes on
+pub fn try_decrypt_dir(
+    root: &Path,
+    identities: &[Identity],
+    options: &DecryptionOptions,
+    bulk: &BulkOptions,
+) -> error::Result<BulkReport> {
+    let options = DecryptionOptions {
+        preview: false,
+        ..*options
+    };
+    let mut report = BulkReport::default();
+    let files = walk_files(root, bulk, &mut report)?;
+    process_files(files, bulk, &mut report, |file_path| {
+        if try_detect_file_type(file_path)? != DetectedFileType::Encrypted {
+            return Ok(false);
+        }
+        match &bulk.output_dir {
+            None => {
+                try_decrypt_into(file_path, None, identities, &options)?;
+                if !bulk.keep_original {
+                    remove_file(file_path)?;
+                }
+            }
+            Some(output_dir) => {
+                let out_dir = mirror_path(root, file_path, output_dir)
+                    .parent()
+                    .map_or_else(|| output_dir.clone(), Path::to_path_buf);
+                fs::create_dir_all(&out_dir)?;
+                try_decrypt_into(
+                    file_path,
+                    Some(&out_dir),
+                    identities,
+                    &options,
+                )?;
+            }
         }
+        Ok(true)
+    })?;
+    Ok(report)
+}
 
-        let mut dist_file = File::open_or_create(target_file_path)?;
+/// Packs the regular files of the directory tree into the single encrypted
+/// archive, see [`archive`]. The files are filtered like [`try_encrypt_dir`]
+/// does, the output directory and the jobs of the options are not used.
+/// The files, which can not be read, are left out and collected
+/// in the report
+pub fn try_pack(
+    root: &Path,
+    out_file_path: Option<&Path>,
+    recipients: &[Recipient],
+    options: &EncryptionOptions,
+    bulk: &BulkOptions,
+) -> error::Result<BulkReport> {
+    let mut report = BulkReport::default();
+    let files = walk_files(root, bulk, &mut report)?;
+    // The archive is named after the directory, even if it is `.`
+    let root_path = fs::canonicalize(root)?;
 
-        let filename = file_path
-            .file_name()
-            .ok_or_else(|| {
-                error::Error::new_const(ErrorKind::OtherError, &"Internal")
-            })?
-            .to_str()
-            .ok_or_else(|| {
-                error::Error::new_const(ErrorKind::OtherError, &"Internal")
-            })?;
-        let enc_meta = EncryptedMeta {
-            filename: filename.to_string(),
+    let fallback_target_file_path = encrypted_file_path(&root_path);
+    let target_file_path = match out_file_path {
+        None => fallback_target_file_path.as_path(),
+        Some(p) => p,
+    };
+    if target_file_path.exists() {
+        return Err(error::Error::new_file_already_exist(
+            target_file_path.to_str().unwrap_or(""),
+        ));
+    }
+    log::info!(target: "lib try_pack", "Target file path: {target_file_path:?}");
+
+    let filename = root_path
+        .file_name()
+        .and_then(os_str_to_bytes)
+        .ok_or_else(|| {
+            error::Error::new(
+                ErrorKind::InvalidArgument,
+                format!("Directory name {root_path:?} can not be stored"),
+            )
+        })?;
+    let (index, files) = read_index(root, files, &mut report);
+    log::info!(target: "lib try_pack", "Entries: {}, content: {} bytes", index.entries.len(), index.content_size());
+
+    let enc_meta = EncryptedMeta {
+        filename,
+        content: None,
+        attributes: FileAttributes::from_metadata(&fs::metadata(root)?),
+        xattrs: Vec::new(),
+        is_archive: true,
+    };
+    let source = PackReader::new(&index, files.clone());
+    let source_len = source.content_len() as usize;
+    encrypt_source(
+        Box::new(source),
+        source_len,
+        target_file_path,
+        recipients,
+        options,
+        &enc_meta,
+    )?;
+
+    report.processed = files;
+    Ok(report)
+}
+
+/// Decrypts the archive into the writer, see [`ArchiveWriter::finish`]
+fn decrypt_archive(
+    file_path: &Path,
+    identities: &[Identity],
+    writer: &ArchiveWriter,
+) -> error::Result<EncryptedMeta> {
+    let enc_meta = archive_result(
+        decrypt_to(
+            file_path,
+            identities,
+            Box::new(writer.clone()),
+        ),
+        writer,
+    )?;
+    if !enc_meta.is_archive {
+        return Err(error::Error::new(
+            ErrorKind::InvalidArgument,
+            format!(
+                "File '{}' is not an archive",
+                file_path.display()
+            ),
+        ));
+    }
+    Ok(enc_meta)
+}
+
+/// The cipher reports the error of the writer as the I/O one,
+/// the real error is taken from the writer
+fn archive_result(
+    result: error::Result<EncryptedMeta>,
+    writer: &ArchiveWriter,
+) -> error::Result<EncryptedMeta> {
+    result.map_err(|e| writer.take_error().unwrap_or(e))
+}
+
+/// Lists the entries of the archive. Nothing is written,
+/// but every entry is checked by its digest like [`try_verify_with`] does
+pub fn try_list_archive(
+    file_path: &Path,
+    identities: &[Identity],
+) -> error::Result<ArchiveIndex> {
+    let writer = ArchiveWriter::new(UnpackTarget::List, None);
+    decrypt_archive(file_path, identities, &writer)
+        .and_then(|_| writer.finish())
+        .map_err(|e| e.with_file_path(file_path))
+}
+
+/// Unpacks the archive into the output directory, the one of the archive
+/// by default. The unpacked directory is named after the packed one,
+/// which is checked by the filename policy like [`try_decrypt_into`] does.
+/// The entries are written into the temporary directory first,
+/// so the failed unpacking leaves nothing behind
+pub fn try_unpack(
+    file_path: &Path,
+    out_dir: Option<&Path>,
+    identities: &[Identity],
+    options: &DecryptionOptions,
+) -> error::Result<ArchiveIndex> {
+    let unpacking = Unpacking::start(file_path, out_dir, options)?;
+    let result = decrypt_archive(file_path, identities, &unpacking.writer);
+    unpacking.finish(file_path, options, result)
+}
+
+/// The temporary directory, the archive content is unpacked into,
+/// see [`try_unpack`]
+struct Unpacking {
+    target_base: PathBuf,
+    target_dir_path: PathBuf,
+    writer: ArchiveWriter,
+}
+
+impl Unpacking {
+    fn start(
+        file_path: &Path,
+        out_dir: Option<&Path>,
+        options: &DecryptionOptions,
+    ) -> error::Result<Self> {
+        let target_base = match out_dir {
+            None => file_path.to_path_buf(),
+            Some(out_dir) => out_dir.join(
+                file_path
+                    .file_name()
+                    .unwrap_or_else(|| OsStr::new("")),
+            ),
         };
+        let target_dir_path = target_base.with_extension(
+            file_path
+                .extension()
+                .unwrap_or_else(|| OsStr::new(""))
+                .to_str()
+                .unwrap_or("")
+                .to_string()
+                + ".tmp-enc-dir",
+        );
+        fs::create_dir(&target_dir_path)
+            .map_err(|e| already_exist_or(e, &target_dir_path))?;
 
-        let raw_meta = RawMeta {
-            cipher_kind: CipherKind::ChaCha20Poly1305,
-            nonce: *nonce,
+        let writer = ArchiveWriter::new(
+            UnpackTarget::Directory(target_dir_path.clone()),
+            options.restore_policy(),
+        );
+        Ok(Self {
+            target_base,
+            target_dir_path,
+            writer,
+        })
+    }
+
+    /// Moves the unpacked directory into its place by the decryption result,
+    /// or removes it
+    fn finish(
+        self,
+        file_path: &Path,
+        options: &DecryptionOptions,
+        result: error::Result<EncryptedMeta>,
+    ) -> error::Result<ArchiveIndex> {
+        let Self {
+            target_base,
+            target_dir_path,
+            writer,
+        } = self;
+        let unpacked = archive_result(result, &writer).and_then(|enc_meta| {
+            let index = writer.finish()?;
+            let real_target = decrypted_file_path(
+                &target_base,
+                &enc_meta.filename,
+                options.filename_policy,
+            )?;
+            if real_target.exists() {
+                return Err(error::Error::new_file_already_exist(
+                    real_target.to_str().unwrap_or(""),
+                ));
+            }
+            Ok((enc_meta, index, real_target))
+        });
+        let (enc_meta, index, real_target_path) = match unpacked {
+            Ok(unpacked) => unpacked,
+            Err(e) => {
+                fs::remove_dir_all(&target_dir_path)?;
+                return Err(e.with_file_path(file_path));
+            }
         };
-        add_raw_meta(&raw_meta, &mut dist_file)?;
 
-        // println!("File len: {}, raw meta length: {}", file_len, raw_meta.len());
-        let cipher = select_cipher(raw_meta.cipher_kind, Some(file_len));
+        log::info!(target: "lib try_unpack", "Unpacked into {real_target_path:?}");
+        fs::rename(target_dir_path, &real_target_path)?;
 
-        cipher.encrypt(
-            Box::new(source_file),
-            Box::new(dist_file),
-            &hash_from_key,
-            nonce,
-            &enc_meta,
-        )?;
+        if let Some(policy) = options.restore_policy() {
+            for warning in restore_attributes(
+                &real_target_path,
+                &enc_meta.attributes,
+                &enc_meta.xattrs,
+                policy,
+            ) {
+                log::warn!(target: "lib try_unpack", "{warning}");
+            }
+        }
+        Ok(index)
     }
-    Ok(())
+}
+
+/// Extracts the single entry of the archive into the output directory,
+/// the one of the archive by default. The file is named after the last
+/// component of the entry path. Returns the path of the extracted file
+pub fn try_extract(
+    file_path: &Path,
+    entry_path: &[u8],
+    out_dir: Option<&Path>,
+    identities: &[Identity],
+    options: &DecryptionOptions,
+) -> error::Result<PathBuf> {
+    let name = entry_path
+        .rsplit(|c| *c == b'/')
+        .next()
+        .and_then(safe_file_name)
+        .ok_or_else(|| {
+            error::Error::new(
+                ErrorKind::UnsafeFilename,
+                format!(
+                    "Unsafe archive entry path '{}'",
+                    String::from_utf8_lossy(entry_path)
+                ),
+            )
+        })?;
+    let out_dir = out_dir.unwrap_or_else(|| {
+        file_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+    });
+
+    let real_target_path = out_dir.join(&name);
+    if real_target_path.exists() {
+        return Err(error::Error::new_file_already_exist(
+            real_target_path.to_str().unwrap_or(""),
+        ));
+    }
+    let mut target_name = name;
+    target_name.push(".tmp-enc");
+    let target_file_path = out_dir.join(target_name);
+
+    let writer = ArchiveWriter::new(
+        UnpackTarget::Entry {
+            path: entry_path.to_vec(),
+            file_path: target_file_path.clone(),
+        },
+        options.restore_policy(),
+    );
+    if let Err(e) = decrypt_archive(file_path, identities, &writer)
+        .and_then(|_| writer.finish())
+    {
+        if target_file_path.exists() {
+            remove_file(&target_file_path)?;
+        }
+        return Err(e.with_file_path(file_path));
+    }
+
+    if let Err(e) = rename_new(&target_file_path, &real_target_path) {
+        remove_file(&target_file_path)?;
+        return Err(e);
+    }
+    Ok(real_target_path)
+}
+
+/// Unlocks the key slots by any of the identities, lets `update` change them
+/// and rewrites the file header.
+/// The encrypted content is copied as is
+fn update_key_slots<F>(
+    file_path: &Path,
+    identities: &[Identity],
+    update: F,
+) -> error::Result<()>
+where
+    F: FnOnce(&mut UnlockedHeader) -> error::Result<()>,
+{
+    let mut source = File::open_read_only(file_path)?;
+    let mut header = UnlockedHeader::read(&mut source, identities)?;
+    update(&mut header)?;
+    check_key_slots_size(&header.slots)?;
+
+    let temp_file_path = &file_path.with_extension(
+        file_path
+            .extension()
+            .unwrap_or_else(|| OsStr::new(""))
+            .to_str()
+            .unwrap_or("")
+            .to_string()
+            + ".tmp-key",
+    );
+
+    // The temp file of the concurrent update is never reused
+    let mut target = create_new(temp_file_path)?;
+    let result = (|| {
+        target.write_all(&header.raw_meta_bytes)?;
+        add_key_slots(&header.slots, &mut target)?;
+        io::copy(&mut source, &mut target)?;
+        target.set_permissions(source.metadata()?.permissions())?;
+        target.sync_all()?;
+
+        fs::rename(temp_file_path, file_path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        remove_file(temp_file_path)?;
+        return result;
+    }
+    log::info!(target: "lib update_key_slots", "Key slots: {}", header.slots.slots.len());
+
+    result
+}
+
+/// Adds the key slot for `new_key`, see [`try_add_key_with`]
+pub fn try_add_key(
+    file_path: &Path,
+    key: &str,
+    new_key: &str,
+    kdf: &KdfParams,
+) -> error::Result<()> {
+    try_add_key_with(
+        file_path,
+        &[Identity::Password(key.to_string())],
+        &Recipient::Password(new_key.to_string()),
+        kdf,
+    )
+}
+
+/// Adds the key slot for the recipient.
+/// The file must be unlocked by any of the identities
+pub fn try_add_key_with(
+    file_path: &Path,
+    identities: &[Identity],
+    recipient: &Recipient,
+    kdf: &KdfParams,
+) -> error::Result<()> {
+    update_key_slots(file_path, identities, |header| {
+        let slot = header.new_slot(recipient, kdf)?;
+        header.slots.slots.push(slot);
+        Ok(())
+    })
+}
+
+/// Removes the key slot, unlocked by `key`, see [`try_remove_key_with`]
+pub fn try_remove_key(file_path: &Path, key: &str) -> error::Result<()> {
+    try_remove_key_with(
+        file_path,
+        &[Identity::Password(key.to_string())],
+    )
+}
+
+/// Removes the key slot, unlocked by any of the identities
+pub fn try_remove_key_with(
+    file_path: &Path,
+    identities: &[Identity],
+) -> error::Result<()> {
+    update_key_slots(file_path, identities, |header| {
+        if header.slots.slots.len() == 1 {
+            return Err(error::Error::new_const(
+                ErrorKind::InvalidArgument,
+                &"The last key slot can not be removed",
+            ));
+        }
+
+        header
+            .slots
+            .slots
+            .remove(header.unlocked_index);
+        Ok(())
+    })
+}
+
+/// Replaces the key slot, unlocked by `key`, by the slot for `new_key`,
+/// see [`try_rekey_with`]
+pub fn try_rekey(
+    file_path: &Path,
+    key: &str,
+    new_key: &str,
+    kdf: &KdfParams,
+) -> error::Result<()> {
+    try_rekey_with(
+        file_path,
+        &[Identity::Password(key.to_string())],
+        &Recipient::Password(new_key.to_string()),
+        kdf,
+    )
+}
+
+/// Replaces the key slot, unlocked by any of the identities,
+/// by the slot for the recipient
+pub fn try_rekey_with(
+    file_path: &Path,
+    identities: &[Identity],
+    recipient: &Recipient,
+    kdf: &KdfParams,
+) -> error::Result<()> {
+    update_key_slots(file_path, identities, |header| {
+        let slot = header.new_slot(recipient, kdf)?;
+        header.slots.slots[header.unlocked_index] = slot;
+        Ok(())
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::ErrorKind;
+    use assert_fs::prelude::*;
     use hex_literal::hex;
+    use std::{fs, io};
 
     #[test]
     fn get_hash() {
@@ -200,4 +1206,37 @@ mod tests {
             hex!("66afe59af310865bc544c9d7a19ded0b1f8e6a1e797c3a1215a33175cae4023c")
         );
     }
+
+    #[test]
+    fn rename_new_without_hard_links() -> crate::error::Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let from = temp.child("from");
+        let to = temp.child("to");
+        from.write_str("content")?;
+        let unsupported = |_: &_, _: &_| Err(io::ErrorKind::Unsupported.into());
+
+        crate::rename_new_with(from.path(), to.path(), unsupported)?;
+        assert!(!from.path().exists());
+        assert_eq!(
+            fs::read_to_string(to.path())?,
+            "content"
+        );
+
+        // The existing file is not replaced
+        from.write_str("other")?;
+        let err = crate::rename_new_with(from.path(), to.path(), unsupported)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::FileAlreadyExist);
+        assert_eq!(
+            fs::read_to_string(from.path())?,
+            "other"
+        );
+        assert_eq!(
+            fs::read_to_string(to.path())?,
+            "content"
+        );
+
+        Ok(())
+    }
 }
@@ -1,16 +1,26 @@
 use file_encryptor::app::context::{
-    get_context_preview, user_key_hash, AppContext,
+    absolutize_key_paths, get_context_archive_options,
+    get_context_bulk_options, get_context_decryption_options,
+    get_context_encryption_options, get_context_identities,
+    get_context_recipients, is_context_key_required, user_key, AppContext,
+    KEY_ATTEMPTS,
 };
 use file_encryptor::cli::args::get_arguments;
 use file_encryptor::cli::runtime::command::register_all_commands;
 use file_encryptor::cli::runtime::CommandProcessorContext;
-use file_encryptor::encryption::{try_detect_file_type, DetectedFileType};
-use file_encryptor::{error, get_hash, try_decrypt, try_encrypt};
+use file_encryptor::encryption::{
+    try_detect_file_type, try_inspect, DetectedFileType,
+};
+use file_encryptor::file::os_str_to_bytes;
+use file_encryptor::{
+    error, try_decrypt_with_options, try_encrypt_to, try_extract,
+    try_list_archive, try_pack, try_recover_with, try_verify_with,
+};
 use path_absolutize::*;
 use rpassword::prompt_password;
 use std::fs::remove_file;
 use std::path::Path;
-use std::{env, io};
+use std::{env, io, process};
 
 /// Log level is being controlled by the ENV variable RUST_LOG
 ///
@@ -47,18 +57,28 @@ fn cli_mode(
     Ok(())
 }
 
-fn main() -> error::Result<()> {
+fn main() {
+    if let Err(e) = run() {
+        // The message with the location of the decryption error,
+        // not the Debug representation
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+}
+
+fn run() -> error::Result<()> {
     init_logger();
     let mut cmd_context: CommandProcessorContext<AppContext> =
         CommandProcessorContext::new();
     let term = console::Term::stdout();
-    let data = get_arguments(env::args_os());
+    let mut data = get_arguments(env::args_os());
+    absolutize_key_paths(&mut data)?;
     let mut ctx = AppContext {
         cli_current_path: Path::new(&data.filepath)
             .absolutize()?
             .to_path_buf(),
         cli_exit: false,
-        key_hash: None,
+        key: None,
         data,
         term,
     };
@@ -70,11 +90,14 @@ fn main() -> error::Result<()> {
     .map_err(|e| error::Error::new(error::ErrorKind::OtherError, e))?;
     log::info!(target: "app_main", "Use `exit` command to exit, `help` to get help\n");
 
-    if ctx.data.key.is_some() {
-        ctx.key_hash = Some(get_hash(
-            ctx.data.key.unwrap().as_str(),
-        )?);
-        ctx.data.key = None;
+    ctx.key = ctx.data.key.take();
+
+    // The chunks of the file are encrypted by the global pool
+    if let Some(jobs) = ctx.data.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .map_err(|e| error::Error::new(error::ErrorKind::OtherError, e))?;
     }
 
     if ctx.data.cli.unwrap_or(false) {
@@ -91,40 +114,142 @@ fn main() -> error::Result<()> {
     }
     log::debug!(target: "app_main", "File exists, ok");
 
+    // The directory is packed into the archive and kept
+    if file_path.is_dir() {
+        println!("Directory will be packed");
+        if is_context_key_required(&ctx, &ctx.data.recipients) {
+            ctx.key = Some(user_key()?);
+            log::debug!(target: "app_main", "Key entered");
+        }
+
+        let report = try_pack(
+            file_path.as_ref(),
+            None,
+            &get_context_recipients(&ctx)?,
+            &get_context_encryption_options(&ctx),
+            &get_context_bulk_options(&ctx, None),
+        )?;
+        print!("{report}");
+        return Ok(());
+    }
+
     let mut preview: bool = false;
     match try_detect_file_type(file_path.as_ref())? {
         DetectedFileType::Raw => {
             println!("Raw file will be encrypted");
-            log::debug!(target: "app_main", "Key entered");
-            if ctx.key_hash.is_none() {
-                ctx.key_hash = Some(user_key_hash()?);
+            if is_context_key_required(&ctx, &ctx.data.recipients) {
+                ctx.key = Some(user_key()?);
+                log::debug!(target: "app_main", "Key entered");
             }
 
             // to encrypt
-            try_encrypt(
+            try_encrypt_to(
                 file_path.as_ref(),
                 None,
-                ctx.key_hash.unwrap(),
+                &get_context_recipients(&ctx)?,
+                &get_context_encryption_options(&ctx),
             )?;
         }
+        DetectedFileType::Encrypted if ctx.data.inspect => {
+            print!("{}", try_inspect(file_path.as_ref())?);
+            return Ok(());
+        }
         DetectedFileType::Encrypted => {
-            preview = get_context_preview(&ctx)?;
-            log::debug!(target: "app_main","Preview arg is: {:?}", preview);
+            let prompt = is_context_key_required(&ctx, &ctx.data.identities);
+            if ctx.data.recover {
+                if prompt {
+                    ctx.key = Some(prompt_password("Enter the key: ")?);
+                }
 
-            let key = match &ctx.data.key {
-                Some(key) => key.clone(),
-                None => prompt_password("Enter the key: ")?,
-            };
+                println!("Encrypted file will be recovered");
+                let report = try_recover_with(
+                    file_path.as_ref(),
+                    &get_context_identities(&ctx)?,
+                    !ctx.data.skip_lost,
+                )?;
+                print!("{report}");
+                return Ok(());
+            }
+            if ctx.data.list {
+                if prompt {
+                    ctx.key = Some(prompt_password("Enter the key: ")?);
+                }
 
-            let hash_from_key = get_hash(&key)?;
-            log::debug!(target: "app_main", "Key entered");
+                let index = try_list_archive(
+                    file_path.as_ref(),
+                    &get_context_identities(&ctx)?,
+                )?;
+                print!("{index}");
+                return Ok(());
+            }
+            if let Some(entry_path) = ctx.data.extract.clone() {
+                let entry_path =
+                    os_str_to_bytes(&entry_path).ok_or_else(|| {
+                        error::Error::new_const(
+                            error::ErrorKind::InvalidArgument,
+                            &"Entry path is not valid Unicode",
+                        )
+                    })?;
+                if prompt {
+                    ctx.key = Some(prompt_password("Enter the key: ")?);
+                }
+
+                let extracted = try_extract(
+                    file_path.as_ref(),
+                    &entry_path,
+                    None,
+                    &get_context_identities(&ctx)?,
+                    &get_context_archive_options(&ctx),
+                )?;
+                println!("Extracted into {extracted:?}");
+                return Ok(());
+            }
+            if ctx.data.verify {
+                if prompt {
+                    ctx.key = Some(prompt_password("Enter the key: ")?);
+                }
+
+                let enc_meta = try_verify_with(
+                    file_path.as_ref(),
+                    &get_context_identities(&ctx)?,
+                )?;
+                print!("Verified successfully\n{enc_meta}");
+                return Ok(());
+            }
+
+            let options = get_context_decryption_options(&ctx)?;
+            preview = options.preview;
+            log::debug!(target: "app_main","Preview arg is: {:?}", preview);
 
             println!("Encrypted file will be decrypted");
-            try_decrypt(
-                file_path.as_ref(),
-                hash_from_key,
-                preview,
-            )?;
+            for attempt in 1..=KEY_ATTEMPTS {
+                if prompt {
+                    ctx.key = Some(prompt_password("Enter the key: ")?);
+                    log::debug!(target: "app_main", "Key entered");
+                }
+
+                match try_decrypt_with_options(
+                    file_path.as_ref(),
+                    &get_context_identities(&ctx)?,
+                    &options,
+                ) {
+                    Err(e)
+                        if prompt
+                            && attempt < KEY_ATTEMPTS
+                            && matches!(
+                                e.kind(),
+                                error::ErrorKind::WrongPassword
+                                    | error::ErrorKind::WrongPasswordOrCorrupted
+                            ) =>
+                    {
+                        println!("Wrong key, try again");
+                    }
+                    result => {
+                        result?;
+                        break;
+                    }
+                }
+            }
         }
     };
 
@@ -2,7 +2,13 @@
 mod tests;
 
 use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use core::fmt;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::io::{Read, Write};
 use std::str::from_utf8;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
 
 const ENC_META_MIN_SIZE: usize = 1;
 
@@ -11,72 +17,668 @@ const ENC_META_MIN_SIZE: usize = 1;
 ///
 /// # Binary structure
 ///
-/// No static sized fields stored.
-/// May contain zero bytes after strings (for an alignment)
+/// Since version 5 it is a sequence of the type-length-value records:
 ///
-/// Strings is being stored like in ELF files :)
-/// Number of strings: 1.
+/// | Offset | Size   | Field              |
+/// |--------|--------|--------------------|
+/// | 0x00   | 2      | Record type, LE    |
+/// | 0x02   | 4      | Value length, LE   |
+/// | 0x06   | length | Value              |
+///
+/// The highest bit of the type marks the critical record:
+/// the meta must not be parsed, if such record type is unknown.
+/// Unknown non-critical records are skipped, so new fields are added
+/// without a format break. Record types:
+///
+/// - `0x0001` (critical): the original filename, UTF-8.
+///   Only read, superseded by the `0x0005` record
+/// - `0x0002`: the [`ContentDigest`], 8 bytes of size (LE) and SHA-256
+/// - `0x0003`: the [`FileAttributes`], see below
+/// - `0x0004`: the [`ExtendedAttribute`], the name length (u8),
+///   the name and the value. Repeated for every attribute
+/// - `0x0005` (critical): the original filename, the flags (u8)
+///   and the raw bytes of the `OsStr`. The flag `0x01` marks
+///   the valid UTF-8
+/// - `0x0006` (critical): empty, the content is the archive of the files
+///   with the [`crate::meta::index::ArchiveIndex`] in front
+///
+/// The file attributes record:
+///
+/// | Offset | Size | Field                  |
+/// |--------|------|------------------------|
+/// | 0x00   | 4    | Attribute flags, LE    |
+/// | 0x04   | 4    | Unix mode, LE          |
+/// | 0x08   | 4    | Unix owner user ID, LE |
+/// | 0x0C   | 4    | Unix owner group ID, LE|
+/// | 0x10   | 12   | Modification time      |
+/// | 0x1C   | 12   | Access time            |
+///
+/// The time is seconds since the Unix epoch (i64, LE),
+/// followed by nanoseconds (u32, LE).
+///
+/// ## Legacy versions
+///
+/// Version 1 is the filename string only, terminated by zero.
+/// May contain zero bytes after it (for an alignment).
+/// Version 2 prepends the content digest, version 3 appends the file
+/// attributes to it, and version 4 appends the extended attributes:
+/// their number (u16, LE) and for each of them the name length (u8),
+/// the value length (u32, LE), the name and the value.
 ///
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct EncryptedMeta {
-    /// Original filename
-    pub filename: String,
+    /// Original filename, the raw bytes of the `OsStr`.
+    /// Not necessarily UTF-8, see [`EncryptedMeta::filename_lossy`]
+    pub filename: Vec<u8>,
+
+    /// Original content size and digest. Since version 2
+    pub content: Option<ContentDigest>,
+
+    /// Since version 3, written together with the content digest
+    pub attributes: FileAttributes,
+
+    /// Since version 4, written only if there are any
+    pub xattrs: Vec<ExtendedAttribute>,
+
+    /// The content is the archive of the files, see [`crate::archive`].
+    /// Such content has no digest, every entry has its own one
+    pub is_archive: bool,
+}
+
+/// Extended attribute of the original file, including the POSIX ACLs
+/// (`system.posix_acl_access`) and the SELinux labels (`security.selinux`)
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExtendedAttribute {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl ExtendedAttribute {
+    /// The record value: the name length, the name and the value
+    fn to_vec(&self) -> Vec<u8> {
+        [[self.name.len() as u8].as_slice(), &self.name, &self.value].concat()
+    }
+
+    fn try_from_bytes(value: &[u8]) -> Result<Self, MetaError> {
+        let mut offset = 0usize;
+        let name_len = take(value, &mut offset, 1)?[0] as usize;
+        let name = take(value, &mut offset, name_len)?.to_vec();
+        Ok(Self {
+            name,
+            value: value[offset..].to_vec(),
+        })
+    }
+}
+
+/// End-to-end check of the decrypted content,
+/// independent of the chunk authentication
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ContentDigest {
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+impl ContentDigest {
+    pub const SIZE: usize = 8 + 32;
+
+    pub fn from_reader(source: &mut dyn Read) -> io::Result<Self> {
+        let mut hasher = ContentHasher::default();
+        io::copy(source, &mut hasher)?;
+        Ok(hasher.finish())
+    }
+
+    pub(super) fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut result = [0u8; Self::SIZE];
+        result[..8].copy_from_slice(&self.size.to_le_bytes());
+        result[8..].copy_from_slice(&self.sha256);
+        result
+    }
+
+    pub(super) fn try_from_bytes(value: &[u8]) -> Result<Self, MetaError> {
+        if value.len() != Self::SIZE {
+            return Err(MetaErrorKind::WrongEncryptedVecSize.into());
+        }
+        Ok(Self {
+            size: u64::from_le_bytes(
+                value[..8]
+                    .try_into()
+                    .expect("Wrong slice size"),
+            ),
+            sha256: value[8..]
+                .try_into()
+                .expect("Wrong slice size"),
+        })
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        self.sha256
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+impl fmt::Display for ContentDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes, SHA-256 {}",
+            self.size,
+            self.sha256_hex()
+        )
+    }
+}
+
+/// Original file attributes, restored on the decryption if possible.
+/// The unknown ones are `None`
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FileAttributes {
+    /// Unix permission bits
+    pub mode: Option<u32>,
+
+    /// Unix owner user and group IDs
+    pub owner: Option<(u32, u32)>,
+
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+}
+
+impl FileAttributes {
+    pub const SIZE: usize = 4 * 4 + 2 * 12;
+
+    const FLAG_MODE: u32 = 0x01;
+    const FLAG_OWNER: u32 = 0x02;
+    const FLAG_MODIFIED: u32 = 0x04;
+    const FLAG_ACCESSED: u32 = 0x08;
+    const FLAGS: u32 = 0x0F;
+
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let (mode, owner) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(metadata.mode() & 0o7777),
+                Some((metadata.uid(), metadata.gid())),
+            )
+        };
+        #[cfg(not(unix))]
+        let (mode, owner) = (None, None);
+
+        Self {
+            mode,
+            owner,
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(super) fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut result = [0u8; Self::SIZE];
+        let mut flags = 0u32;
+        if let Some(mode) = self.mode {
+            flags |= Self::FLAG_MODE;
+            result[0x04..0x08].copy_from_slice(&mode.to_le_bytes());
+        }
+        if let Some((uid, gid)) = self.owner {
+            flags |= Self::FLAG_OWNER;
+            result[0x08..0x0C].copy_from_slice(&uid.to_le_bytes());
+            result[0x0C..0x10].copy_from_slice(&gid.to_le_bytes());
+        }
+        if let Some(modified) = self.modified {
+            flags |= Self::FLAG_MODIFIED;
+            result[0x10..0x1C].copy_from_slice(&time_to_bytes(modified));
+        }
+        if let Some(accessed) = self.accessed {
+            flags |= Self::FLAG_ACCESSED;
+            result[0x1C..0x28].copy_from_slice(&time_to_bytes(accessed));
+        }
+        result[0x00..0x04].copy_from_slice(&flags.to_le_bytes());
+        result
+    }
+
+    pub(super) fn try_from_bytes(value: &[u8]) -> Result<Self, MetaError> {
+        if value.len() != Self::SIZE {
+            return Err(MetaErrorKind::WrongEncryptedAttributes.into());
+        }
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(
+                value[offset..offset + 4]
+                    .try_into()
+                    .expect("Wrong slice size"),
+            )
+        };
+        let flags = u32_at(0x00);
+        if flags & !Self::FLAGS != 0 {
+            return Err(MetaErrorKind::WrongEncryptedAttributes.into());
+        }
+
+        Ok(Self {
+            mode: (flags & Self::FLAG_MODE != 0).then(|| u32_at(0x04)),
+            owner: (flags & Self::FLAG_OWNER != 0)
+                .then(|| (u32_at(0x08), u32_at(0x0C))),
+            modified: (flags & Self::FLAG_MODIFIED != 0)
+                .then(|| time_from_bytes(&value[0x10..0x1C]))
+                .transpose()?,
+            accessed: (flags & Self::FLAG_ACCESSED != 0)
+                .then(|| time_from_bytes(&value[0x1C..0x28]))
+                .transpose()?,
+        })
+    }
+}
+
+fn time_to_bytes(time: SystemTime) -> [u8; 12] {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (
+            duration.as_secs() as i64,
+            duration.subsec_nanos(),
+        ),
+        // Before the epoch, the nanoseconds are still positive
+        Err(e) => {
+            let duration = e.duration();
+            match duration.subsec_nanos() {
+                0 => (-(duration.as_secs() as i64), 0),
+                nanos => (
+                    -(duration.as_secs() as i64) - 1,
+                    1_000_000_000 - nanos,
+                ),
+            }
+        }
+    };
+
+    let mut result = [0u8; 12];
+    result[..8].copy_from_slice(&secs.to_le_bytes());
+    result[8..].copy_from_slice(&nanos.to_le_bytes());
+    result
+}
+
+fn time_from_bytes(value: &[u8]) -> Result<SystemTime, MetaError> {
+    let secs = i64::from_le_bytes(
+        value[..8]
+            .try_into()
+            .expect("Wrong slice size"),
+    );
+    let nanos = u32::from_le_bytes(
+        value[8..12]
+            .try_into()
+            .expect("Wrong slice size"),
+    );
+    if nanos >= 1_000_000_000 {
+        return Err(MetaErrorKind::WrongEncryptedAttributes.into());
+    }
+
+    let time = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+            .and_then(|time| {
+                time.checked_add(Duration::from_nanos(nanos as u64))
+            })
+    };
+    time.ok_or_else(|| MetaErrorKind::WrongEncryptedAttributes.into())
+}
+
+/// Computes the [`ContentDigest`] of the written bytes
+#[derive(Clone, Debug, Default)]
+pub struct ContentHasher {
+    size: u64,
+    hasher: Sha256,
+}
+
+impl ContentHasher {
+    pub fn finish(self) -> ContentDigest {
+        ContentDigest {
+            size: self.size,
+            sha256: self.hasher.finalize().into(),
+        }
+    }
+}
+
+impl Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl EncryptedMeta {
+    /// Limits the memory, allocated by the decryptor
+    pub const MAX_SIZE: u64 = 16 * 1024 * 1024;
+
     pub const fn version() -> u8 {
-        1
+        5
+    }
+
+    pub const fn is_version_supported(version: u8) -> bool {
+        version >= 1 && version <= 5
     }
 
     pub fn len(&self) -> usize {
-        self.filename.len() + 1
+        self.to_vec().len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.filename.len() == 0
     }
 
+    /// Whether the filename is valid UTF-8, so it is restored on any platform
+    pub fn is_filename_utf8(&self) -> bool {
+        from_utf8(&self.filename).is_ok()
+    }
+
+    /// Filename for the output, the invalid UTF-8 is replaced
+    pub fn filename_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.filename)
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
-        let result = Vec::<u8>::with_capacity(self.len())
-            .into_iter()
-            .chain(self.filename.bytes())
-            .chain([0u8])
-            .collect::<Vec<u8>>();
+        let mut result = Vec::with_capacity(128);
+        let flags = if self.is_filename_utf8() {
+            FILENAME_FLAG_UTF8
+        } else {
+            0
+        };
+        write_record(
+            &mut result,
+            TLV_RAW_FILENAME | TLV_CRITICAL,
+            &[[flags].as_slice(), &self.filename].concat(),
+        );
+        if let Some(content) = &self.content {
+            write_record(
+                &mut result,
+                TLV_CONTENT_DIGEST,
+                &content.to_bytes(),
+            );
+        }
+        if !self.attributes.is_empty() {
+            write_record(
+                &mut result,
+                TLV_ATTRIBUTES,
+                &self.attributes.to_bytes(),
+            );
+        }
+        for xattr in &self.xattrs {
+            write_record(&mut result, TLV_XATTR, &xattr.to_vec());
+        }
+        if self.is_archive {
+            write_record(
+                &mut result,
+                TLV_ARCHIVE | TLV_CRITICAL,
+                &[],
+            );
+        }
 
         log::trace!(target: "meta/enc RawMeta to_vec", "Result: {result:?}");
         result
     }
 
-    pub fn try_from_bytes(value: &[u8]) -> Result<Self, MetaError> {
+    pub fn try_from_bytes(
+        version: u8,
+        value: &[u8],
+    ) -> Result<Self, MetaError> {
         log::debug!(target: "meta/enc RawMeta try_from_bytes", "value.len(): {:?}",  value.len());
 
-        if value.len() < ENC_META_MIN_SIZE {
+        match version {
+            5 => Self::try_from_records(value),
+            _ if Self::is_version_supported(version) => {
+                Self::try_from_legacy_bytes(version, value)
+            }
+            _ => Err(MetaErrorKind::WrongEncryptedVersion.into()),
+        }
+    }
+
+    fn try_from_records(value: &[u8]) -> Result<Self, MetaError> {
+        let mut filename = None;
+        let mut content = None;
+        let mut attributes = None;
+        let mut xattrs = Vec::new();
+        let mut is_archive = false;
+
+        let mut offset = 0usize;
+        while offset < value.len() {
+            let (record_type, data) = read_record(value, &mut offset)?;
+            log::trace!(target: "meta/enc RawMeta try_from_records", "Record {record_type:#06x}: {data:?}");
+
+            match record_type & !TLV_CRITICAL {
+                TLV_FILENAME if filename.is_none() => {
+                    filename = Some(from_utf8(data)?.as_bytes().to_vec());
+                }
+                TLV_RAW_FILENAME if filename.is_none() => {
+                    filename = Some(raw_filename_from_bytes(data)?);
+                }
+                TLV_CONTENT_DIGEST if content.is_none() => {
+                    content = Some(ContentDigest::try_from_bytes(data)?);
+                }
+                TLV_ATTRIBUTES if attributes.is_none() => {
+                    attributes = Some(FileAttributes::try_from_bytes(data)?);
+                }
+                TLV_XATTR => {
+                    xattrs.push(ExtendedAttribute::try_from_bytes(data)?);
+                }
+                TLV_ARCHIVE if !is_archive && data.is_empty() => {
+                    is_archive = true;
+                }
+                TLV_FILENAME | TLV_RAW_FILENAME | TLV_CONTENT_DIGEST
+                | TLV_ATTRIBUTES | TLV_ARCHIVE => {
+                    return Err(MetaErrorKind::WrongEncryptedRecord.into());
+                }
+                _ if record_type & TLV_CRITICAL != 0 => {
+                    return Err(
+                        MetaErrorKind::WrongEncryptedCriticalRecord.into(),
+                    );
+                }
+                _ => {
+                    log::debug!(target: "meta/enc RawMeta try_from_records", "Unknown record {record_type:#06x} is skipped");
+                }
+            }
+        }
+
+        Ok(Self {
+            filename: filename
+                .ok_or(MetaErrorKind::WrongEncryptedWrongStringsAmount)?,
+            content,
+            attributes: attributes.unwrap_or_default(),
+            xattrs,
+            is_archive,
+        })
+    }
+
+    /// Size of the static sized structure body of the legacy versions
+    const fn legacy_body_size(version: u8) -> usize {
+        match version {
+            1 => 0,
+            2 => ContentDigest::SIZE,
+            _ => ContentDigest::SIZE + FileAttributes::SIZE,
+        }
+    }
+
+    /// Returns the attributes and their size
+    fn legacy_xattrs_from_bytes(
+        value: &[u8],
+    ) -> Result<(Vec<ExtendedAttribute>, usize), MetaError> {
+        let mut offset = 0usize;
+        let count = u16::from_le_bytes(
+            take(value, &mut offset, 2)?
+                .try_into()
+                .expect("Wrong slice size"),
+        );
+        let mut result = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_len = take(value, &mut offset, 1)?[0] as usize;
+            let value_len = u32::from_le_bytes(
+                take(value, &mut offset, 4)?
+                    .try_into()
+                    .expect("Wrong slice size"),
+            ) as usize;
+            let name = take(value, &mut offset, name_len)?.to_vec();
+            let value = take(value, &mut offset, value_len)?.to_vec();
+            result.push(ExtendedAttribute { name, value });
+        }
+        Ok((result, offset))
+    }
+
+    fn try_from_legacy_bytes(
+        version: u8,
+        value: &[u8],
+    ) -> Result<Self, MetaError> {
+        let body_size = Self::legacy_body_size(version);
+        if value.len() < body_size + ENC_META_MIN_SIZE {
             return Err(MetaErrorKind::WrongEncryptedVecSize.into());
         }
         if value[value.len() - 1] != 0x00 {
             return Err(MetaErrorKind::WrongEncryptedWrongStringsAmount.into());
         }
 
+        let content = if version >= 2 {
+            Some(ContentDigest::try_from_bytes(
+                &value[..ContentDigest::SIZE],
+            )?)
+        } else {
+            None
+        };
+        let attributes = if version >= 3 {
+            FileAttributes::try_from_bytes(
+                &value[ContentDigest::SIZE..body_size],
+            )?
+        } else {
+            FileAttributes::default()
+        };
+        let (xattrs, xattrs_len) = if version >= 4 {
+            Self::legacy_xattrs_from_bytes(&value[body_size..])?
+        } else {
+            (Vec::new(), 0)
+        };
+        let body_size = body_size + xattrs_len;
+        if value.len() < body_size + ENC_META_MIN_SIZE {
+            return Err(MetaErrorKind::WrongEncryptedVecSize.into());
+        }
+
         let filename_bytes = value
             .iter()
             .skip(
-                0, /* structure body before strings */
+                body_size, /* structure body before strings */
             )
             .map_while(|c| if *c != 0x00 { Some(*c) } else { None })
             .collect::<Vec<u8>>();
         log::trace!(target: "meta/enc RawMeta try_from_bytes", "Filename: {filename_bytes:?}");
 
         Ok(Self {
-            filename: from_utf8(filename_bytes.as_slice())?.to_string(),
+            filename: from_utf8(filename_bytes.as_slice())?
+                .as_bytes()
+                .to_vec(),
+            content,
+            attributes,
+            xattrs,
+            is_archive: false,
         })
     }
 }
 
+/// TLV record types, see [`EncryptedMeta`]
+const TLV_FILENAME: u16 = 0x0001;
+const TLV_CONTENT_DIGEST: u16 = 0x0002;
+const TLV_ATTRIBUTES: u16 = 0x0003;
+const TLV_XATTR: u16 = 0x0004;
+const TLV_RAW_FILENAME: u16 = 0x0005;
+const TLV_ARCHIVE: u16 = 0x0006;
+
+/// The record must be known to parse the meta
+pub(super) const TLV_CRITICAL: u16 = 0x8000;
+
+const TLV_HEADER_SIZE: usize = 2 + 4;
+
+/// The raw filename is valid UTF-8
+const FILENAME_FLAG_UTF8: u8 = 0x01;
+
+/// Parses the raw filename record: the flags and the bytes
+fn raw_filename_from_bytes(value: &[u8]) -> Result<Vec<u8>, MetaError> {
+    let (flags, filename) = value
+        .split_first()
+        .ok_or(MetaErrorKind::WrongEncryptedRecord)?;
+    if flags & !FILENAME_FLAG_UTF8 != 0 {
+        return Err(MetaErrorKind::WrongEncryptedRecord.into());
+    }
+    if flags & FILENAME_FLAG_UTF8 != 0 {
+        from_utf8(filename)?;
+    }
+    Ok(filename.to_vec())
+}
+
+pub(super) fn write_record(
+    target: &mut Vec<u8>,
+    record_type: u16,
+    value: &[u8],
+) {
+    target.extend_from_slice(&record_type.to_le_bytes());
+    target.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    target.extend_from_slice(value);
+}
+
+/// Returns the record type and value
+pub(super) fn read_record<'a>(
+    value: &'a [u8],
+    offset: &mut usize,
+) -> Result<(u16, &'a [u8]), MetaError> {
+    let header = take(value, offset, TLV_HEADER_SIZE)
+        .map_err(|_| MetaError::from(MetaErrorKind::WrongEncryptedRecord))?;
+    let record_type = u16::from_le_bytes([header[0], header[1]]);
+    let len = u32::from_le_bytes(
+        header[2..6]
+            .try_into()
+            .expect("Wrong slice size"),
+    ) as usize;
+    let data = take(value, offset, len)
+        .map_err(|_| MetaError::from(MetaErrorKind::WrongEncryptedRecord))?;
+    Ok((record_type, data))
+}
+
+/// Returns `len` bytes at the offset and moves the offset
+pub(super) fn take<'a>(
+    value: &'a [u8],
+    offset: &mut usize,
+    len: usize,
+) -> Result<&'a [u8], MetaError> {
+    let bytes = offset
+        .checked_add(len)
+        .and_then(|end| value.get(*offset..end))
+        .ok_or(MetaErrorKind::WrongEncryptedAttributes)?;
+    *offset += len;
+    Ok(bytes)
+}
+
+impl fmt::Display for EncryptedMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "File name: {}",
+            self.filename_lossy()
+        )?;
+        match &self.content {
+            Some(content) => writeln!(f, "Content: {content}"),
+            None if self.is_archive => writeln!(
+                f,
+                "Content: archive, every entry has its digest"
+            ),
+            None => writeln!(f, "Content: no digest (version 1)"),
+        }
+    }
+}
+
+/// Parses the legacy version 1 structure
 impl TryFrom<&Vec<u8>> for EncryptedMeta {
     type Error = MetaError;
 
     fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
-        Self::try_from_bytes(value)
+        Self::try_from_bytes(1, value)
     }
 }
 
@@ -84,6 +686,6 @@ impl TryFrom<Vec<u8>> for EncryptedMeta {
     type Error = MetaError;
 
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        Self::try_from_bytes(&value)
+        Self::try_from_bytes(1, &value)
     }
 }
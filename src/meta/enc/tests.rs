@@ -1,8 +1,12 @@
 use crate::error;
-use crate::meta::enc::EncryptedMeta;
+use crate::meta::enc::{
+    ContentDigest, EncryptedMeta, ExtendedAttribute, FileAttributes,
+};
 use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use hex_literal::hex;
+use std::time::{Duration, UNIX_EPOCH};
 
-const FILENAME: &'static str = "the filename";
+const FILENAME: &str = "the filename";
 
 fn fixtures() -> (Vec<u8>, EncryptedMeta) {
     (
@@ -12,7 +16,11 @@ fn fixtures() -> (Vec<u8>, EncryptedMeta) {
             .chain([0x00; 4])
             .collect(),
         EncryptedMeta {
-            filename: FILENAME.try_into().unwrap(),
+            filename: FILENAME.into(),
+            content: None,
+            attributes: FileAttributes::default(),
+            xattrs: Vec::new(),
+            is_archive: false,
         },
     )
 }
@@ -58,3 +66,368 @@ fn test_from_vec() -> error::Result<()> {
 
     Ok(())
 }
+
+fn fixtures_v2() -> (Vec<u8>, EncryptedMeta) {
+    let sha256 = [0xAB; 32];
+    (
+        Vec::<u8>::new()
+            .into_iter()
+            .chain(1234u64.to_le_bytes())
+            .chain(sha256)
+            .chain(FILENAME.bytes())
+            .chain([0x00])
+            .collect(),
+        EncryptedMeta {
+            filename: FILENAME.into(),
+            content: Some(ContentDigest { size: 1234, sha256 }),
+            attributes: FileAttributes::default(),
+            xattrs: Vec::new(),
+            is_archive: false,
+        },
+    )
+}
+
+#[test]
+fn test_from_bytes_v2() -> error::Result<()> {
+    let (vector, meta) = fixtures_v2();
+
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(2, &vector)?,
+        meta
+    );
+
+    Ok(())
+}
+
+/// The meta of the legacy versions 3 and 4
+fn legacy_bytes(meta: &EncryptedMeta, version: u8) -> Vec<u8> {
+    let mut result = meta
+        .content
+        .unwrap()
+        .to_bytes()
+        .to_vec();
+    result.extend_from_slice(&meta.attributes.to_bytes());
+    if version >= 4 {
+        result.extend_from_slice(&(meta.xattrs.len() as u16).to_le_bytes());
+        for xattr in &meta.xattrs {
+            result.push(xattr.name.len() as u8);
+            result.extend_from_slice(&(xattr.value.len() as u32).to_le_bytes());
+            result.extend_from_slice(&xattr.name);
+            result.extend_from_slice(&xattr.value);
+        }
+    }
+    result.extend(FILENAME.bytes());
+    result.push(0x00);
+    result
+}
+
+fn attributes_fixture() -> FileAttributes {
+    FileAttributes {
+        mode: Some(0o640),
+        owner: Some((1000, 100)),
+        modified: Some(UNIX_EPOCH + Duration::new(1_600_000_000, 123)),
+        // Before the epoch
+        accessed: Some(UNIX_EPOCH - Duration::new(10, 5)),
+    }
+}
+
+fn xattrs_fixture() -> Vec<ExtendedAttribute> {
+    vec![
+        ExtendedAttribute {
+            name: b"user.tag".to_vec(),
+            value: b"blue".to_vec(),
+        },
+        ExtendedAttribute {
+            name: b"user.empty".to_vec(),
+            value: vec![],
+        },
+    ]
+}
+
+#[test]
+fn test_from_bytes_v3() -> error::Result<()> {
+    let (_, mut meta) = fixtures_v2();
+    meta.attributes = attributes_fixture();
+
+    let vector = legacy_bytes(&meta, 3);
+    assert_eq!(
+        &vector[0x28..0x38],
+        [0x0F, 0, 0, 0, 0xA0, 0x01, 0, 0, 0xE8, 0x03, 0, 0, 100, 0, 0, 0]
+    );
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(3, &vector)?,
+        meta
+    );
+
+    // The unknown attributes are omitted
+    meta.attributes = FileAttributes::default();
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(3, &legacy_bytes(&meta, 3))?,
+        meta
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_attributes() -> error::Result<()> {
+    let (_, meta) = fixtures_v2();
+
+    let mut vector = legacy_bytes(&meta, 3);
+    vector[0x28] = 0x10;
+    let result = EncryptedMeta::try_from_bytes(3, &vector);
+    assert_eq!(
+        result.err().unwrap().repr,
+        MetaErrorKind::WrongEncryptedAttributes
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_v4() -> error::Result<()> {
+    let (_, mut meta) = fixtures_v2();
+    meta.xattrs = xattrs_fixture();
+
+    let vector = legacy_bytes(&meta, 4);
+    assert_eq!(
+        &vector[0x50..0x57],
+        [2, 0, 8, 4, 0, 0, 0]
+    );
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(4, &vector)?,
+        meta
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_xattrs() -> error::Result<()> {
+    let (_, mut meta) = fixtures_v2();
+    meta.xattrs = xattrs_fixture();
+
+    // The value is longer than the rest of the meta
+    let mut vector = legacy_bytes(&meta, 4);
+    vector[0x53] = 0xFF;
+    let result = EncryptedMeta::try_from_bytes(4, &vector);
+    assert_eq!(
+        result.err().unwrap().repr,
+        MetaErrorKind::WrongEncryptedAttributes
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_to_vec() -> error::Result<()> {
+    let (_, mut meta) = fixtures();
+
+    let vector = meta.to_vec();
+    assert_eq!(
+        &vector[..7],
+        [0x05, 0x80, 13, 0, 0, 0, 0x01]
+    );
+    assert_eq!(&vector[7..], FILENAME.as_bytes());
+    assert_eq!(meta.len(), vector.len());
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)?,
+        meta
+    );
+
+    meta.content = fixtures_v2().1.content;
+    meta.attributes = attributes_fixture();
+    meta.xattrs = xattrs_fixture();
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &meta.to_vec())?,
+        meta
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_records_unknown() -> error::Result<()> {
+    let (_, meta) = fixtures_v2();
+
+    // Non-critical record of the unknown type is skipped
+    let mut vector = meta.to_vec();
+    vector.extend_from_slice(&[0x34, 0x12, 3, 0, 0, 0, 1, 2, 3]);
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)?,
+        meta
+    );
+
+    // Critical one is not
+    let mut vector = meta.to_vec();
+    vector.extend_from_slice(&[0x34, 0x92, 3, 0, 0, 0, 1, 2, 3]);
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongEncryptedCriticalRecord
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_records_err() -> error::Result<()> {
+    let (_, meta) = fixtures_v2();
+    let vector = meta.to_vec();
+    let parse_err = |vector: &[u8]| {
+        EncryptedMeta::try_from_bytes(5, vector)
+            .err()
+            .unwrap()
+            .repr
+    };
+
+    // Truncated record
+    assert_eq!(
+        parse_err(&vector[..vector.len() - 1]),
+        MetaErrorKind::WrongEncryptedRecord
+    );
+    assert_eq!(
+        parse_err(&[vector.as_slice(), &[0x01]].concat()),
+        MetaErrorKind::WrongEncryptedRecord
+    );
+    // Duplicate filename
+    assert_eq!(
+        parse_err(&[vector.as_slice(), &vector[..19]].concat()),
+        MetaErrorKind::WrongEncryptedRecord
+    );
+    // No filename
+    assert_eq!(
+        parse_err(&vector[19..]),
+        MetaErrorKind::WrongEncryptedWrongStringsAmount
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_records_filename() -> error::Result<()> {
+    let (_, mut meta) = fixtures();
+
+    // Latin-1, not UTF-8
+    meta.filename = b"caf\xe9.txt".to_vec();
+    assert!(!meta.is_filename_utf8());
+    assert_eq!(meta.filename_lossy(), "caf\u{FFFD}.txt");
+    let vector = meta.to_vec();
+    assert_eq!(vector[6], 0x00);
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)?,
+        meta
+    );
+
+    // The UTF-8 flag is checked
+    let mut wrong_flag = vector.clone();
+    wrong_flag[6] = 0x01;
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &wrong_flag)
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongEncryptedUtf8Error
+    );
+    let mut unknown_flag = vector;
+    unknown_flag[6] = 0x02;
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &unknown_flag)
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongEncryptedRecord
+    );
+
+    // The UTF-8 filename record is still read
+    let vector =
+        [[0x01, 0x80, 12, 0, 0, 0].as_slice(), FILENAME.as_bytes()].concat();
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)?,
+        fixtures().1
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_records_archive() -> error::Result<()> {
+    let (_, mut meta) = fixtures();
+    meta.is_archive = true;
+
+    let vector = meta.to_vec();
+    assert_eq!(
+        vector[vector.len() - 6..],
+        [0x06, 0x80, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        EncryptedMeta::try_from_bytes(5, &vector)?,
+        meta
+    );
+
+    // Duplicate or not empty
+    for record in [
+        [0x06, 0x80, 0, 0, 0, 0].as_slice(),
+        &[0x06, 0x80, 1, 0, 0, 0, 0x00],
+    ] {
+        let wrong = [
+            fixtures().1.to_vec().as_slice(),
+            &vector[vector.len() - 6..],
+            record,
+        ]
+        .concat();
+        assert_eq!(
+            EncryptedMeta::try_from_bytes(5, &wrong[..])
+                .err()
+                .unwrap()
+                .repr,
+            MetaErrorKind::WrongEncryptedRecord
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_v2_size() -> error::Result<()> {
+    let (vector, _) = fixtures_v2();
+
+    let result =
+        EncryptedMeta::try_from_bytes(2, &vector[..ContentDigest::SIZE]);
+    assert_eq!(
+        result.err().unwrap().repr,
+        MetaErrorKind::WrongEncryptedVecSize
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_version() -> error::Result<()> {
+    let (vector, _) = fixtures_v2();
+
+    let result = EncryptedMeta::try_from_bytes(0xFF, &vector);
+    assert_eq!(
+        result.err().unwrap().repr,
+        MetaErrorKind::WrongEncryptedVersion
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_content_digest() -> error::Result<()> {
+    let digest = ContentDigest::from_reader(&mut "abc".as_bytes())?;
+
+    assert_eq!(
+        digest,
+        ContentDigest {
+            size: 3,
+            sha256: hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        }
+    );
+
+    Ok(())
+}
@@ -17,9 +17,25 @@ pub enum ErrorKind {
     WrongHeaderVecSize,
     WrongRawVecSize,
     WrongRawCipherKind,
+    WrongRawKdfKind,
+    WrongKdfCosts,
+    WrongRawVersion,
+    WrongRawChunkSize,
+    WrongRawFlags,
+    WrongSlotsVersion,
+    WrongSlotsVecSize,
+    WrongSlotKind,
+    WrongSlotStanzaType,
+    WrongSlotKeyFactors,
+    WrongEncryptedVersion,
     WrongEncryptedVecSize,
     WrongEncryptedWrongStringsAmount,
     WrongEncryptedUtf8Error,
+    WrongEncryptedAttributes,
+    WrongEncryptedRecord,
+    WrongEncryptedCriticalRecord,
+    WrongIndexRecord,
+    WrongIndexCriticalRecord,
 }
 
 impl ErrorKind {
@@ -29,6 +45,19 @@ impl ErrorKind {
             WrongHeaderVecSize => "Wrong Header vector size", // TODO: allow to specify the size
             WrongRawVecSize => "Wrong Raw vector size", // TODO: allow to specify the size
             WrongRawCipherKind => "Wrong Raw cipher kind",
+            WrongRawKdfKind => "Wrong Raw key derivation function kind",
+            WrongKdfCosts => {
+                "Key derivation function costs exceed the maximums"
+            }
+            WrongRawVersion => "Unsupported Raw version",
+            WrongRawChunkSize => "Wrong Raw chunk size",
+            WrongRawFlags => "Unknown Raw flags or wrong parity shards",
+            WrongSlotsVersion => "Unsupported key slots version",
+            WrongSlotsVecSize => "Wrong key slots vector size",
+            WrongSlotKind => "Unknown key slot kind",
+            WrongSlotStanzaType => "Wrong key slot stanza type",
+            WrongSlotKeyFactors => "Unknown key slot key factors",
+            WrongEncryptedVersion => "Unsupported Encrypted version",
             WrongEncryptedVecSize => "Wrong Encrypted vector size", // TODO: allow to specify the size
             WrongEncryptedWrongStringsAmount => {
                 "Wrong Encrypted strings amount"
@@ -36,6 +65,13 @@ impl ErrorKind {
             WrongEncryptedUtf8Error => {
                 "Wrong Encrypted string conversion error"
             } // TODO: allow to specify the size
+            WrongEncryptedAttributes => "Wrong Encrypted file attributes",
+            WrongEncryptedRecord => "Malformed or duplicate Encrypted record",
+            WrongEncryptedCriticalRecord => "Unknown critical Encrypted record",
+            WrongIndexRecord => {
+                "Malformed, duplicate or missing archive index record"
+            }
+            WrongIndexCriticalRecord => "Unknown critical archive index record",
         }
     }
 }
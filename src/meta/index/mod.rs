@@ -0,0 +1,197 @@
+#[cfg(test)]
+mod tests;
+
+use crate::meta::enc::{
+    read_record, write_record, ContentDigest, FileAttributes, TLV_CRITICAL,
+};
+use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use core::fmt;
+use std::borrow::Cow;
+
+/// Encrypted
+/// Index of the archive entries, in front of their content,
+/// see [`crate::archive`]
+///
+/// # Binary structure
+///
+/// A sequence of the type-length-value records like [`crate::meta::enc::EncryptedMeta`],
+/// the record `0x0001` (critical) per entry. Its value is the records
+/// of the entry itself:
+///
+/// - `0x0001` (critical): the path relative to the archive root,
+///   the raw bytes of the `OsStr` components, separated by `/`
+/// - `0x0002` (critical): the [`ContentDigest`] of the entry
+/// - `0x0003`: the [`FileAttributes`]
+///
+/// Unknown non-critical records are skipped on both levels.
+/// The content of the entries follows the index in the same order
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ArchiveIndex {
+    pub entries: Vec<ArchiveEntry>,
+}
+
+/// File of the archive
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path relative to the archive root, the components are separated by `/`.
+    /// Not necessarily UTF-8, see [`ArchiveEntry::path_lossy`]
+    pub path: Vec<u8>,
+
+    /// The content size and digest
+    pub content: ContentDigest,
+
+    pub attributes: FileAttributes,
+}
+
+/// TLV record types, see [`ArchiveIndex`]
+const TLV_ENTRY: u16 = 0x0001;
+const TLV_ENTRY_PATH: u16 = 0x0001;
+const TLV_ENTRY_CONTENT_DIGEST: u16 = 0x0002;
+const TLV_ENTRY_ATTRIBUTES: u16 = 0x0003;
+
+impl ArchiveEntry {
+    /// Path for the output, the invalid UTF-8 is replaced
+    pub fn path_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.path)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(128);
+        write_record(
+            &mut result,
+            TLV_ENTRY_PATH | TLV_CRITICAL,
+            &self.path,
+        );
+        write_record(
+            &mut result,
+            TLV_ENTRY_CONTENT_DIGEST | TLV_CRITICAL,
+            &self.content.to_bytes(),
+        );
+        if !self.attributes.is_empty() {
+            write_record(
+                &mut result,
+                TLV_ENTRY_ATTRIBUTES,
+                &self.attributes.to_bytes(),
+            );
+        }
+        result
+    }
+
+    fn try_from_records(value: &[u8]) -> Result<Self, MetaError> {
+        let mut path = None;
+        let mut content = None;
+        let mut attributes = None;
+
+        let mut offset = 0usize;
+        while offset < value.len() {
+            let (record_type, data) = read_index_record(value, &mut offset)?;
+            match record_type & !TLV_CRITICAL {
+                TLV_ENTRY_PATH if path.is_none() => {
+                    path = Some(data.to_vec());
+                }
+                TLV_ENTRY_CONTENT_DIGEST if content.is_none() => {
+                    content = Some(ContentDigest::try_from_bytes(data)?);
+                }
+                TLV_ENTRY_ATTRIBUTES if attributes.is_none() => {
+                    attributes = Some(FileAttributes::try_from_bytes(data)?);
+                }
+                TLV_ENTRY_PATH
+                | TLV_ENTRY_CONTENT_DIGEST
+                | TLV_ENTRY_ATTRIBUTES => {
+                    return Err(MetaErrorKind::WrongIndexRecord.into());
+                }
+                _ if record_type & TLV_CRITICAL != 0 => {
+                    return Err(MetaErrorKind::WrongIndexCriticalRecord.into());
+                }
+                _ => {
+                    log::debug!(target: "meta/index ArchiveEntry try_from_records", "Unknown record {record_type:#06x} is skipped");
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.ok_or(MetaErrorKind::WrongIndexRecord)?,
+            content: content.ok_or(MetaErrorKind::WrongIndexRecord)?,
+            attributes: attributes.unwrap_or_default(),
+        })
+    }
+}
+
+impl ArchiveIndex {
+    /// Total size of the entries content
+    pub fn content_size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.content.size)
+            .sum()
+    }
+
+    pub fn find(&self, path: &[u8]) -> Option<&ArchiveEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path)
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        for entry in &self.entries {
+            write_record(
+                &mut result,
+                TLV_ENTRY | TLV_CRITICAL,
+                &entry.to_vec(),
+            );
+        }
+
+        log::trace!(target: "meta/index ArchiveIndex to_vec", "Result: {result:?}");
+        result
+    }
+
+    pub fn try_from_bytes(value: &[u8]) -> Result<Self, MetaError> {
+        log::debug!(target: "meta/index ArchiveIndex try_from_bytes", "value.len(): {:?}", value.len());
+
+        let mut entries = Vec::new();
+        let mut offset = 0usize;
+        while offset < value.len() {
+            let (record_type, data) = read_index_record(value, &mut offset)?;
+            match record_type & !TLV_CRITICAL {
+                TLV_ENTRY => {
+                    entries.push(ArchiveEntry::try_from_records(data)?);
+                }
+                _ if record_type & TLV_CRITICAL != 0 => {
+                    return Err(MetaErrorKind::WrongIndexCriticalRecord.into());
+                }
+                _ => {
+                    log::debug!(target: "meta/index ArchiveIndex try_from_bytes", "Unknown record {record_type:#06x} is skipped");
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn read_index_record<'a>(
+    value: &'a [u8],
+    offset: &mut usize,
+) -> Result<(u16, &'a [u8]), MetaError> {
+    read_record(value, offset)
+        .map_err(|_| MetaError::from(MetaErrorKind::WrongIndexRecord))
+}
+
+impl fmt::Display for ArchiveIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:>12}  {}",
+                entry.content.size,
+                entry.path_lossy()
+            )?;
+        }
+        writeln!(
+            f,
+            "Entries: {}, content: {} bytes",
+            self.entries.len(),
+            self.content_size()
+        )
+    }
+}
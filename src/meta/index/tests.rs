@@ -0,0 +1,126 @@
+use crate::error;
+use crate::meta::enc::{ContentDigest, FileAttributes};
+use crate::meta::error::ErrorKind as MetaErrorKind;
+use crate::meta::index::{ArchiveEntry, ArchiveIndex};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn fixtures() -> ArchiveIndex {
+    ArchiveIndex {
+        entries: vec![
+            ArchiveEntry {
+                path: b"a.txt".to_vec(),
+                content: ContentDigest {
+                    size: 3,
+                    sha256: [0xAB; 32],
+                },
+                attributes: FileAttributes::default(),
+            },
+            ArchiveEntry {
+                path: b"dir/\xFF.bin".to_vec(),
+                content: ContentDigest {
+                    size: 1234,
+                    sha256: [0xCD; 32],
+                },
+                attributes: FileAttributes {
+                    mode: Some(0o640),
+                    owner: None,
+                    modified: Some(
+                        UNIX_EPOCH + Duration::new(1_700_000_000, 5),
+                    ),
+                    accessed: None,
+                },
+            },
+        ],
+    }
+}
+
+#[test]
+fn test_to_vec() {
+    let index = fixtures();
+    let bytes = index.to_vec();
+
+    // The entry record: the path and the digest records
+    let entry_len = (6 + 5) + (6 + ContentDigest::SIZE);
+    assert_eq!(
+        bytes[..6],
+        [[0x01, 0x80].as_slice(), &(entry_len as u32).to_le_bytes()].concat()
+    );
+    assert_eq!(
+        bytes[6..17],
+        [0x01, 0x80, 5, 0, 0, 0, b'a', b'.', b't', b'x', b't']
+    );
+    assert_eq!(bytes[17..19], [0x02, 0x80]);
+}
+
+#[test]
+fn test_from_bytes() -> error::Result<()> {
+    let index = fixtures();
+
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&index.to_vec())?,
+        index
+    );
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&[])?,
+        ArchiveIndex::default()
+    );
+    assert_eq!(index.content_size(), 1237);
+    assert_eq!(
+        index.find(b"dir/\xFF.bin"),
+        Some(&index.entries[1])
+    );
+    assert_eq!(index.find(b"dir"), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_records() -> error::Result<()> {
+    let index = fixtures();
+    let bytes = index.to_vec();
+
+    // Unknown non-critical records are skipped
+    let unknown = [0x07, 0x00, 1, 0, 0, 0, 0xEE];
+    let skipped = [unknown.as_slice(), &bytes].concat();
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&skipped)?,
+        index
+    );
+
+    let critical = [0x07, 0x80, 0, 0, 0, 0];
+    let failed = [critical.as_slice(), &bytes].concat();
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&failed)
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongIndexCriticalRecord
+    );
+
+    // Truncated
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&bytes[..bytes.len() - 1])
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongIndexRecord
+    );
+
+    // No digest
+    let entry = [0x01, 0x80, 1, 0, 0, 0, b'a'];
+    let no_digest = [
+        [0x01, 0x80].as_slice(),
+        &(entry.len() as u32).to_le_bytes(),
+        &entry,
+    ]
+    .concat();
+    assert_eq!(
+        ArchiveIndex::try_from_bytes(&no_digest)
+            .err()
+            .unwrap()
+            .repr,
+        MetaErrorKind::WrongIndexRecord
+    );
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
 pub mod enc;
 pub mod error;
 pub mod header;
+pub mod index;
 pub mod raw;
+pub mod slots;
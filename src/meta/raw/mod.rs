@@ -2,9 +2,14 @@
 mod tests;
 
 use crate::cipher::CipherKind;
+use crate::kdf::{KdfParams, KDF_SALT_SIZE};
+use crate::meta::enc::EncryptedMeta;
 use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+use crate::meta::header::MetaHeader;
+use crate::parity::ParityParams;
 
 pub const META_RAW_NONCE_SIZE: usize = 19usize;
+pub const KEY_CHECK_SIZE: usize = 16usize;
 
 /// Raw (non-encrypted)
 /// Meta-information about file encryption
@@ -12,18 +17,61 @@ pub const META_RAW_NONCE_SIZE: usize = 19usize;
 /// # Binary structure
 ///
 /// - `CK` stands for Cipher Kind
+/// - `KK` stands for KDF Kind (versions 2 to 5)
+/// - `FL` stands for the flags (since version 8), bit 0 is the parity
+/// - `D`, `R` stand for the parity data and parity shards (since version 8,
+///   zeros without the parity flag)
 /// - `--` stands for Reserved
 /// - `N` stands for Nonce
+/// - `M`, `T`, `P` stand for KDF memory, time and parallelism costs
+///   (versions 2 to 5, in Little Endian)
+/// - `S` stands for KDF Salt (versions 2 to 5)
+///   or the key check value (since version 7)
+/// - `C` stands for the plain text chunk size
+///   (since version 5, in Little Endian)
+/// - `L` stands for the length of the EncryptedMeta with its MetaHeader
+///   (since version 9, in Little Endian, in place of `M`)
 ///
 /// ```kotlin
 ///      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
-/// 0x00 CK -- -- -- -- -- -- -- -- -- -- -- -- N  N  N
+/// 0x00 CK KK FL D  R  -- -- -- -- -- -- -- -- N  N  N
 /// 0x10 N  N  N  N  N  N  N  N  N  N  N  N  N  N  N  N
+/// 0x20 M  M  M  M  T  T  T  T  P  P  P  P  C  C  C  C
+/// 0x30 S  S  S  S  S  S  S  S  S  S  S  S  S  S  S  S
 /// ```
 ///
+/// Version 1 structure consists of the first 0x20 bytes only,
+/// the key is derived using the single SHA-256.
+///
+/// Version 3 has the same structure as version 2,
+/// but the encrypted stream is finalized (see `StreamParams`).
+///
+/// Version 4 has the same structure as version 3, but the MetaHeader
+/// and RawMeta bytes, stored in the file, are authenticated
+/// as the associated data of every encrypted chunk.
+///
+/// The chunk size is stored since version 5,
+/// the older versions use `LEGACY_CHUNK_SIZE`.
+///
+/// Since version 6 the KDF fields and the salt are reserved:
+/// the content is encrypted by a random data key,
+/// wrapped by the key slots (see `KeySlots`), which follow the RawMeta.
+///
+/// Since version 7 the key check value of the data key is stored
+/// in place of the salt, so the wrong key is detected
+/// before the output file is opened (see `keyslot::key_check_value`).
+///
+/// Since version 8 the encrypted stream may contain the Reed-Solomon parity
+/// (see `parity::ParityEncoder`). Unknown flags are rejected.
+///
+/// Since version 9 the length of the EncryptedMeta is stored,
+/// so the content is located, even if the first chunk is lost
+/// (see `cipher::kind::Cipher::recover`).
+///
 /// ## Example
 ///
 /// Consider:
+/// - the version is 1
 /// - the CipherKind is 0x01
 /// - nonce is `0x01 ... 0x13`
 ///
@@ -34,81 +82,250 @@ pub const META_RAW_NONCE_SIZE: usize = 19usize;
 /// ```
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct RawMeta {
+    /// Structure version. Stored inside the MetaHeader
+    pub version: u8,
+
     /// Cipher type
     pub cipher_kind: CipherKind,
 
-    /// Public number for a cipher
+    /// Public number for a cipher.
+    /// Only the first `cipher::kind::nonce_size` bytes are used
     pub nonce: [u8; META_RAW_NONCE_SIZE],
+
+    /// Key derivation function and its costs.
+    /// None since version 6, the key slots have their own
+    pub kdf: Option<KdfParams>,
+
+    /// Public salt for the key derivation function. Zeros if not used
+    pub salt: [u8; KDF_SALT_SIZE],
+
+    /// Plain text chunk size of the encrypted stream
+    pub chunk_size: u32,
+
+    /// Check value of the data key. Some since version 7
+    pub key_check: Option<[u8; KEY_CHECK_SIZE]>,
+
+    /// Parity of the encrypted stream. May be Some since version 8
+    pub parity: Option<ParityParams>,
+
+    /// Length of the MetaHeader and the EncryptedMeta
+    /// in front of the content. Some since version 9
+    pub meta_len: Option<u32>,
 }
 
 impl RawMeta {
-    const NONCE_BYTE_INDEX: usize = Self::size() - META_RAW_NONCE_SIZE;
+    const NONCE_BYTE_INDEX: usize = 0x20 - META_RAW_NONCE_SIZE;
+    const FLAGS_BYTE_INDEX: usize = 0x02;
+    const FLAG_PARITY: u8 = 0x01;
+    const KDF_BYTE_INDEX: usize = 0x20;
+    const META_LEN_BYTE_INDEX: usize = 0x20;
+    const CHUNK_SIZE_BYTE_INDEX: usize = 0x2C;
+    const SALT_BYTE_INDEX: usize = 0x30;
+
+    pub const LEGACY_CHUNK_SIZE: u32 = 500;
+    pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
 
-    pub const fn size() -> usize {
-        0x20
+    /// The first chunk must contain the whole MetaHeader
+    /// of the EncryptedMeta
+    pub const MIN_CHUNK_SIZE: u32 = MetaHeader::size() as u32;
+
+    /// Limits the memory, allocated by the decryptor
+    pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+    pub const fn size(version: u8) -> usize {
+        match version {
+            1 => 0x20,
+            _ => 0x40,
+        }
     }
 
     pub const fn len(&self) -> usize {
-        Self::size()
+        Self::size(self.version)
     }
 
     pub const fn is_empty(&self) -> bool {
         false
     }
 
-    pub const fn version() -> u8 {
-        1
+    /// Version of the structure, created by this build
+    pub const fn latest_version() -> u8 {
+        9
+    }
+
+    pub const fn is_chunk_size_valid(chunk_size: u32) -> bool {
+        chunk_size >= Self::MIN_CHUNK_SIZE && chunk_size <= Self::MAX_CHUNK_SIZE
+    }
+
+    pub const fn is_meta_len_valid(meta_len: u32) -> bool {
+        meta_len as u64 >= MetaHeader::size() as u64
+            && meta_len as u64
+                <= MetaHeader::size() as u64 + EncryptedMeta::MAX_SIZE
     }
 
-    pub fn to_bytes(&self) -> [u8; Self::size()] {
-        let mut result: [u8; Self::size()] = [0u8; Self::size()];
+    pub const fn is_version_supported(version: u8) -> bool {
+        version >= 1 && version <= Self::latest_version()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![0u8; self.len()];
         result[0] = self.cipher_kind.to_u8();
 
-        result[Self::NONCE_BYTE_INDEX..].clone_from_slice(&self.nonce);
+        result[Self::NONCE_BYTE_INDEX..Self::KDF_BYTE_INDEX]
+            .clone_from_slice(&self.nonce);
+
+        if let Some(kdf) = self.kdf.filter(|_| self.version >= 2) {
+            result[1] = kdf.kind.to_u8();
+
+            let costs = [kdf.m_cost, kdf.t_cost, kdf.p_cost];
+            for (i, cost) in costs.iter().enumerate() {
+                let index = Self::KDF_BYTE_INDEX + i * 4;
+                result[index..index + 4].clone_from_slice(&cost.to_le_bytes());
+            }
+        }
+
+        if self.version >= 2 {
+            result[Self::SALT_BYTE_INDEX..].clone_from_slice(&self.salt);
+        }
+
+        if self.version >= 5 {
+            result[Self::CHUNK_SIZE_BYTE_INDEX..Self::SALT_BYTE_INDEX]
+                .clone_from_slice(&self.chunk_size.to_le_bytes());
+        }
+
+        if let Some(key_check) = self
+            .key_check
+            .filter(|_| self.version >= 7)
+        {
+            result[Self::SALT_BYTE_INDEX..].clone_from_slice(&key_check);
+        }
+
+        if let Some(parity) = self
+            .parity
+            .filter(|_| self.version >= 8)
+        {
+            result[Self::FLAGS_BYTE_INDEX] |= Self::FLAG_PARITY;
+            result[Self::FLAGS_BYTE_INDEX + 1] = parity.data_shards;
+            result[Self::FLAGS_BYTE_INDEX + 2] = parity.parity_shards;
+        }
+
+        if let Some(meta_len) = self
+            .meta_len
+            .filter(|_| self.version >= 9)
+        {
+            result[Self::META_LEN_BYTE_INDEX..Self::META_LEN_BYTE_INDEX + 4]
+                .clone_from_slice(&meta_len.to_le_bytes());
+        }
 
         log::trace!(target: "meta/raw RawMeta to_bytes", "Result: {result:?}");
         result
     }
 
     pub fn try_from_bytes(
-        bytes: [u8; Self::size()],
+        version: u8,
+        bytes: &[u8],
     ) -> Result<Self, MetaError> {
-        Ok(Self {
-            cipher_kind: bytes[0].try_into()?,
-            nonce: bytes[Self::NONCE_BYTE_INDEX..]
-                .try_into()
-                .expect("Wrong slice size"),
-        })
-    }
-}
+        log::debug!(target: "meta/raw RawMeta try_from_bytes", "version: {version:?}, bytes.len(): {:?}", bytes.len());
 
-impl TryFrom<[u8; Self::size()]> for RawMeta {
-    type Error = MetaError;
+        if !Self::is_version_supported(version) {
+            return Err(MetaErrorKind::WrongRawVersion.into());
+        }
+        if bytes.len() != Self::size(version) {
+            return Err(MetaErrorKind::WrongRawVecSize.into());
+        }
 
-    fn try_from(value: [u8; Self::size()]) -> Result<RawMeta, Self::Error> {
-        RawMeta::try_from_bytes(value)
-    }
-}
+        let read_u32 = |index: usize| {
+            u32::from_le_bytes(
+                bytes[index..index + 4]
+                    .try_into()
+                    .expect("Wrong slice size"),
+            )
+        };
 
-impl TryFrom<&Vec<u8>> for RawMeta {
-    type Error = MetaError;
+        let (kdf, salt) = match version {
+            1 => (
+                Some(KdfParams::legacy()),
+                [0u8; KDF_SALT_SIZE],
+            ),
+            2..=5 => {
+                let kdf = KdfParams {
+                    kind: bytes[1].try_into()?,
+                    m_cost: read_u32(Self::KDF_BYTE_INDEX),
+                    t_cost: read_u32(Self::KDF_BYTE_INDEX + 4),
+                    p_cost: read_u32(Self::KDF_BYTE_INDEX + 8),
+                };
+                // The unsalted hash is of the version 1 only
+                if !kdf.kind.is_salted() {
+                    return Err(MetaErrorKind::WrongRawKdfKind.into());
+                }
+                if !kdf.is_valid() {
+                    return Err(MetaErrorKind::WrongKdfCosts.into());
+                }
+                let salt = bytes[Self::SALT_BYTE_INDEX..]
+                    .try_into()
+                    .expect("Wrong slice size");
+                (Some(kdf), salt)
+            }
+            _ => (None, [0u8; KDF_SALT_SIZE]),
+        };
 
-    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
-        log::debug!(target: "meta/raw RawMeta try_from", "value.len(): {:?}",  value.len());
+        let chunk_size = if version >= 5 {
+            read_u32(Self::CHUNK_SIZE_BYTE_INDEX)
+        } else {
+            Self::LEGACY_CHUNK_SIZE
+        };
+        if !Self::is_chunk_size_valid(chunk_size) {
+            return Err(MetaErrorKind::WrongRawChunkSize.into());
+        }
 
-        RawMeta::try_from_bytes(
-            value
-                .as_slice()
-                .try_into()
-                .map_err(|_| MetaError::from(MetaErrorKind::WrongRawVecSize))?,
-        )
-    }
-}
+        let key_check = match version {
+            7.. => Some(
+                bytes[Self::SALT_BYTE_INDEX..]
+                    .try_into()
+                    .expect("Wrong slice size"),
+            ),
+            _ => None,
+        };
 
-impl TryFrom<Vec<u8>> for RawMeta {
-    type Error = MetaError;
+        let flags = match version {
+            8.. => bytes[Self::FLAGS_BYTE_INDEX],
+            _ => 0,
+        };
+        if flags & !Self::FLAG_PARITY != 0 {
+            return Err(MetaErrorKind::WrongRawFlags.into());
+        }
+        let parity = if flags & Self::FLAG_PARITY != 0 {
+            let parity = ParityParams {
+                data_shards: bytes[Self::FLAGS_BYTE_INDEX + 1],
+                parity_shards: bytes[Self::FLAGS_BYTE_INDEX + 2],
+            };
+            if !parity.is_valid() {
+                return Err(MetaErrorKind::WrongRawFlags.into());
+            }
+            Some(parity)
+        } else {
+            None
+        };
 
-    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-        (&value).try_into()
+        let meta_len = match version {
+            9.. => Some(read_u32(Self::META_LEN_BYTE_INDEX)),
+            _ => None,
+        };
+        if meta_len.is_some_and(|meta_len| !Self::is_meta_len_valid(meta_len)) {
+            return Err(MetaErrorKind::WrongEncryptedVecSize.into());
+        }
+
+        Ok(Self {
+            version,
+            cipher_kind: bytes[0].try_into()?,
+            nonce: bytes[Self::NONCE_BYTE_INDEX..Self::KDF_BYTE_INDEX]
+                .try_into()
+                .expect("Wrong slice size"),
+            kdf,
+            salt,
+            chunk_size,
+            key_check,
+            parity,
+            meta_len,
+        })
     }
 }
@@ -1,5 +1,9 @@
+use crate::kdf::{KdfKind, KdfParams, KDF_SALT_SIZE};
+use crate::meta::enc::EncryptedMeta;
 use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
-use crate::meta::raw::{RawMeta, META_RAW_NONCE_SIZE};
+use crate::meta::header::MetaHeader;
+use crate::meta::raw::{RawMeta, KEY_CHECK_SIZE, META_RAW_NONCE_SIZE};
+use crate::parity::ParityParams;
 use crate::{error, CipherKind};
 
 const NONCE: [u8; META_RAW_NONCE_SIZE] = [
@@ -7,30 +11,84 @@ const NONCE: [u8; META_RAW_NONCE_SIZE] = [
     5u8, 6u8, 7u8, 8u8,
 ];
 
+const SALT: [u8; KDF_SALT_SIZE] = [0x5Au8; KDF_SALT_SIZE];
+
+const RAW_TEMPLATE_V1: RawMeta = RawMeta {
+    version: 1,
+    nonce: NONCE,
+    cipher_kind: CipherKind::ChaCha20Poly1305,
+    kdf: Some(KdfParams::legacy()),
+    salt: [0u8; KDF_SALT_SIZE],
+    chunk_size: RawMeta::LEGACY_CHUNK_SIZE,
+    key_check: None,
+    parity: None,
+    meta_len: None,
+};
+
 const RAW_TEMPLATE: RawMeta = RawMeta {
+    version: 2,
     nonce: NONCE,
     cipher_kind: CipherKind::ChaCha20Poly1305,
+    kdf: Some(KdfParams {
+        kind: KdfKind::Argon2id,
+        m_cost: 0x4C00,
+        t_cost: 2,
+        p_cost: 1,
+    }),
+    salt: SALT,
+    chunk_size: RawMeta::LEGACY_CHUNK_SIZE,
+    key_check: None,
+    parity: None,
+    meta_len: None,
+};
+
+const RAW_TEMPLATE_V5: RawMeta = RawMeta {
+    version: 5,
+    chunk_size: 0x10000,
+    ..RAW_TEMPLATE
 };
 
-fn fixtures() -> ([u8; RawMeta::size()]) {
+fn fixtures_v1() -> [u8; RawMeta::size(1)] {
+    {
+        let mut x = [0u8; RawMeta::size(1)];
+
+        x[0] = CipherKind::ChaCha20Poly1305.to_u8();
+        x[RawMeta::NONCE_BYTE_INDEX..].clone_from_slice(&NONCE);
+
+        x
+    }
+}
+
+fn fixtures() -> [u8; RawMeta::size(2)] {
     {
-        let mut x = [0u8; RawMeta::size()];
+        let mut x = [0u8; RawMeta::size(2)];
 
         x[0] = CipherKind::ChaCha20Poly1305.to_u8();
-        (&mut x[RawMeta::NONCE_BYTE_INDEX..]).clone_from_slice(&NONCE);
+        x[1] = KdfKind::Argon2id.to_u8();
+        x[RawMeta::NONCE_BYTE_INDEX..0x20].clone_from_slice(&NONCE);
+        x[0x20..0x2C].clone_from_slice(&[
+            0x00, 0x4C, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x00,
+        ]);
+        x[0x30..].clone_from_slice(&SALT);
 
         x
     }
 }
 
+fn fixtures_v5() -> [u8; RawMeta::size(5)] {
+    let mut x = fixtures();
+    x[0x2C..0x30].clone_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+
+    x
+}
+
 #[test]
 fn test_from_bytes_err_cipherkind() -> error::Result<()> {
-    let bytes = fixtures();
-
-    let mut bytes = bytes.clone();
+    let mut bytes = fixtures();
     bytes[0] = 0xFF;
 
-    let result: Result<RawMeta, MetaError> = bytes.try_into();
+    let result = RawMeta::try_from_bytes(2, &bytes);
     assert!(result.is_err());
 
     let err = result.err().unwrap();
@@ -42,10 +100,58 @@ fn test_from_bytes_err_cipherkind() -> error::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_from_bytes_err_kdfkind() -> error::Result<()> {
+    let mut bytes = fixtures();
+    bytes[1] = 0xFF;
+
+    let result = RawMeta::try_from_bytes(2, &bytes);
+
+    let err = result.err().unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongRawKdfKind);
+
+    // The unsalted hash is of the version 1 only
+    bytes[1] = KdfKind::Sha256.to_u8();
+    let err = RawMeta::try_from_bytes(2, &bytes)
+        .err()
+        .unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongRawKdfKind);
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_kdf_costs() -> error::Result<()> {
+    // Memory cost, time cost and parallelism over the maximums
+    for index in [0x20, 0x24, 0x28] {
+        let mut bytes = fixtures();
+        bytes[index..index + 4].clone_from_slice(&u32::MAX.to_le_bytes());
+
+        let result = RawMeta::try_from_bytes(2, &bytes);
+
+        let err = result.err().unwrap();
+        assert_eq!(err.repr, MetaErrorKind::WrongKdfCosts);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_version() -> error::Result<()> {
+    let bytes = fixtures();
+
+    let result = RawMeta::try_from_bytes(RawMeta::latest_version() + 1, &bytes);
+
+    let err = result.err().unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongRawVersion);
+
+    Ok(())
+}
+
 #[test]
 fn test_from_bytes() -> error::Result<()> {
     let bytes = fixtures();
-    let header: RawMeta = bytes.try_into()?;
+    let header = RawMeta::try_from_bytes(2, &bytes)?;
 
     assert_eq!(RAW_TEMPLATE, header,);
 
@@ -53,35 +159,224 @@ fn test_from_bytes() -> error::Result<()> {
 }
 
 #[test]
-fn test_to_bytes() -> error::Result<()> {
-    let bytes = fixtures();
+fn test_from_bytes_v1() -> error::Result<()> {
+    let mut bytes = fixtures_v1();
+    // Reserved byte is not the KDF kind in version 1
+    bytes[1] = 0xFF;
 
-    assert_eq!(RAW_TEMPLATE.to_bytes(), bytes,);
+    let header = RawMeta::try_from_bytes(1, &bytes)?;
+
+    assert_eq!(RAW_TEMPLATE_V1, header,);
 
     Ok(())
 }
 
 #[test]
-fn test_from_vec_err() -> error::Result<()> {
-    let bytes = fixtures();
+fn test_from_bytes_v5() -> error::Result<()> {
+    let header = RawMeta::try_from_bytes(5, &fixtures_v5())?;
+    assert_eq!(RAW_TEMPLATE_V5, header);
 
-    let result: Result<RawMeta, MetaError> =
-        (&bytes[..RawMeta::size() - 1].to_vec()).try_into();
-    assert!(result.is_err());
+    // The chunk size bytes are reserved before version 5
+    let header = RawMeta::try_from_bytes(2, &fixtures_v5())?;
+    assert_eq!(RAW_TEMPLATE, header);
 
-    let err = result.err().unwrap();
-    assert_eq!(err.repr, MetaErrorKind::WrongRawVecSize);
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_v6() -> error::Result<()> {
+    // The KDF fields are reserved since version 6
+    let header = RawMeta::try_from_bytes(6, &fixtures_v5())?;
+    let expected = RawMeta {
+        version: 6,
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        ..RAW_TEMPLATE_V5
+    };
+    assert_eq!(expected, header);
+
+    let mut bytes = fixtures_v5();
+    bytes[1] = 0;
+    bytes[0x20..0x2C].fill(0);
+    bytes[0x30..].fill(0);
+    assert_eq!(expected.to_bytes(), bytes.to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_v7() -> error::Result<()> {
+    // The key check value takes the place of the salt since version 7
+    let header = RawMeta::try_from_bytes(7, &fixtures_v5())?;
+    let expected = RawMeta {
+        version: 7,
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        key_check: Some(
+            SALT[..KEY_CHECK_SIZE]
+                .try_into()
+                .unwrap(),
+        ),
+        ..RAW_TEMPLATE_V5
+    };
+    assert_eq!(expected, header);
+
+    let mut bytes = fixtures_v5();
+    bytes[1] = 0;
+    bytes[0x20..0x2C].fill(0);
+    assert_eq!(expected.to_bytes(), bytes.to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_v8() -> error::Result<()> {
+    let mut bytes = fixtures_v5();
+    bytes[0x02..0x05].clone_from_slice(&[0x01, 16, 2]);
+
+    let header = RawMeta::try_from_bytes(8, &bytes)?;
+    let expected = RawMeta {
+        version: 8,
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        key_check: Some(
+            SALT[..KEY_CHECK_SIZE]
+                .try_into()
+                .unwrap(),
+        ),
+        parity: Some(ParityParams::new(2)),
+        ..RAW_TEMPLATE_V5
+    };
+    assert_eq!(expected, header);
+
+    bytes[1] = 0;
+    bytes[0x20..0x2C].fill(0);
+    assert_eq!(expected.to_bytes(), bytes.to_vec());
+
+    // The flags bytes are reserved before version 8
+    assert_eq!(
+        RawMeta::try_from_bytes(7, &bytes)?.parity,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_v9() -> error::Result<()> {
+    let mut bytes = fixtures_v5();
+    bytes[1] = 0;
+    bytes[0x20..0x2C].fill(0);
+    bytes[0x20..0x24].clone_from_slice(&0x40u32.to_le_bytes());
+
+    let header = RawMeta::try_from_bytes(9, &bytes)?;
+    let expected = RawMeta {
+        version: 9,
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        key_check: Some(
+            SALT[..KEY_CHECK_SIZE]
+                .try_into()
+                .unwrap(),
+        ),
+        meta_len: Some(0x40),
+        ..RAW_TEMPLATE_V5
+    };
+    assert_eq!(expected, header);
+    assert_eq!(expected.to_bytes(), bytes.to_vec());
+
+    // The meta length is reserved before version 9
+    assert_eq!(
+        RawMeta::try_from_bytes(8, &bytes)?.meta_len,
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_meta_len() -> error::Result<()> {
+    let max_meta_len = MetaHeader::size() as u64 + EncryptedMeta::MAX_SIZE;
+    for meta_len in [0, MetaHeader::size() as u32 - 1, max_meta_len as u32 + 1]
+    {
+        let mut bytes = fixtures_v5();
+        bytes[0x20..0x24].clone_from_slice(&meta_len.to_le_bytes());
+
+        let err = RawMeta::try_from_bytes(9, &bytes)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.repr,
+            MetaErrorKind::WrongEncryptedVecSize
+        );
+    }
 
     Ok(())
 }
 
 #[test]
-fn test_from_vec() -> error::Result<()> {
+fn test_from_bytes_err_flags() -> error::Result<()> {
+    // The unknown flag, the parity without the parity shards
+    for flags in [[0x02u8, 0, 0], [0x01, 16, 0]] {
+        let mut bytes = fixtures_v5();
+        bytes[0x02..0x05].clone_from_slice(&flags);
+
+        let err = RawMeta::try_from_bytes(8, &bytes)
+            .err()
+            .unwrap();
+        assert_eq!(err.repr, MetaErrorKind::WrongRawFlags);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err_chunk_size() -> error::Result<()> {
+    for chunk_size in [RawMeta::MIN_CHUNK_SIZE - 1, RawMeta::MAX_CHUNK_SIZE + 1]
+    {
+        let mut bytes = fixtures_v5();
+        bytes[0x2C..0x30].clone_from_slice(&chunk_size.to_le_bytes());
+
+        let result = RawMeta::try_from_bytes(5, &bytes);
+
+        let err = result.err().unwrap();
+        assert_eq!(
+            err.repr,
+            MetaErrorKind::WrongRawChunkSize
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_to_bytes() -> error::Result<()> {
+    assert_eq!(
+        RAW_TEMPLATE.to_bytes(),
+        fixtures().to_vec()
+    );
+    assert_eq!(
+        RAW_TEMPLATE_V1.to_bytes(),
+        fixtures_v1().to_vec()
+    );
+    assert_eq!(
+        RAW_TEMPLATE_V5.to_bytes(),
+        fixtures_v5().to_vec()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_vec_err() -> error::Result<()> {
     let bytes = fixtures();
 
-    let result: RawMeta = (&bytes.to_vec()).try_into()?;
+    let result: Result<RawMeta, MetaError> =
+        RawMeta::try_from_bytes(2, &bytes[..RawMeta::size(2) - 1]);
+    assert!(result.is_err());
 
-    assert_eq!(result, RAW_TEMPLATE);
+    let err = result.err().unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongRawVecSize);
 
     Ok(())
 }
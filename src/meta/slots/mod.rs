@@ -0,0 +1,415 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error;
+use crate::kdf::{KdfParams, KDF_KEY_SIZE, KDF_SALT_SIZE};
+use crate::meta::error::{ErrorKind as MetaErrorKind, MetaError};
+
+/// Data key, encrypted by an AEAD (with the 16 bytes tag)
+pub const WRAPPED_KEY_SIZE: usize = KDF_KEY_SIZE + 16;
+
+pub const X25519_KEY_SIZE: usize = 32;
+
+/// What the password slot key is derived from
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum KeyFactors {
+    Password,
+    Keyfile,
+    /// Both the password and the keyfile are required
+    PasswordKeyfile,
+}
+
+impl KeyFactors {
+    pub fn to_u8(self) -> u8 {
+        use KeyFactors::*;
+        match self {
+            Password => 0,
+            Keyfile => 1,
+            PasswordKeyfile => 2,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        use KeyFactors::*;
+        match self {
+            Password => "password",
+            Keyfile => "keyfile",
+            PasswordKeyfile => "password and keyfile",
+        }
+    }
+}
+
+impl TryInto<KeyFactors> for u8 {
+    type Error = MetaError;
+
+    fn try_into(self) -> Result<KeyFactors, Self::Error> {
+        match self {
+            0 => Ok(KeyFactors::Password),
+            1 => Ok(KeyFactors::Keyfile),
+            2 => Ok(KeyFactors::PasswordKeyfile),
+            _ => Err(MetaErrorKind::WrongSlotKeyFactors.into()),
+        }
+    }
+}
+
+/// Key slot, wrapping the data key
+///
+/// # Binary structure
+///
+/// - `K` stands for slot Kind
+/// - `--` stands for Reserved
+/// - `L` stands for the body Length (in Little Endian)
+/// - `B` stands for the Body
+///
+/// ```kotlin
+///      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+/// 0x00 K  -- L  L  B  B  B  B  B  B  B  B  B  B  B  B
+/// 0x10 B  ...
+/// ```
+///
+/// ## Password slot body (kind 0x00)
+///
+/// - `KK` stands for KDF Kind
+/// - `F` stands for the key Factors (zero, the password, before keyfiles)
+/// - `M`, `T`, `P` stand for KDF memory, time and parallelism costs
+///   (in Little Endian)
+/// - `S` stands for KDF Salt
+/// - `W` stands for the Wrapped data key
+///
+/// ```kotlin
+///      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+/// 0x00 KK F  -- -- M  M  M  M  T  T  T  T  P  P  P  P
+/// 0x10 S  S  S  S  S  S  S  S  S  S  S  S  S  S  S  S
+/// 0x20 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// 0x30 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// 0x40 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// ```
+///
+/// ## X25519 slot body (kind 0x01)
+///
+/// - `E` stands for the Ephemeral public key
+/// - `W` stands for the Wrapped data key
+///
+/// ```kotlin
+///      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+/// 0x00 E  E  E  E  E  E  E  E  E  E  E  E  E  E  E  E
+/// 0x10 E  E  E  E  E  E  E  E  E  E  E  E  E  E  E  E
+/// 0x20 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// 0x30 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// 0x40 W  W  W  W  W  W  W  W  W  W  W  W  W  W  W  W
+/// ```
+///
+/// ## Stanza slot body (kind 0x02)
+///
+/// - `TL` stands for the stanza Type Length
+/// - `T` stands for the stanza Type (ASCII)
+/// - `AL` stands for the Arguments Length (in Little Endian)
+/// - `A` stands for the Arguments
+/// - `B` stands for the stanza Body, up to the end of the slot
+///
+/// ```kotlin
+///      0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+/// 0x00 TL T  T  T  ... AL AL A  A  ... B  B  B  B  ...
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum KeySlot {
+    /// The data key is wrapped by the key, derived from the password
+    Password {
+        kdf: KdfParams,
+        factors: KeyFactors,
+        salt: [u8; KDF_SALT_SIZE],
+        wrapped_key: [u8; WRAPPED_KEY_SIZE],
+    },
+
+    /// The data key is wrapped by the key, agreed with the X25519 recipient
+    X25519 {
+        ephemeral_public: [u8; X25519_KEY_SIZE],
+        wrapped_key: [u8; WRAPPED_KEY_SIZE],
+    },
+
+    /// Typed recipient stanza, which does not depend on the file cipher.
+    /// Stanzas of unknown types are kept and never unlocked,
+    /// so the new recipient types do not break the older decryptors
+    Stanza {
+        stanza_type: String,
+        args: Vec<u8>,
+        body: Vec<u8>,
+    },
+}
+
+impl KeySlot {
+    const HEADER_SIZE: usize = 4;
+    const PASSWORD_BODY_SIZE: usize = 0x50;
+    const X25519_BODY_SIZE: usize = 0x50;
+
+    pub fn kind_u8(&self) -> u8 {
+        match self {
+            KeySlot::Password { .. } => 0,
+            KeySlot::X25519 { .. } => 1,
+            KeySlot::Stanza { .. } => 2,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        Self::HEADER_SIZE
+            + match self {
+                KeySlot::Password { .. } => Self::PASSWORD_BODY_SIZE,
+                KeySlot::X25519 { .. } => Self::X25519_BODY_SIZE,
+                KeySlot::Stanza {
+                    stanza_type,
+                    args,
+                    body,
+                } => 1 + stanza_type.len() + 2 + args.len() + body.len(),
+            }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Fails, if the slot or the stanza fields exceed their length fields
+    pub fn to_bytes(&self) -> error::Result<Vec<u8>> {
+        let body_len = self.len() - Self::HEADER_SIZE;
+        let body_len = u16::try_from(body_len)
+            .map_err(|_| too_long_error("Key slot", body_len, u16::MAX))?;
+
+        let mut result = vec![0u8; self.len()];
+        result[0] = self.kind_u8();
+        result[2..Self::HEADER_SIZE].clone_from_slice(&body_len.to_le_bytes());
+
+        let body = &mut result[Self::HEADER_SIZE..];
+        match self {
+            KeySlot::Password {
+                kdf,
+                factors,
+                salt,
+                wrapped_key,
+            } => {
+                body[0] = kdf.kind.to_u8();
+                body[1] = factors.to_u8();
+
+                let costs = [kdf.m_cost, kdf.t_cost, kdf.p_cost];
+                for (i, cost) in costs.iter().enumerate() {
+                    let index = 0x04 + i * 4;
+                    body[index..index + 4]
+                        .clone_from_slice(&cost.to_le_bytes());
+                }
+
+                body[0x10..0x20].clone_from_slice(salt);
+                body[0x20..].clone_from_slice(wrapped_key);
+            }
+            KeySlot::X25519 {
+                ephemeral_public,
+                wrapped_key,
+            } => {
+                body[..0x20].clone_from_slice(ephemeral_public);
+                body[0x20..].clone_from_slice(wrapped_key);
+            }
+            KeySlot::Stanza {
+                stanza_type,
+                args,
+                body: stanza_body,
+            } => {
+                let args_index = 1 + stanza_type.len();
+                let body_index = args_index + 2 + args.len();
+                let type_len =
+                    u8::try_from(stanza_type.len()).map_err(|_| {
+                        too_long_error(
+                            "Stanza type",
+                            stanza_type.len(),
+                            u8::MAX,
+                        )
+                    })?;
+                let args_len = u16::try_from(args.len()).map_err(|_| {
+                    too_long_error("Stanza arguments", args.len(), u16::MAX)
+                })?;
+
+                body[0] = type_len;
+                body[1..args_index].clone_from_slice(stanza_type.as_bytes());
+                body[args_index..args_index + 2]
+                    .clone_from_slice(&args_len.to_le_bytes());
+                body[args_index + 2..body_index].clone_from_slice(args);
+                body[body_index..].clone_from_slice(stanza_body);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Parses the single slot from the beginning of `bytes`,
+    /// returns it with its length
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<(Self, usize), MetaError> {
+        if bytes.len() < Self::HEADER_SIZE {
+            return Err(MetaErrorKind::WrongSlotsVecSize.into());
+        }
+
+        let body_len = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        let body = bytes
+            .get(Self::HEADER_SIZE..Self::HEADER_SIZE + body_len)
+            .ok_or_else(|| MetaError::from(MetaErrorKind::WrongSlotsVecSize))?;
+
+        let read_u32 = |index: usize| {
+            u32::from_le_bytes(
+                body[index..index + 4]
+                    .try_into()
+                    .expect("Wrong slice size"),
+            )
+        };
+
+        let slot = match bytes[0] {
+            0 => {
+                if body_len != Self::PASSWORD_BODY_SIZE {
+                    return Err(MetaErrorKind::WrongSlotsVecSize.into());
+                }
+
+                let kdf = KdfParams {
+                    kind: body[0].try_into()?,
+                    m_cost: read_u32(0x04),
+                    t_cost: read_u32(0x08),
+                    p_cost: read_u32(0x0C),
+                };
+                if !kdf.kind.is_salted() {
+                    return Err(MetaErrorKind::WrongRawKdfKind.into());
+                }
+                if !kdf.is_valid() {
+                    return Err(MetaErrorKind::WrongKdfCosts.into());
+                }
+
+                KeySlot::Password {
+                    kdf,
+                    factors: body[1].try_into()?,
+                    salt: body[0x10..0x20]
+                        .try_into()
+                        .expect("Wrong slice size"),
+                    wrapped_key: body[0x20..]
+                        .try_into()
+                        .expect("Wrong slice size"),
+                }
+            }
+            1 => {
+                if body_len != Self::X25519_BODY_SIZE {
+                    return Err(MetaErrorKind::WrongSlotsVecSize.into());
+                }
+
+                KeySlot::X25519 {
+                    ephemeral_public: body[..0x20]
+                        .try_into()
+                        .expect("Wrong slice size"),
+                    wrapped_key: body[0x20..]
+                        .try_into()
+                        .expect("Wrong slice size"),
+                }
+            }
+            2 => Self::stanza_from_body(body)?,
+            _ => return Err(MetaErrorKind::WrongSlotKind.into()),
+        };
+
+        Ok((slot, Self::HEADER_SIZE + body_len))
+    }
+
+    fn stanza_from_body(body: &[u8]) -> Result<Self, MetaError> {
+        let size_error = || MetaError::from(MetaErrorKind::WrongSlotsVecSize);
+
+        let type_len = *body.first().ok_or_else(size_error)? as usize;
+        let stanza_type = body
+            .get(1..1 + type_len)
+            .ok_or_else(size_error)?;
+        if stanza_type.is_empty() || !stanza_type.is_ascii() {
+            return Err(MetaErrorKind::WrongSlotStanzaType.into());
+        }
+
+        let args_index = 1 + type_len;
+        let args_len = body
+            .get(args_index..args_index + 2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]) as usize)
+            .ok_or_else(size_error)?;
+        let body_index = args_index + 2 + args_len;
+        let args = body
+            .get(args_index + 2..body_index)
+            .ok_or_else(size_error)?;
+
+        Ok(KeySlot::Stanza {
+            stanza_type: String::from_utf8_lossy(stanza_type).into_owned(),
+            args: args.to_vec(),
+            body: body[body_index..].to_vec(),
+        })
+    }
+}
+
+fn too_long_error(
+    field: &str,
+    len: usize,
+    max: impl Into<usize>,
+) -> error::Error {
+    error::Error::new(
+        error::ErrorKind::InvalidArgument,
+        format!(
+            "{field} must be at most {} bytes, got: {len}",
+            max.into()
+        ),
+    )
+}
+
+/// Key slots, stored after the RawMeta since its version 6.
+/// Described by its own MetaHeader
+///
+/// # Binary structure
+///
+/// Slots (see [`KeySlot`]) are stored one after another
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct KeySlots {
+    pub slots: Vec<KeySlot>,
+}
+
+impl KeySlots {
+    /// Limits the memory, allocated by the decryptor
+    pub const MAX_SIZE: usize = 0x10000;
+
+    pub const fn version() -> u8 {
+        1
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots
+            .iter()
+            .map(KeySlot::len)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> error::Result<Vec<u8>> {
+        let result = self
+            .slots
+            .iter()
+            .map(KeySlot::to_bytes)
+            .collect::<error::Result<Vec<_>>>()?
+            .concat();
+
+        log::trace!(target: "meta/slots KeySlots to_bytes", "Result: {result:?}");
+        Ok(result)
+    }
+
+    pub fn try_from_bytes(
+        version: u8,
+        bytes: &[u8],
+    ) -> Result<Self, MetaError> {
+        log::debug!(target: "meta/slots KeySlots try_from_bytes", "version: {version:?}, bytes.len(): {:?}", bytes.len());
+
+        if version != Self::version() {
+            return Err(MetaErrorKind::WrongSlotsVersion.into());
+        }
+
+        let mut slots = Vec::new();
+        let mut index = 0usize;
+        while index < bytes.len() {
+            let (slot, len) = KeySlot::try_from_bytes(&bytes[index..])?;
+            slots.push(slot);
+            index += len;
+        }
+
+        Ok(Self { slots })
+    }
+}
@@ -0,0 +1,236 @@
+use crate::error;
+use crate::error::ErrorKind;
+use crate::kdf::{KdfKind, KdfParams, KDF_SALT_SIZE};
+use crate::meta::error::ErrorKind as MetaErrorKind;
+use crate::meta::slots::{
+    KeyFactors, KeySlot, KeySlots, WRAPPED_KEY_SIZE, X25519_KEY_SIZE,
+};
+
+const PASSWORD_SLOT: KeySlot = KeySlot::Password {
+    kdf: KdfParams {
+        kind: KdfKind::Argon2id,
+        m_cost: 0x4C00,
+        t_cost: 2,
+        p_cost: 1,
+    },
+    factors: KeyFactors::Password,
+    salt: [0x5Au8; KDF_SALT_SIZE],
+    wrapped_key: [0xA5u8; WRAPPED_KEY_SIZE],
+};
+
+fn password_slot_fixture() -> Vec<u8> {
+    let mut x = vec![0u8; 4 + 0x50];
+
+    x[0..4].clone_from_slice(&[0x00, 0x00, 0x50, 0x00]);
+    x[4] = KdfKind::Argon2id.to_u8();
+    x[8..0x14].clone_from_slice(&[
+        0x00, 0x4C, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+    ]);
+    x[0x14..0x24].fill(0x5A);
+    x[0x24..].fill(0xA5);
+
+    x
+}
+
+const X25519_SLOT: KeySlot = KeySlot::X25519 {
+    ephemeral_public: [0x3Cu8; X25519_KEY_SIZE],
+    wrapped_key: [0xC3u8; WRAPPED_KEY_SIZE],
+};
+
+fn x25519_slot_fixture() -> Vec<u8> {
+    let mut x = vec![0u8; 4 + 0x50];
+
+    x[0..4].clone_from_slice(&[0x01, 0x00, 0x50, 0x00]);
+    x[4..0x24].fill(0x3C);
+    x[0x24..].fill(0xC3);
+
+    x
+}
+
+fn stanza_slot() -> KeySlot {
+    KeySlot::Stanza {
+        stanza_type: "test".to_string(),
+        args: vec![0x11, 0x22, 0x33],
+        body: vec![0x44; 5],
+    }
+}
+
+fn stanza_slot_fixture() -> Vec<u8> {
+    vec![
+        0x02, 0x00, 0x0F, 0x00, 0x04, b't', b'e', b's', b't', 0x03, 0x00, 0x11,
+        0x22, 0x33, 0x44, 0x44, 0x44, 0x44, 0x44,
+    ]
+}
+
+#[test]
+fn test_to_bytes() -> error::Result<()> {
+    let slots = KeySlots {
+        slots: vec![PASSWORD_SLOT, X25519_SLOT],
+    };
+
+    assert_eq!(slots.len(), 2 * (4 + 0x50));
+    assert_eq!(
+        slots.to_bytes()?,
+        [password_slot_fixture(), x25519_slot_fixture()].concat()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes() -> error::Result<()> {
+    let bytes = [x25519_slot_fixture(), password_slot_fixture()].concat();
+    let slots = KeySlots::try_from_bytes(KeySlots::version(), &bytes)?;
+
+    assert_eq!(
+        slots.slots,
+        vec![X25519_SLOT, PASSWORD_SLOT]
+    );
+
+    let slots = KeySlots::try_from_bytes(KeySlots::version(), &[])?;
+    assert!(slots.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_key_factors() -> error::Result<()> {
+    let mut bytes = password_slot_fixture();
+    bytes[5] = KeyFactors::PasswordKeyfile.to_u8();
+
+    let slots = KeySlots::try_from_bytes(KeySlots::version(), &bytes)?;
+    let KeySlot::Password { factors, .. } = slots.slots[0] else {
+        panic!("Expected the password slot");
+    };
+    assert_eq!(factors, KeyFactors::PasswordKeyfile);
+    assert_eq!(slots.to_bytes()?, bytes);
+
+    Ok(())
+}
+
+#[test]
+fn test_stanza() -> error::Result<()> {
+    let slots = KeySlots {
+        slots: vec![stanza_slot(), PASSWORD_SLOT],
+    };
+    let bytes = [stanza_slot_fixture(), password_slot_fixture()].concat();
+
+    assert_eq!(slots.len(), bytes.len());
+    assert_eq!(slots.to_bytes()?, bytes);
+    assert_eq!(
+        KeySlots::try_from_bytes(KeySlots::version(), &bytes)?,
+        slots
+    );
+
+    // Arguments overflow the body
+    let mut wrong_args_len = stanza_slot_fixture();
+    wrong_args_len[9] = 0x20;
+    let err = KeySlots::try_from_bytes(KeySlots::version(), &wrong_args_len)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.repr,
+        MetaErrorKind::WrongSlotsVecSize
+    );
+
+    let mut empty_type = stanza_slot_fixture();
+    empty_type[4] = 0;
+    let err = KeySlots::try_from_bytes(KeySlots::version(), &empty_type)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.repr,
+        MetaErrorKind::WrongSlotStanzaType
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_from_bytes_err() -> error::Result<()> {
+    let bytes = password_slot_fixture();
+
+    let err = KeySlots::try_from_bytes(KeySlots::version() + 1, &bytes)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.repr,
+        MetaErrorKind::WrongSlotsVersion
+    );
+
+    let err = KeySlots::try_from_bytes(
+        KeySlots::version(),
+        &bytes[..bytes.len() - 1],
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.repr,
+        MetaErrorKind::WrongSlotsVecSize
+    );
+
+    let mut unknown = bytes.clone();
+    unknown[0] = 0xFF;
+    let err = KeySlots::try_from_bytes(KeySlots::version(), &unknown)
+        .err()
+        .unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongSlotKind);
+
+    let mut factors = bytes.clone();
+    factors[5] = 0xFF;
+    let err = KeySlots::try_from_bytes(KeySlots::version(), &factors)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.repr,
+        MetaErrorKind::WrongSlotKeyFactors
+    );
+
+    let mut kdf_costs = bytes.clone();
+    kdf_costs[8..0x0C].clone_from_slice(&u32::MAX.to_le_bytes());
+    let err = KeySlots::try_from_bytes(KeySlots::version(), &kdf_costs)
+        .err()
+        .unwrap();
+    assert_eq!(err.repr, MetaErrorKind::WrongKdfCosts);
+
+    let mut kdf_kind = bytes;
+    for kind in [0xFF, KdfKind::Sha256.to_u8()] {
+        kdf_kind[4] = kind;
+        let err = KeySlots::try_from_bytes(KeySlots::version(), &kdf_kind)
+            .err()
+            .unwrap();
+        assert_eq!(err.repr, MetaErrorKind::WrongRawKdfKind);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_stanza_to_bytes_err() -> error::Result<()> {
+    // The lengths do not fit their fields
+    let slots = [
+        KeySlot::Stanza {
+            stanza_type: "t".repeat(0x100),
+            args: Vec::new(),
+            body: Vec::new(),
+        },
+        KeySlot::Stanza {
+            stanza_type: "test".to_string(),
+            args: vec![0u8; 0x10000],
+            body: Vec::new(),
+        },
+        KeySlot::Stanza {
+            stanza_type: "test".to_string(),
+            args: Vec::new(),
+            body: vec![0u8; 0x10000],
+        },
+    ];
+    for slot in slots {
+        assert_eq!(
+            slot.to_bytes().err().unwrap().kind(),
+            ErrorKind::InvalidArgument
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,257 @@
+#[cfg(test)]
+mod tests;
+
+use crate::error;
+use crate::file::read_full;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::io;
+use std::io::{Read, Write};
+
+/// Reed-Solomon parity of the encrypted stream, see [`ParityEncoder`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ParityParams {
+    /// Cipher text chunks per group
+    pub data_shards: u8,
+
+    /// Parity shards per group. Up to this number of damaged chunks
+    /// of the group are repaired
+    pub parity_shards: u8,
+}
+
+impl ParityParams {
+    pub const DEFAULT_DATA_SHARDS: u8 = 16;
+
+    pub fn new(parity_shards: u8) -> Self {
+        Self {
+            data_shards: Self::DEFAULT_DATA_SHARDS,
+            parity_shards,
+        }
+    }
+
+    /// Reed-Solomon over GF(2^8) is limited by 256 shards
+    pub const fn is_valid(&self) -> bool {
+        self.data_shards > 0
+            && self.parity_shards > 0
+            && self.data_shards as usize + self.parity_shards as usize <= 256
+    }
+
+    fn codec(&self) -> ReedSolomon {
+        ReedSolomon::new(
+            self.data_shards as usize,
+            self.parity_shards as usize,
+        )
+        .expect("Invalid parity params")
+    }
+}
+
+/// Writes the cipher text chunks, followed by the parity shards
+/// of every group of `data_shards` chunks
+///
+/// # Binary structure
+///
+/// The shard is the cipher text chunk, padded by zeros
+/// to the full cipher text chunk size (`shard_len`).
+/// The last group may contain less chunks, the missing ones are zeros.
+///
+/// ```text
+/// C0 C1 .. C(D-1) P0 .. P(R-1) CD C(D+1) .. P0 .. P(R-1)
+/// ```
+pub struct ParityEncoder {
+    codec: ReedSolomon,
+    params: ParityParams,
+    shard_len: usize,
+    shards: Vec<Vec<u8>>,
+}
+
+impl ParityEncoder {
+    pub fn new(params: ParityParams, shard_len: usize) -> Self {
+        Self {
+            codec: params.codec(),
+            params,
+            shard_len,
+            shards: Vec::with_capacity(params.data_shards as usize),
+        }
+    }
+
+    pub fn write_chunk(
+        &mut self,
+        chunk: &[u8],
+        target: &mut dyn Write,
+    ) -> error::Result<()> {
+        target.write_all(chunk)?;
+
+        let mut shard = chunk.to_vec();
+        shard.resize(self.shard_len, 0);
+        self.shards.push(shard);
+        if self.shards.len() == self.params.data_shards as usize {
+            self.write_parity(target)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the parity of the last group
+    pub fn finish(mut self, target: &mut dyn Write) -> error::Result<()> {
+        if !self.shards.is_empty() {
+            self.write_parity(target)?;
+        }
+        Ok(())
+    }
+
+    fn write_parity(&mut self, target: &mut dyn Write) -> error::Result<()> {
+        let shard_count = self.params.data_shards as usize
+            + self.params.parity_shards as usize;
+        self.shards
+            .resize(shard_count, vec![0u8; self.shard_len]);
+        self.codec
+            .encode(&mut self.shards)
+            .map_err(|e| error::Error::new(error::ErrorKind::OtherError, e))?;
+
+        for shard in &self.shards[self.params.data_shards as usize..] {
+            target.write_all(shard)?;
+        }
+        self.shards.clear();
+        Ok(())
+    }
+}
+
+/// Checks the cipher text chunk: its index, whether it is the last one
+/// and its bytes
+pub type ChunkVerifier<'a> = Box<dyn Fn(u32, bool, &[u8]) -> bool + 'a>;
+
+/// Reads the cipher text chunks, written by [`ParityEncoder`],
+/// without the parity. The chunks, which are not verified,
+/// are repaired by the parity of their group, if possible.
+/// Otherwise they are passed as is
+pub struct ParityReader<'a> {
+    source: Box<dyn Read + 'a>,
+    verify: ChunkVerifier<'a>,
+    codec: ReedSolomon,
+    params: ParityParams,
+    shard_len: usize,
+    group: Vec<u8>,
+    next_group: Vec<u8>,
+    next_group_len: usize,
+    chunk_index: u32,
+    output: io::Cursor<Vec<u8>>,
+    repaired: usize,
+}
+
+impl<'a> ParityReader<'a> {
+    pub fn new(
+        source: Box<dyn Read + 'a>,
+        params: ParityParams,
+        shard_len: usize,
+        verify: ChunkVerifier<'a>,
+    ) -> error::Result<Self> {
+        let group_len = shard_len
+            * (params.data_shards as usize + params.parity_shards as usize);
+        let mut result = Self {
+            source,
+            verify,
+            codec: params.codec(),
+            params,
+            shard_len,
+            group: vec![0u8; group_len],
+            next_group: vec![0u8; group_len],
+            next_group_len: 0,
+            chunk_index: 0,
+            output: io::Cursor::new(Vec::new()),
+            repaired: 0,
+        };
+        result.next_group_len = read_full(
+            &mut result.source,
+            &mut result.next_group,
+        )?;
+
+        Ok(result)
+    }
+
+    /// Number of the chunks, repaired so far
+    pub fn repaired(&self) -> usize {
+        self.repaired
+    }
+
+    /// Reads and repairs the next group. Returns false at the end
+    fn next_group(&mut self) -> io::Result<bool> {
+        if self.next_group_len == 0 {
+            return Ok(false);
+        }
+        std::mem::swap(&mut self.group, &mut self.next_group);
+        let group_len = self.next_group_len;
+        self.next_group_len =
+            read_full(&mut self.source, &mut self.next_group)?;
+        let is_last_group = self.next_group_len == 0;
+
+        let parity_len = self.shard_len * self.params.parity_shards as usize;
+        if group_len <= parity_len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Parity of the last chunk group is truncated",
+            ));
+        }
+        let data_len = group_len - parity_len;
+        let data = &self.group[..data_len];
+        let chunk_count = data_len.div_ceil(self.shard_len);
+
+        let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(
+            self.params.data_shards as usize
+                + self.params.parity_shards as usize,
+        );
+        let mut lost = Vec::new();
+        for (i, chunk) in data.chunks(self.shard_len).enumerate() {
+            let index = self.chunk_index + i as u32;
+            let is_last = is_last_group && i + 1 == chunk_count;
+            if (self.verify)(index, is_last, chunk) {
+                let mut shard = chunk.to_vec();
+                shard.resize(self.shard_len, 0);
+                shards.push(Some(shard));
+            } else {
+                lost.push(i);
+                shards.push(None);
+            }
+        }
+        shards.resize(
+            self.params.data_shards as usize,
+            Some(vec![0u8; self.shard_len]),
+        );
+        shards.extend(
+            self.group[data_len..group_len]
+                .chunks(self.shard_len)
+                .map(|shard| Some(shard.to_vec())),
+        );
+
+        let mut output = data.to_vec();
+        if !lost.is_empty()
+            && self
+                .codec
+                .reconstruct_data(&mut shards)
+                .is_ok()
+        {
+            for i in lost {
+                let begin = i * self.shard_len;
+                let end = data_len.min(begin + self.shard_len);
+                let shard = shards[i]
+                    .as_ref()
+                    .expect("Shard is not reconstructed");
+                output[begin..end].copy_from_slice(&shard[..end - begin]);
+                log::info!(target: "parity ParityReader", "Chunk #{} is repaired by the parity", self.chunk_index + i as u32);
+                self.repaired += 1;
+            }
+        }
+
+        self.chunk_index += chunk_count as u32;
+        self.output = io::Cursor::new(output);
+        Ok(true)
+    }
+}
+
+impl Read for ParityReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read_count = self.output.read(buf)?;
+            if read_count > 0 || buf.is_empty() || !self.next_group()? {
+                return Ok(read_count);
+            }
+        }
+    }
+}
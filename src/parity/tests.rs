@@ -0,0 +1,122 @@
+use crate::error;
+use crate::parity::{ParityEncoder, ParityParams, ParityReader};
+use std::io::Read;
+
+const SHARD_LEN: usize = 8;
+
+const PARAMS: ParityParams = ParityParams {
+    data_shards: 4,
+    parity_shards: 2,
+};
+
+/// 10 chunks: 2 full groups and the last group with 2 chunks,
+/// the last chunk is shorter
+fn chunks() -> Vec<Vec<u8>> {
+    (0..10u8)
+        .map(|i| {
+            let len = if i == 9 { 3 } else { SHARD_LEN };
+            vec![i + 1; len]
+        })
+        .collect()
+}
+
+fn encode(chunks: &[Vec<u8>]) -> error::Result<Vec<u8>> {
+    let mut encoder = ParityEncoder::new(PARAMS, SHARD_LEN);
+    let mut result = Vec::new();
+    for chunk in chunks {
+        encoder.write_chunk(chunk, &mut result)?;
+    }
+    encoder.finish(&mut result)?;
+    Ok(result)
+}
+
+/// Reads the chunks, the chunk is verified by its content
+fn decode(bytes: Vec<u8>) -> error::Result<(Vec<u8>, usize)> {
+    let expected = chunks();
+    let verify = move |index: u32, is_last: bool, chunk: &[u8]| {
+        assert_eq!(is_last, index == 9);
+        chunk == expected[index as usize]
+    };
+
+    let mut reader = ParityReader::new(
+        Box::from(std::io::Cursor::new(bytes)),
+        PARAMS,
+        SHARD_LEN,
+        Box::from(verify),
+    )?;
+    let mut result = Vec::new();
+    reader.read_to_end(&mut result)?;
+    Ok((result, reader.repaired()))
+}
+
+#[test]
+fn test_layout() -> error::Result<()> {
+    let bytes = encode(&chunks())?;
+    // 9 full chunks, the short one and 3 groups of the parity
+    assert_eq!(
+        bytes.len(),
+        9 * SHARD_LEN + 3 + 3 * 2 * SHARD_LEN
+    );
+    assert_eq!(
+        &bytes[..4 * SHARD_LEN],
+        &chunks()[..4].concat()[..]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_repair() -> error::Result<()> {
+    let mut bytes = encode(&chunks())?;
+    // Two chunks of the first group
+    bytes[1] ^= 0xFF;
+    bytes[3 * SHARD_LEN] ^= 0xFF;
+    // The short last chunk
+    let last_chunk = 4 * SHARD_LEN
+        + 2 * SHARD_LEN
+        + 4 * SHARD_LEN
+        + 2 * SHARD_LEN
+        + SHARD_LEN;
+    bytes[last_chunk + 2] ^= 0xFF;
+
+    let (result, repaired) = decode(bytes)?;
+    assert_eq!(result, chunks().concat());
+    assert_eq!(repaired, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_too_many() -> error::Result<()> {
+    let mut bytes = encode(&chunks())?;
+    for chunk in 0..3 {
+        bytes[chunk * SHARD_LEN] ^= 0xFF;
+    }
+
+    // The damaged chunks are passed as is
+    let (result, repaired) = decode(bytes)?;
+    assert_eq!(repaired, 0);
+    assert_eq!(
+        &result[3 * SHARD_LEN..],
+        &chunks().concat()[3 * SHARD_LEN..]
+    );
+    assert_ne!(&result[..SHARD_LEN], &chunks()[0][..]);
+
+    Ok(())
+}
+
+#[test]
+fn test_params() {
+    assert!(ParityParams::new(1).is_valid());
+    assert!(!ParityParams::new(0).is_valid());
+    assert!(ParityParams {
+        data_shards: 200,
+        parity_shards: 56
+    }
+    .is_valid());
+    assert!(!ParityParams {
+        data_shards: 200,
+        parity_shards: 57
+    }
+    .is_valid());
+}
@@ -0,0 +1,226 @@
+mod ssh;
+#[cfg(test)]
+mod tests;
+
+pub use ssh::{SshEd25519Identity, SshEd25519Recipient};
+
+use crate::error;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use core::fmt;
+use rand::rngs::OsRng;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Public key, the file is encrypted to
+///
+/// # Text format
+///
+/// The prefix `rfe-x25519:` followed by 32 key bytes in the standard Base64:
+///
+/// ```text
+/// rfe-x25519:3x6Lhbd6jCgZ0tHk1kXkXQ2bgbo6ENo9J+hdVx5bH2U=
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct X25519Recipient(pub PublicKey);
+
+/// Private key, which decrypts the files, encrypted to its recipient
+///
+/// # Text format
+///
+/// The prefix `RFE-X25519-SECRET-KEY:` followed by 32 key bytes
+/// in the standard Base64
+///
+/// # Identity file
+///
+/// Identity per line. Empty lines and lines, started by `#`, are ignored.
+/// The file, created by `keygen`, contains its recipient in the comment:
+///
+/// ```text
+/// # Recipient: rfe-x25519:3x6Lhbd6jCgZ0tHk1kXkXQ2bgbo6ENo9J+hdVx5bH2U=
+/// RFE-X25519-SECRET-KEY:wGgE1pXn0O2tJ4XJkEdFpbl2d7QLZ5ieCS0FUf0MD04=
+/// ```
+///
+/// The recipients file has the same structure
+#[derive(Clone)]
+pub struct X25519Identity(pub StaticSecret);
+
+impl X25519Recipient {
+    pub const PREFIX: &'static str = "rfe-x25519:";
+}
+
+impl X25519Identity {
+    pub const PREFIX: &'static str = "RFE-X25519-SECRET-KEY:";
+
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn recipient(&self) -> X25519Recipient {
+        X25519Recipient(PublicKey::from(&self.0))
+    }
+
+    /// Identity file content, see [`X25519Identity`]
+    pub fn to_file_content(&self) -> String {
+        format!(
+            "# Created by rust-file-encryptor, keep it secret\n# Recipient: {}\n{}\n",
+            self.recipient(),
+            self
+        )
+    }
+}
+
+impl fmt::Debug for X25519Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("X25519Identity")
+            .field(&self.recipient())
+            .finish()
+    }
+}
+
+impl fmt::Display for X25519Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            Self::PREFIX,
+            BASE64.encode(self.0.as_bytes())
+        )
+    }
+}
+
+impl fmt::Display for X25519Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            Self::PREFIX,
+            BASE64.encode(self.0.as_bytes())
+        )
+    }
+}
+
+/// Decodes 32 Base64 encoded bytes after the prefix
+fn decode_key(s: &str, prefix: &str) -> error::Result<[u8; 32]> {
+    let encoded = s
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| {
+            error::Error::new(
+                error::ErrorKind::InvalidArgument,
+                format!("Expected the key, started by '{prefix}'"),
+            )
+        })?;
+
+    let bytes = BASE64.decode(encoded).map_err(|e| {
+        error::Error::new(
+            error::ErrorKind::InvalidArgument,
+            format!("Invalid key encoding: {e}"),
+        )
+    })?;
+    bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| {
+            error::Error::new(
+                error::ErrorKind::InvalidArgument,
+                format!(
+                    "Invalid key length, expected: 32, real: {}",
+                    bytes.len()
+                ),
+            )
+        })
+}
+
+impl FromStr for X25519Recipient {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(PublicKey::from(decode_key(
+            s,
+            Self::PREFIX,
+        )?)))
+    }
+}
+
+impl FromStr for X25519Identity {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(StaticSecret::from(decode_key(
+            s,
+            Self::PREFIX,
+        )?)))
+    }
+}
+
+/// Parses the key per line, skipping empty lines and comments
+pub fn parse_keys<T>(text: &str) -> error::Result<Vec<T>>
+where
+    T: FromStr<Err = error::Error>,
+{
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+/// Reads the identity or recipients file, see [`X25519Identity`]
+pub fn read_keys_file<T>(path: &Path) -> error::Result<Vec<T>>
+where
+    T: FromStr<Err = error::Error>,
+{
+    let keys = parse_keys(&fs::read_to_string(path)?)?;
+    if keys.is_empty() {
+        return Err(error::Error::new(
+            error::ErrorKind::InvalidArgument,
+            format!(
+                "No keys in the file '{}'",
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(keys)
+}
+
+/// Generates the identity and writes it into `path`, and its recipient
+/// into `path.pub`. Existing files are not overwritten.
+/// Returns the recipient file path
+pub fn write_new_identity(path: &Path) -> error::Result<PathBuf> {
+    let identity = X25519Identity::generate();
+
+    let mut pub_path = path.as_os_str().to_os_string();
+    pub_path.push(".pub");
+    let pub_path = PathBuf::from(pub_path);
+    for path in [path, pub_path.as_path()] {
+        if path.exists() {
+            return Err(error::Error::new_file_already_exist(
+                path.to_str().unwrap_or(""),
+            ));
+        }
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(path)?
+        .write_all(identity.to_file_content().as_bytes())?;
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&pub_path)?
+        .write_all(format!("{}\n", identity.recipient()).as_bytes())?;
+
+    Ok(pub_path)
+}
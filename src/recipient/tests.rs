@@ -0,0 +1,148 @@
+use crate::error;
+use crate::error::ErrorKind;
+use crate::recipient::{
+    parse_keys, SshEd25519Identity, SshEd25519Recipient, X25519Identity,
+    X25519Recipient,
+};
+use x25519_dalek::PublicKey;
+
+const SSH_PUBLIC: &str = include_str!("../../tests/general/ssh/id_ed25519.pub");
+const SSH_PRIVATE: &str = include_str!("../../tests/general/ssh/id_ed25519");
+const SSH_PRIVATE_ENCRYPTED: &str =
+    include_str!("../../tests/general/ssh/id_ed25519_encrypted");
+const SSH_RSA_PUBLIC: &str = include_str!("../../tests/general/ssh/id_rsa.pub");
+
+#[test]
+fn test_roundtrip() -> error::Result<()> {
+    let identity = X25519Identity::generate();
+    let recipient = identity.recipient();
+
+    let parsed: X25519Recipient = recipient.to_string().parse()?;
+    assert_eq!(parsed, recipient);
+    assert!(recipient
+        .to_string()
+        .starts_with(X25519Recipient::PREFIX));
+
+    let parsed: X25519Identity = identity.to_string().parse()?;
+    assert_eq!(parsed.recipient(), recipient);
+
+    Ok(())
+}
+
+#[test]
+fn test_identity_file() -> error::Result<()> {
+    let identity = X25519Identity::generate();
+    let content = identity.to_file_content();
+
+    let identities = parse_keys::<X25519Identity>(&content)?;
+    assert_eq!(identities.len(), 1);
+    assert_eq!(
+        identities[0].recipient(),
+        identity.recipient()
+    );
+
+    let content = format!(
+        "\n# Comment\n{}\n\n  {}  \n",
+        identity.recipient(),
+        X25519Identity::generate().recipient()
+    );
+    assert_eq!(
+        parse_keys::<X25519Recipient>(&content)?.len(),
+        2
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_err() -> error::Result<()> {
+    let identity = X25519Identity::generate();
+
+    for text in [
+        // Identity is not a recipient
+        identity.to_string(),
+        // Wrong length
+        format!("{}AAAA", X25519Recipient::PREFIX),
+        // Wrong encoding
+        format!("{}!!!!", X25519Recipient::PREFIX),
+    ] {
+        let result = text.parse::<X25519Recipient>();
+        assert_eq!(
+            result.err().unwrap().kind(),
+            ErrorKind::InvalidArgument
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_ssh_ed25519() -> error::Result<()> {
+    let recipient: SshEd25519Recipient = SSH_PUBLIC.parse()?;
+    let identity: SshEd25519Identity = SSH_PRIVATE.parse()?;
+    assert_eq!(identity.recipient(), recipient);
+
+    // The comment is dropped
+    assert_eq!(
+        recipient.to_string(),
+        SSH_PUBLIC.rsplit_once(' ').unwrap().0
+    );
+    assert_eq!(
+        recipient
+            .to_string()
+            .parse::<SshEd25519Recipient>()?,
+        recipient
+    );
+
+    // Converted keys are the X25519 key pair
+    assert_eq!(
+        PublicKey::from(&identity.to_x25519()),
+        recipient.to_x25519()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ssh_ed25519_err() -> error::Result<()> {
+    for result in [
+        SSH_PRIVATE_ENCRYPTED
+            .parse::<SshEd25519Identity>()
+            .err(),
+        SSH_PRIVATE
+            .replace(
+                "b3BlbnNzaC1rZXktdjEA",
+                "b3BlbnNzaC1rZXktdjIA",
+            )
+            .parse::<SshEd25519Identity>()
+            .err(),
+        SSH_PUBLIC
+            .parse::<SshEd25519Identity>()
+            .err(),
+    ] {
+        assert_eq!(
+            result.unwrap().kind(),
+            ErrorKind::InvalidArgument
+        );
+    }
+
+    for result in [
+        SSH_RSA_PUBLIC
+            .parse::<SshEd25519Recipient>()
+            .err(),
+        SSH_RSA_PUBLIC
+            .replacen("ssh-rsa", "ssh-ed25519", 1)
+            .parse::<SshEd25519Recipient>()
+            .err(),
+        "ssh-ed25519"
+            .parse::<SshEd25519Recipient>()
+            .err(),
+    ] {
+        assert_eq!(
+            result.unwrap().kind(),
+            ErrorKind::InvalidArgument
+        );
+    }
+
+    Ok(())
+}
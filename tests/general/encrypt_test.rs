@@ -1,13 +1,45 @@
 use assert_fs::prelude::*;
-use file_encryptor;
+use file_encryptor::bulk::{process_files, BulkOptions, BulkReport};
+use file_encryptor::cipher::kind::{select_cipher, StreamParams};
+use file_encryptor::cipher::nonce::{generate_nonce, RandomNonceGenerator};
+use file_encryptor::cipher::CipherKind;
+use file_encryptor::encryption::{
+    add_key_slots, add_raw_meta, encrypted_file_path, get_key_slots,
+    get_raw_meta, raw_meta_to_bytes, try_inspect, DecryptionOptions,
+    EncryptionOptions, FilenamePolicy,
+};
 use file_encryptor::error;
-use std::fs::File;
+use file_encryptor::error::{DecryptContext, ErrorKind};
+use file_encryptor::file::{CheckedReader, RestorePolicy};
+use file_encryptor::kdf::{KdfParams, KDF_SALT_SIZE};
+use file_encryptor::keyfile::Keyfile;
+use file_encryptor::keyslot::{
+    generate_data_key, key_check_value, new_slot, Identity, Recipient,
+};
+use file_encryptor::meta::enc::{
+    ContentDigest, EncryptedMeta, ExtendedAttribute, FileAttributes,
+};
+use file_encryptor::meta::header::MetaHeader;
+use file_encryptor::meta::raw::RawMeta;
+use file_encryptor::meta::slots::KeySlots;
+use file_encryptor::parity::ParityParams;
+use file_encryptor::recipient::{
+    read_keys_file, write_new_identity, SshEd25519Identity, X25519Identity,
+    X25519Recipient,
+};
+use std::fs::{File, FileTimes};
 use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 
-const ROOT_FILE_DIR: &'static str = "tests/general/";
-const TEMP_FILE_DIR: &'static str = "target/tmp";
+const ROOT_FILE_DIR: &str = "tests/general/";
+const LEGACY_FILE_DIR: &str = "tests/general/legacy/";
+const SSH_FILE_DIR: &str = "tests/general/ssh/";
+const TEMP_FILE_DIR: &str = "target/tmp";
+const KEY: &str = "amongus";
 
 fn setup() -> io::Result<()> {
     let temp_dir_path = PathBuf::from(".").join(TEMP_FILE_DIR);
@@ -16,52 +48,54 @@ fn setup() -> io::Result<()> {
     Ok(())
 }
 
-// TODO: remove copypasted tests
+fn read_all(path: &Path) -> Vec<u8> {
+    let mut buffer = Vec::<u8>::with_capacity(512);
+    File::open(path)
+        .expect("")
+        .read_to_end(&mut buffer)
+        .expect("");
+    buffer
+}
 
-#[test]
-fn test_common() -> error::Result<()> {
+fn test_roundtrip(
+    name: &str,
+    options: &EncryptionOptions,
+) -> error::Result<()> {
     setup()?;
 
     let temp = assert_fs::TempDir::new()?;
     temp.copy_from(ROOT_FILE_DIR, &["*.txt"])
         .expect("");
 
-    let raw_file = temp.child("to_enc.txt");
-    let key_hash = file_encryptor::get_hash("amongus").expect("");
-    file_encryptor::try_encrypt(
-        (&raw_file).path(),
-        None,
-        key_hash.clone(),
-    )?;
+    let raw_file = temp.child(format!("{name}.txt"));
+    file_encryptor::try_encrypt(raw_file.path(), None, KEY, options)?;
 
     fs::remove_file(raw_file.path()).expect("");
 
-    let enc_file = temp.child("to_enc.enc");
+    let enc_file = temp.child(format!("{name}.txt.enc"));
     let enc_file_path = enc_file.path();
     println!("enc_file_path {enc_file_path:?}");
 
     fs::copy(
         enc_file_path,
-        PathBuf::from(TEMP_FILE_DIR).join("enc.bin"),
+        PathBuf::from(TEMP_FILE_DIR).join(format!("{name}.bin")),
     )?;
 
-    file_encryptor::try_decrypt(enc_file_path, key_hash.clone(), false)?;
+    let raw_meta = get_raw_meta(&mut File::open(enc_file_path)?)?;
+    assert_eq!(
+        raw_meta.cipher_kind,
+        options.cipher_kind
+    );
+    assert_eq!(raw_meta.chunk_size, options.chunk_size);
 
-    let mut buffer = Vec::<u8>::with_capacity(512);
-    File::open(
-        Path::new(".")
-            .join(ROOT_FILE_DIR)
-            .join("to_enc.txt"),
-    )
-    .expect("")
-    .read_to_end(&mut buffer)
-    .expect("");
+    file_encryptor::try_decrypt(enc_file_path, KEY, false)?;
 
-    let mut expected_buff = Vec::<u8>::with_capacity(512);
-    File::open(temp.child("to_enc.txt").path())
-        .expect("")
-        .read_to_end(&mut expected_buff)
-        .expect("");
+    let buffer = read_all(
+        &Path::new(".")
+            .join(ROOT_FILE_DIR)
+            .join(format!("{name}.txt")),
+    );
+    let expected_buff = read_all(raw_file.path());
 
     assert_eq!(buffer, expected_buff);
 
@@ -70,106 +104,2047 @@ fn test_common() -> error::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_common() -> error::Result<()> {
+    test_roundtrip("to_enc", &EncryptionOptions::default())
+}
+
 #[test]
 fn test_common_large() -> error::Result<()> {
-    setup()?;
+    test_roundtrip(
+        "to_enc_large",
+        &EncryptionOptions::default(),
+    )
+}
+
+#[test]
+fn test_common_small() -> error::Result<()> {
+    test_roundtrip(
+        "to_enc_small",
+        &EncryptionOptions::default(),
+    )
+}
+
+#[test]
+fn test_aesgcm() -> error::Result<()> {
+    let options = EncryptionOptions {
+        cipher_kind: CipherKind::AesGcm,
+        ..EncryptionOptions::default()
+    };
+
+    test_roundtrip("to_enc", &options)?;
+    test_roundtrip("to_enc_large", &options)?;
+    test_roundtrip("to_enc_small", &options)
+}
 
+#[test]
+fn test_wrong_key() -> error::Result<()> {
     let temp = assert_fs::TempDir::new()?;
-    temp.copy_from(ROOT_FILE_DIR, &["*.txt"])
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
         .expect("");
 
-    let raw_file = temp.child("to_enc_large.txt");
-    let key_hash = file_encryptor::get_hash("amongus").expect("");
+    let raw_file = temp.child("to_enc.txt");
     file_encryptor::try_encrypt(
-        (&raw_file).path(),
+        raw_file.path(),
         None,
-        key_hash.clone(),
+        KEY,
+        &EncryptionOptions::default(),
     )?;
-
     fs::remove_file(raw_file.path()).expect("");
 
-    let enc_file = temp.child("to_enc_large.enc");
-    let enc_file_path = enc_file.path();
-    println!("enc_file_path {enc_file_path:?}");
+    let enc_file = temp.child("to_enc.txt.enc");
+    assert_eq!(
+        file_encryptor::try_decrypt(enc_file.path(), "amogus", false)
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::WrongPassword
+    );
 
-    fs::copy(
-        enc_file_path,
-        PathBuf::from(TEMP_FILE_DIR).join("enc_large.bin"),
-    )?;
+    // The key is checked before the target is created
+    let names: Vec<_> = fs::read_dir(temp.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<_>>()?;
+    assert_eq!(names, ["to_enc.txt.enc"]);
 
-    file_encryptor::try_decrypt(enc_file_path, key_hash.clone(), false)?;
+    Ok(())
+}
 
-    let mut buffer = Vec::<u8>::with_capacity(512);
-    File::open(
-        Path::new(".")
-            .join(ROOT_FILE_DIR)
-            .join("to_enc_large.txt"),
+fn test_legacy(name: &str) -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(
+        LEGACY_FILE_DIR,
+        &[format!("{name}.enc")],
     )
-    .expect("")
-    .read_to_end(&mut buffer)
     .expect("");
 
-    let mut expected_buff = Vec::<u8>::with_capacity(512);
-    File::open(temp.child("to_enc_large.txt").path())
-        .expect("")
-        .read_to_end(&mut expected_buff)
+    let enc_file = temp.child(format!("{name}.enc"));
+    file_encryptor::try_decrypt(enc_file.path(), KEY, false)?;
+
+    assert_eq!(
+        read_all(temp.child(format!("{name}.txt")).path()),
+        read_all(
+            &Path::new(".")
+                .join(LEGACY_FILE_DIR)
+                .join("legacy.txt.expected")
+        )
+    );
+
+    Ok(())
+}
+
+/// Files, created by the version 0.2.1 (SHA-256 key hash)
+#[test]
+fn test_legacy_v1() -> error::Result<()> {
+    test_legacy("legacy_v1")
+}
+
+/// Argon2id key, the stream is not finalized
+#[test]
+fn test_legacy_v2() -> error::Result<()> {
+    test_legacy("legacy_v2")
+}
+
+/// MetaHeader (16 bytes) + the TLV records of "x.txt" (12 bytes),
+/// the content digest (46 bytes) and the attributes (46 bytes)
+const TWO_CHUNKS_META_LEN: usize = 16 + 12 + 46 + 46;
+const TWO_CHUNKS_CONTENT_LEN: usize = 1000 - TWO_CHUNKS_META_LEN;
+
+/// Encrypts the file, which plain text (with the meta) is exactly 2 chunks
+/// of 500 bytes
+fn encrypt_two_chunks(temp: &assert_fs::TempDir) -> error::Result<PathBuf> {
+    let raw_file = temp.child("x.txt");
+    raw_file
+        .write_binary(&[0x42u8; TWO_CHUNKS_CONTENT_LEN])
         .expect("");
 
-    assert_eq!(buffer, expected_buff);
+    let options = EncryptionOptions {
+        chunk_size: 500,
+        ..EncryptionOptions::default()
+    };
+    file_encryptor::try_encrypt(raw_file.path(), None, KEY, &options)?;
+    fs::remove_file(raw_file.path()).expect("");
 
-    // temp.close()?;
+    Ok(temp
+        .child("x.txt.enc")
+        .path()
+        .to_path_buf())
+}
+
+#[test]
+fn test_truncated() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_two_chunks(&temp)?;
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(&enc_file_path)?;
+    file.set_len(file.metadata()?.len() - 516)?;
+
+    let result = file_encryptor::try_decrypt(&enc_file_path, KEY, false);
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::StreamTruncated
+    );
 
     Ok(())
 }
 
 #[test]
-fn test_common_small() -> error::Result<()> {
-    setup()?;
+fn test_appended_chunk() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_two_chunks(&temp)?;
 
+    let mut content = read_all(&enc_file_path);
+    let first_chunk_begin = content.len() - 2 * 516;
+    let first_chunk =
+        content[first_chunk_begin..first_chunk_begin + 516].to_vec();
+    content.extend(first_chunk);
+    fs::write(&enc_file_path, content)?;
+
+    let result = file_encryptor::try_decrypt(&enc_file_path, KEY, false);
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::StreamTruncated
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_corrupted_chunk() -> error::Result<()> {
     let temp = assert_fs::TempDir::new()?;
-    temp.copy_from(ROOT_FILE_DIR, &["*.txt"])
+    let enc_file_path = encrypt_two_chunks(&temp)?;
+
+    let mut content = read_all(&enc_file_path);
+    let last_chunk_begin = content.len() - 516;
+    content[last_chunk_begin + 10] ^= 0x01;
+    fs::write(&enc_file_path, content)?;
+
+    let err = file_encryptor::try_decrypt(&enc_file_path, KEY, false)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::ChunkCorrupted);
+    assert_eq!(
+        err.decrypt_context(),
+        Some(&DecryptContext {
+            file_path: Some(enc_file_path.clone()),
+            chunk_index: Some(1),
+            byte_offset: Some(last_chunk_begin as u64),
+            header_authenticated: Some(true),
+        })
+    );
+    assert!(!temp
+        .child("x.enc.tmp-enc")
+        .path()
+        .exists());
+
+    Ok(())
+}
+
+/// Flips a byte of the chunk of the two chunks file, see [`encrypt_two_chunks`]
+fn corrupt_chunk(enc_file_path: &Path, chunk_index: usize) -> io::Result<()> {
+    let mut content = read_all(enc_file_path);
+    let chunk_begin = content.len() - (2 - chunk_index) * 516;
+    content[chunk_begin + 10] ^= 0x01;
+    fs::write(enc_file_path, content)
+}
+
+#[test]
+fn test_recover() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_two_chunks(&temp)?;
+    corrupt_chunk(&enc_file_path, 1)?;
+
+    let report = file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        true,
+    )?;
+    // The second chunk is 500..1000 of the plain text stream
+    let lost_begin = 500 - TWO_CHUNKS_META_LEN as u64;
+    assert_eq!(report.chunk_count, 2);
+    assert_eq!(report.lost_chunks, [1]);
+    assert_eq!(
+        report.lost_ranges,
+        vec![lost_begin..TWO_CHUNKS_CONTENT_LEN as u64]
+    );
+    assert!(!report.truncated);
+    assert_eq!(
+        report
+            .enc_meta
+            .unwrap()
+            .filename_lossy(),
+        "x.txt"
+    );
+
+    let mut expected = vec![0x42u8; lost_begin as usize];
+    expected.resize(TWO_CHUNKS_CONTENT_LEN, 0);
+    assert_eq!(
+        read_all(temp.child("x.txt").path()),
+        expected
+    );
+    assert!(enc_file_path.exists());
+
+    // The lost content is skipped
+    fs::remove_file(temp.child("x.txt").path())?;
+    file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        false,
+    )?;
+    assert_eq!(
+        read_all(temp.child("x.txt").path()),
+        vec![0x42u8; lost_begin as usize]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_lost_meta() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_two_chunks(&temp)?;
+    corrupt_chunk(&enc_file_path, 0)?;
+
+    let report = file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        true,
+    )?;
+    // The meta length is stored in the RawMeta,
+    // so the content of the second chunk is not shifted
+    let lost_end = 500 - TWO_CHUNKS_META_LEN as u64;
+    assert_eq!(report.lost_chunks, [0]);
+    assert_eq!(report.lost_ranges, vec![0..lost_end]);
+    assert_eq!(report.enc_meta, None);
+
+    let recovered_path = temp.child("x.txt.recovered");
+    let mut expected = vec![0u8; lost_end as usize];
+    expected.resize(TWO_CHUNKS_CONTENT_LEN, 0x42);
+    assert_eq!(
+        read_all(recovered_path.path()),
+        expected
+    );
+
+    // The lost content is skipped
+    fs::remove_file(recovered_path.path())?;
+    file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        false,
+    )?;
+    assert_eq!(
+        read_all(recovered_path.path()),
+        vec![0x42u8; 500]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_complete() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_common(&temp)?;
+
+    let report = file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        true,
+    )?;
+    assert!(report.is_complete());
+    assert_eq!(
+        read_all(temp.child("to_enc.txt").path()),
+        read_all(
+            &Path::new(".")
+                .join(ROOT_FILE_DIR)
+                .join("to_enc.txt")
+        )
+    );
+
+    Ok(())
+}
+
+/// Argon2id key, the stream is finalized, the header is not authenticated
+#[test]
+fn test_legacy_v3() -> error::Result<()> {
+    test_legacy("legacy_v3")
+}
+
+/// The header is authenticated, the chunk size is not stored (500 bytes)
+#[test]
+fn test_legacy_v4() -> error::Result<()> {
+    test_legacy("legacy_v4")
+}
+
+/// The chunk size is stored, the key is derived directly from the password
+#[test]
+fn test_legacy_v5() -> error::Result<()> {
+    test_legacy("legacy_v5")
+}
+
+/// The key is wrapped by the key slots, the key check value is not stored
+#[test]
+fn test_legacy_v6() -> error::Result<()> {
+    test_legacy("legacy_v6")
+}
+
+/// The key check value is stored, the flags are reserved
+#[test]
+fn test_legacy_v7() -> error::Result<()> {
+    test_legacy("legacy_v7")
+}
+
+/// Encrypts 40 chunks of 500 bytes with the parity
+fn encrypt_with_parity(
+    temp: &assert_fs::TempDir,
+    parity_shards: u8,
+) -> error::Result<PathBuf> {
+    let raw_file = temp.child("x.txt");
+    let content: Vec<u8> = (0..20000u32).map(|i| i as u8).collect();
+    raw_file
+        .write_binary(&content)
         .expect("");
 
-    let raw_file = temp.child("to_enc_small.txt");
-    let key_hash = file_encryptor::get_hash("amongus").expect("");
-    file_encryptor::try_encrypt(
-        (&raw_file).path(),
+    let options = EncryptionOptions {
+        chunk_size: 500,
+        parity: Some(ParityParams::new(parity_shards)),
+        ..EncryptionOptions::default()
+    };
+    file_encryptor::try_encrypt(raw_file.path(), None, KEY, &options)?;
+    fs::remove_file(raw_file.path()).expect("");
+
+    Ok(temp
+        .child("x.txt.enc")
+        .path()
+        .to_path_buf())
+}
+
+#[test]
+fn test_parity() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_parity(&temp, 2)?;
+    let inspection = try_inspect(&enc_file_path)?;
+    assert_eq!(
+        inspection.raw_meta.parity,
+        Some(ParityParams::new(2))
+    );
+    let stream_offset =
+        read_all(&enc_file_path).len() - read_stream(&enc_file_path)?.len();
+
+    // Two chunks of the first group and one of the last,
+    // the parity of the second group
+    let mut content = read_all(&enc_file_path);
+    for chunk in [0usize, 5, 37] {
+        let group = chunk / 16;
+        let index = stream_offset + (chunk + group * 2) * 516;
+        content[index..index + 100].fill(0);
+    }
+    content[stream_offset + 34 * 516 + 10] ^= 0x01;
+    fs::write(&enc_file_path, content)?;
+
+    let expected: Vec<u8> = (0..20000u32).map(|i| i as u8).collect();
+    file_encryptor::try_decrypt(&enc_file_path, KEY, false)?;
+    assert_eq!(
+        read_all(temp.child("x.txt").path()),
+        expected
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_parity_too_many() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_parity(&temp, 1)?;
+    let stream_offset =
+        read_all(&enc_file_path).len() - read_stream(&enc_file_path)?.len();
+
+    let mut content = read_all(&enc_file_path);
+    for chunk in [3usize, 4] {
+        content[stream_offset + chunk * 516] ^= 0x01;
+    }
+    fs::write(&enc_file_path, content)?;
+
+    let err = file_encryptor::try_decrypt(&enc_file_path, KEY, false)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::ChunkCorrupted);
+    assert_eq!(
+        err.decrypt_context()
+            .unwrap()
+            .chunk_index,
+        Some(3)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_verify() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_common(&temp)?;
+    let content = read_all(&PathBuf::from(ROOT_FILE_DIR).join("to_enc.txt"));
+
+    let enc_meta = file_encryptor::try_verify_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+    )?;
+    assert_eq!(enc_meta.filename, b"to_enc.txt");
+    assert_eq!(
+        enc_meta.content,
+        Some(ContentDigest::from_reader(
+            &mut content.as_slice()
+        )?)
+    );
+    // Nothing is written
+    assert_eq!(fs::read_dir(temp.path())?.count(), 1);
+
+    Ok(())
+}
+
+/// Encrypts the content like `try_encrypt`, but with the given EncryptedMeta
+fn encrypt_with_enc_meta(
+    enc_file_path: &Path,
+    content: &[u8],
+    enc_meta: &EncryptedMeta,
+) -> error::Result<()> {
+    let cipher_kind = CipherKind::default();
+    let data_key = generate_data_key();
+    let raw_meta = RawMeta {
+        version: RawMeta::latest_version(),
+        cipher_kind,
+        nonce: generate_nonce(cipher_kind, &mut RandomNonceGenerator),
+        kdf: None,
+        salt: [0u8; KDF_SALT_SIZE],
+        chunk_size: RawMeta::DEFAULT_CHUNK_SIZE,
+        key_check: Some(key_check_value(&data_key)),
+        parity: None,
+        meta_len: Some((MetaHeader::size() + enc_meta.len()) as u32),
+    };
+    let raw_meta_bytes = raw_meta_to_bytes(&raw_meta);
+    let key_slots = KeySlots {
+        slots: vec![new_slot(
+            cipher_kind,
+            &raw_meta_bytes,
+            &Recipient::Password(KEY.to_string()),
+            &KdfParams::default(),
+            &data_key,
+        )?],
+    };
+
+    let mut target = File::create(enc_file_path)?;
+    add_raw_meta(&raw_meta, &mut target)?;
+    add_key_slots(&key_slots, &mut target)?;
+    select_cipher(
+        cipher_kind,
+        StreamParams::new(&raw_meta, &raw_meta_bytes),
         None,
-        key_hash.clone(),
+    )
+    .encrypt(
+        Box::new(io::Cursor::new(content.to_vec())),
+        Box::new(target),
+        &data_key,
+        &raw_meta.nonce,
+        enc_meta,
+    )
+}
+
+#[test]
+fn test_digest_mismatch() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = temp.child("x.enc").path().to_path_buf();
+    let content = b"the content";
+    let mut digest = ContentDigest::from_reader(&mut content.as_slice())?;
+    digest.sha256[0] ^= 0x01;
+    encrypt_with_enc_meta(
+        &enc_file_path,
+        content,
+        &EncryptedMeta {
+            filename: b"x.txt".to_vec(),
+            content: Some(digest),
+            attributes: FileAttributes::default(),
+            xattrs: Vec::new(),
+            is_archive: false,
+        },
     )?;
 
-    fs::remove_file(raw_file.path()).expect("");
+    let identities = [Identity::Password(KEY.to_string())];
+    let err = file_encryptor::try_verify_with(&enc_file_path, &identities)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::DigestMismatch);
 
-    let enc_file = temp.child("to_enc_small.enc");
-    let enc_file_path = enc_file.path();
-    println!("enc_file_path {enc_file_path:?}");
+    let err = file_encryptor::try_decrypt(&enc_file_path, KEY, false)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::DigestMismatch);
+    assert!(!temp.child("x.txt").path().exists());
+    assert!(!temp
+        .child("x.enc.tmp-enc")
+        .path()
+        .exists());
 
-    fs::copy(
-        enc_file_path,
-        PathBuf::from(TEMP_FILE_DIR).join("enc_small.bin"),
+    Ok(())
+}
+
+/// Files without the content digest are still decrypted
+#[test]
+fn test_no_digest() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = temp.child("x.enc").path().to_path_buf();
+    encrypt_with_enc_meta(
+        &enc_file_path,
+        b"the content",
+        &EncryptedMeta {
+            filename: b"x.txt".to_vec(),
+            content: None,
+            attributes: FileAttributes::default(),
+            xattrs: Vec::new(),
+            is_archive: false,
+        },
     )?;
 
-    file_encryptor::try_decrypt(enc_file_path, key_hash.clone(), false)?;
+    file_encryptor::try_decrypt(&enc_file_path, KEY, false)?;
+    assert_eq!(
+        read_all(temp.child("x.txt").path()),
+        b"the content"
+    );
 
-    let mut buffer = Vec::<u8>::with_capacity(512);
-    File::open(
-        Path::new(".")
-            .join(ROOT_FILE_DIR)
-            .join("to_enc_small.txt"),
+    Ok(())
+}
+
+/// Encrypts the file with the malicious original filename
+/// into the `sub` directory of the temp one
+fn encrypt_with_filename(
+    temp: &assert_fs::TempDir,
+    filename: &[u8],
+) -> error::Result<PathBuf> {
+    let sub_dir = temp.child("sub");
+    fs::create_dir_all(sub_dir.path())?;
+    let enc_file_path = sub_dir.path().join("x.enc");
+    encrypt_with_enc_meta(
+        &enc_file_path,
+        b"the content",
+        &EncryptedMeta {
+            filename: filename.to_vec(),
+            content: None,
+            attributes: FileAttributes::default(),
+            xattrs: Vec::new(),
+            is_archive: false,
+        },
+    )?;
+
+    Ok(enc_file_path)
+}
+
+fn decrypt_with_policy(
+    enc_file_path: &Path,
+    filename_policy: FilenamePolicy,
+) -> error::Result<()> {
+    file_encryptor::try_decrypt_with_options(
+        enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        &DecryptionOptions {
+            filename_policy,
+            ..DecryptionOptions::default()
+        },
     )
-    .expect("")
-    .read_to_end(&mut buffer)
-    .expect("");
+}
 
-    let mut expected_buff = Vec::<u8>::with_capacity(512);
-    File::open(temp.child("to_enc_small.txt").path())
-        .expect("")
-        .read_to_end(&mut expected_buff)
+#[test]
+fn test_unsafe_filename_reject() -> error::Result<()> {
+    for filename in [
+        "../evil.txt",
+        "../../evil.txt",
+        "/tmp/evil.txt",
+        "sub/evil.txt",
+        "..\\evil.txt",
+        "..",
+        ".",
+        "",
+    ] {
+        let temp = assert_fs::TempDir::new()?;
+        let enc_file_path = encrypt_with_filename(&temp, filename.as_bytes())?;
+
+        let err = decrypt_with_policy(&enc_file_path, FilenamePolicy::Reject)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::UnsafeFilename,
+            "{filename:?}"
+        );
+        // Only the encrypted file is left
+        assert_eq!(
+            fs::read_dir(temp.child("sub").path())?.count(),
+            1
+        );
+        assert_eq!(fs::read_dir(temp.path())?.count(), 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unsafe_filename_basename() -> error::Result<()> {
+    for (filename, expected) in [
+        ("../../evil.txt", "evil.txt"),
+        ("/tmp/evil.txt", "evil.txt"),
+        ("..\\evil.txt", "evil.txt"),
+        ("..", "x.decrypted"),
+        ("evil/..", "x.decrypted"),
+    ] {
+        let temp = assert_fs::TempDir::new()?;
+        let enc_file_path = encrypt_with_filename(&temp, filename.as_bytes())?;
+
+        decrypt_with_policy(&enc_file_path, FilenamePolicy::Basename)?;
+        assert_eq!(
+            read_all(&temp.path().join("sub").join(expected)),
+            b"the content"
+        );
+        assert_eq!(fs::read_dir(temp.path())?.count(), 1);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unsafe_filename_rename() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_filename(&temp, b"../evil.txt")?;
+
+    decrypt_with_policy(&enc_file_path, FilenamePolicy::Rename)?;
+    assert_eq!(
+        read_all(temp.child("sub/x.decrypted").path()),
+        b"the content"
+    );
+    assert!(!temp.child("evil.txt").path().exists());
+
+    // The safe filename is kept
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_filename(&temp, b"safe.txt")?;
+    decrypt_with_policy(&enc_file_path, FilenamePolicy::Rename)?;
+    assert!(temp
+        .child("sub/safe.txt")
+        .path()
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_recover_unsafe_filename() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_filename(&temp, b"../evil.txt")?;
+
+    file_encryptor::try_recover_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        true,
+    )?;
+    assert_eq!(
+        read_all(temp.child("sub/x.recovered").path()),
+        b"the content"
+    );
+    assert!(!temp.child("evil.txt").path().exists());
+
+    Ok(())
+}
+
+/// Encrypts the file with the known mode and timestamps
+fn encrypt_with_attributes(
+    temp: &assert_fs::TempDir,
+) -> error::Result<(PathBuf, SystemTime)> {
+    let raw_file = temp.child("x.txt");
+    raw_file
+        .write_str("the content")
         .expect("");
+    let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 0);
+    File::options()
+        .write(true)
+        .open(raw_file.path())?
+        .set_times(
+            FileTimes::new()
+                .set_modified(modified)
+                .set_accessed(modified),
+        )?;
+    #[cfg(unix)]
+    fs::set_permissions(
+        raw_file.path(),
+        fs::Permissions::from_mode(0o640),
+    )?;
 
-    assert_eq!(buffer, expected_buff);
+    file_encryptor::try_encrypt(
+        raw_file.path(),
+        None,
+        KEY,
+        &EncryptionOptions::default(),
+    )?;
+    fs::remove_file(raw_file.path()).expect("");
 
-    // temp.close()?;
+    Ok((
+        temp.child("x.txt.enc")
+            .path()
+            .to_path_buf(),
+        modified,
+    ))
+}
+
+#[test]
+fn test_restore_attributes() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let (enc_file_path, modified) = encrypt_with_attributes(&temp)?;
+
+    file_encryptor::try_decrypt(&enc_file_path, KEY, false)?;
+    let metadata = fs::metadata(temp.child("x.txt").path())?;
+    assert_eq!(metadata.modified()?, modified);
+    #[cfg(unix)]
+    assert_eq!(
+        metadata.permissions().mode() & 0o7777,
+        0o640
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_attributes_disabled() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let (enc_file_path, modified) = encrypt_with_attributes(&temp)?;
+
+    file_encryptor::try_decrypt_with_options(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+        &DecryptionOptions {
+            restore_attributes: false,
+            ..DecryptionOptions::default()
+        },
+    )?;
+    let metadata = fs::metadata(temp.child("x.txt").path())?;
+    assert_ne!(metadata.modified()?, modified);
+
+    Ok(())
+}
+
+#[test]
+fn test_checked_reader() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let file_path = temp.child("x.txt").path().to_path_buf();
+    fs::write(&file_path, "the content")?;
+    let content = ContentDigest::from_reader(&mut File::open(&file_path)?)?;
+    let read = |file_path: &Path| -> io::Result<Vec<u8>> {
+        let mut result = Vec::new();
+        CheckedReader::new(
+            file_path,
+            File::open(file_path)?,
+            content,
+        )
+        .read_to_end(&mut result)?;
+        Ok(result)
+    };
+
+    assert_eq!(read(&file_path)?, b"the content");
+
+    // Changed, grown and shrunk
+    for changed in ["The content", "the content!", "the"] {
+        fs::write(&file_path, changed)?;
+        assert_eq!(
+            read(&file_path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData,
+            "{changed}"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_restore_attributes_untrusted() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let raw_file = temp.child("x.txt");
+    raw_file
+        .write_str("the content")
+        .expect("");
+    fs::set_permissions(
+        raw_file.path(),
+        fs::Permissions::from_mode(0o4755),
+    )?;
+    file_encryptor::try_encrypt(
+        raw_file.path(),
+        None,
+        KEY,
+        &EncryptionOptions::default(),
+    )?;
+    fs::remove_file(raw_file.path()).expect("");
+
+    // The setuid bit is not restored
+    file_encryptor::try_decrypt(
+        temp.child("x.txt.enc").path(),
+        KEY,
+        false,
+    )?;
+    assert_eq!(
+        fs::metadata(raw_file.path())?
+            .permissions()
+            .mode()
+            & 0o7777,
+        0o755
+    );
+
+    let policy = RestorePolicy::default();
+    assert!(policy.is_xattr_restored(b"user.tag"));
+    for name in [
+        b"security.selinux".as_slice(),
+        b"security.capability",
+        b"trusted.overlay.opaque",
+        b"system.posix_acl_access",
+    ] {
+        assert!(
+            !policy.is_xattr_restored(name),
+            "{name:?}"
+        );
+    }
+    let policy = RestorePolicy {
+        acls: true,
+        ..policy
+    };
+    assert!(policy.is_xattr_restored(b"system.posix_acl_access"));
+    assert!(policy.is_xattr_restored(b"system.posix_acl_default"));
+    assert!(!policy.is_xattr_restored(b"system.nfs4_acl"));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_xattrs() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let raw_file = temp.child("x.txt");
+    raw_file
+        .write_str("the content")
+        .expect("");
+    match xattr::set(raw_file.path(), "user.tag", b"blue") {
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+
+    let options = EncryptionOptions {
+        xattrs: true,
+        ..EncryptionOptions::default()
+    };
+    file_encryptor::try_encrypt(raw_file.path(), None, KEY, &options)?;
+    fs::remove_file(raw_file.path()).expect("");
+
+    let enc_file_path = temp
+        .child("x.txt.enc")
+        .path()
+        .to_path_buf();
+    let enc_meta = file_encryptor::try_verify_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+    )?;
+    assert!(enc_meta
+        .xattrs
+        .contains(&ExtendedAttribute {
+            name: b"user.tag".to_vec(),
+            value: b"blue".to_vec(),
+        }));
+
+    file_encryptor::try_decrypt(&enc_file_path, KEY, false)?;
+    assert_eq!(
+        xattr::get(raw_file.path(), "user.tag")?,
+        Some(b"blue".to_vec())
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_filename() -> error::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Latin-1 "café.txt"
+    let filename = b"caf\xe9.txt";
+    let temp = assert_fs::TempDir::new()?;
+    let raw_file_path = temp
+        .path()
+        .join(OsStr::from_bytes(filename));
+    fs::write(&raw_file_path, b"the content")?;
+
+    file_encryptor::try_encrypt(
+        &raw_file_path,
+        None,
+        KEY,
+        &EncryptionOptions::default(),
+    )?;
+    fs::remove_file(&raw_file_path)?;
+
+    let enc_file_path = encrypted_file_path(&raw_file_path);
+    let enc_meta = file_encryptor::try_verify_with(
+        &enc_file_path,
+        &[Identity::Password(KEY.to_string())],
+    )?;
+    assert_eq!(enc_meta.filename, filename);
+    assert!(!enc_meta.is_filename_utf8());
+
+    file_encryptor::try_decrypt(&enc_file_path, KEY, false)?;
+    assert_eq!(read_all(&raw_file_path), b"the content");
+
+    // The basename is byte-exact too
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_with_filename(&temp, b"../caf\xe9.txt")?;
+    decrypt_with_policy(&enc_file_path, FilenamePolicy::Basename)?;
+    assert_eq!(
+        read_all(
+            &temp
+                .path()
+                .join("sub")
+                .join(OsStr::from_bytes(filename))
+        ),
+        b"the content"
+    );
+
+    Ok(())
+}
+
+/// The tree of `a.txt`, `sub/b.txt` and `sub/deep/c.txt`
+fn create_tree(root: &Path) -> error::Result<()> {
+    fs::create_dir_all(root.join("sub/deep"))?;
+    for name in ["a.txt", "sub/b.txt", "sub/deep/c.txt"] {
+        fs::write(root.join(name), name)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_dir_in_place() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    create_tree(root)?;
+    // The target of the encryption exists, the file fails
+    fs::create_dir(root.join("sub/b.txt.enc"))?;
+
+    let report = file_encryptor::try_encrypt_dir(
+        root,
+        &[Recipient::Password(KEY.to_string())],
+        &EncryptionOptions::default(),
+        &BulkOptions::default(),
+    )?;
+    assert_eq!(
+        report.processed,
+        [root.join("a.txt"), root.join("sub/deep/c.txt")]
+    );
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(
+        report.failed[0].0,
+        root.join("sub/b.txt")
+    );
+    assert_eq!(
+        report.failed[0].1.kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    assert!(!root.join("a.txt").exists());
+    assert!(root.join("sub/b.txt").exists());
+    assert!(root.join("sub/deep/c.txt.enc").exists());
+
+    fs::remove_dir(root.join("sub/b.txt.enc"))?;
+    let report = file_encryptor::try_decrypt_dir(
+        root,
+        &[Identity::Password(KEY.to_string())],
+        &DecryptionOptions::default(),
+        &BulkOptions::default(),
+    )?;
+    assert!(report.is_success());
+    assert_eq!(report.processed.len(), 2);
+    assert_eq!(report.skipped, [root.join("sub/b.txt")]);
+    for name in ["a.txt", "sub/b.txt", "sub/deep/c.txt"] {
+        assert_eq!(
+            read_all(&root.join(name)),
+            name.as_bytes()
+        );
+    }
+    assert!(!root.join("a.txt.enc").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_no_overwrite() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    // The names differ by the extension only
+    for name in ["a.txt", "a.md"] {
+        fs::write(root.join(name), name)?;
+        file_encryptor::try_encrypt(
+            &root.join(name),
+            None,
+            KEY,
+            &EncryptionOptions::default(),
+        )?;
+    }
+    let enc_file_path = root.join("a.txt.enc");
+    let encrypted = read_all(&enc_file_path);
+    assert_ne!(
+        encrypted,
+        read_all(&root.join("a.md.enc"))
+    );
+
+    // The existing target is never replaced
+    assert_eq!(
+        file_encryptor::try_encrypt(
+            &root.join("a.txt"),
+            None,
+            KEY,
+            &EncryptionOptions::default(),
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    assert_eq!(read_all(&enc_file_path), encrypted);
+
+    fs::write(root.join("a.txt"), "changed")?;
+    assert_eq!(
+        file_encryptor::try_decrypt(&enc_file_path, KEY, false)
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    assert_eq!(
+        read_all(&root.join("a.txt")),
+        b"changed"
+    );
+    assert_eq!(fs::read_dir(root)?.count(), 4);
+
+    Ok(())
+}
+
+/// The workers, racing for the same target, never share it:
+/// the only one writes it, the others fail and keep their originals
+#[test]
+fn test_dir_collision() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    let files: Vec<PathBuf> = (0..8)
+        .map(|i| root.join(format!("{i}.txt")))
+        .collect();
+    for file_path in &files {
+        fs::write(file_path, file_path.to_str().unwrap())?;
+    }
+    let target = root.join("same.enc");
+
+    let mut report = BulkReport::default();
+    process_files(
+        files.clone(),
+        &BulkOptions {
+            jobs: 4,
+            ..BulkOptions::default()
+        },
+        &mut report,
+        |file_path| {
+            file_encryptor::try_encrypt_to(
+                file_path,
+                Some(&target),
+                &[Recipient::Password(KEY.to_string())],
+                &EncryptionOptions::default(),
+            )?;
+            fs::remove_file(file_path)?;
+            Ok(true)
+        },
+    )?;
+    assert_eq!(report.processed.len(), 1);
+    assert_eq!(report.failed.len(), 7);
+    for (file_path, e) in &report.failed {
+        assert_eq!(e.kind(), ErrorKind::FileAlreadyExist);
+        assert!(file_path.exists());
+    }
+
+    let processed = &report.processed[0];
+    file_encryptor::try_decrypt(&target, KEY, false)?;
+    assert_eq!(
+        read_all(processed),
+        processed.to_str().unwrap().as_bytes()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_dir_skip_encrypted() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path();
+    create_tree(root)?;
+    fs::write(root.join(".rfeignore"), "deep/\n")?;
+
+    let encrypt_dir = || {
+        file_encryptor::try_encrypt_dir(
+            root,
+            &[Recipient::Password(KEY.to_string())],
+            &EncryptionOptions::default(),
+            &BulkOptions::default(),
+        )
+    };
+    let report = encrypt_dir()?;
+    assert_eq!(
+        report.processed,
+        [root.join("a.txt"), root.join("sub/b.txt")]
+    );
+    assert!(root.join("sub/deep/c.txt").exists());
+
+    // Encrypted once only
+    let report = encrypt_dir()?;
+    assert!(report.is_success());
+    assert!(report.processed.is_empty());
+    assert!(report
+        .skipped
+        .contains(&root.join("a.txt.enc")));
+    assert!(report
+        .skipped
+        .contains(&root.join("sub/b.txt.enc")));
+
+    Ok(())
+}
+
+#[test]
+fn test_dir_mirror() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path().join("src");
+    create_tree(&root)?;
+
+    let enc_dir = temp.path().join("enc");
+    let report = file_encryptor::try_encrypt_dir(
+        &root,
+        &[Recipient::Password(KEY.to_string())],
+        &EncryptionOptions::default(),
+        &BulkOptions {
+            output_dir: Some(enc_dir.clone()),
+            jobs: 4,
+            ..BulkOptions::default()
+        },
+    )?;
+    assert!(report.is_success());
+    assert_eq!(report.processed.len(), 3);
+    // The originals are kept
+    assert!(root.join("sub/deep/c.txt").exists());
+    assert!(enc_dir
+        .join("sub/deep/c.txt.enc")
+        .exists());
+
+    let dec_dir = temp.path().join("dec");
+    let report = file_encryptor::try_decrypt_dir(
+        &enc_dir,
+        &[Identity::Password(KEY.to_string())],
+        &DecryptionOptions::default(),
+        &BulkOptions {
+            output_dir: Some(dec_dir.clone()),
+            jobs: 2,
+            ..BulkOptions::default()
+        },
+    )?;
+    assert!(report.is_success());
+    assert_eq!(report.processed.len(), 3);
+    assert!(enc_dir
+        .join("sub/deep/c.txt.enc")
+        .exists());
+    for name in ["a.txt", "sub/b.txt", "sub/deep/c.txt"] {
+        assert_eq!(
+            read_all(&dec_dir.join(name)),
+            name.as_bytes()
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_archive() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path().join("src");
+    create_tree(&root)?;
+    let big: Vec<u8> = (0..100_000u32)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    fs::write(root.join("sub/big.bin"), &big)?;
+    fs::write(root.join("skip.log"), "skipped")?;
+
+    let options = EncryptionOptions {
+        chunk_size: 4096,
+        parity: Some(ParityParams::new(2)),
+        ..EncryptionOptions::default()
+    };
+    let report = file_encryptor::try_pack(
+        &root,
+        None,
+        &[Recipient::Password(KEY.to_string())],
+        &options,
+        &BulkOptions {
+            exclude: vec!["*.log".to_string()],
+            ..BulkOptions::default()
+        },
+    )?;
+    assert!(report.is_success());
+    assert_eq!(report.processed.len(), 4);
+
+    let archive = temp.path().join("src.enc");
+    let identities = [Identity::Password(KEY.to_string())];
+    let enc_meta = file_encryptor::try_verify_with(&archive, &identities)?;
+    assert!(enc_meta.is_archive);
+    assert_eq!(enc_meta.filename, b"src");
+
+    let index = file_encryptor::try_list_archive(&archive, &identities)?;
+    assert_eq!(
+        index
+            .entries
+            .iter()
+            .map(|entry| entry.path_lossy())
+            .collect::<Vec<_>>(),
+        ["a.txt", "sub/b.txt", "sub/big.bin", "sub/deep/c.txt"]
+    );
+    assert_eq!(
+        index.content_size(),
+        big.len() as u64 + 5 + 9 + 14
+    );
+
+    let extract_dir = temp.path().join("extract");
+    fs::create_dir(&extract_dir)?;
+    let extracted = file_encryptor::try_extract(
+        &archive,
+        b"sub/deep/c.txt",
+        Some(&extract_dir),
+        &identities,
+        &DecryptionOptions::default(),
+    )?;
+    assert_eq!(extracted, extract_dir.join("c.txt"));
+    assert_eq!(read_all(&extracted), b"sub/deep/c.txt");
+
+    let unpack_dir = temp.path().join("unpack");
+    fs::create_dir(&unpack_dir)?;
+    assert_eq!(
+        file_encryptor::try_unpack(
+            &archive,
+            Some(&unpack_dir),
+            &identities,
+            &DecryptionOptions::default(),
+        )?,
+        index
+    );
+    assert_eq!(
+        read_all(&unpack_dir.join("src/sub/big.bin")),
+        big
+    );
+    assert!(!unpack_dir.join("src/skip.log").exists());
+
+    // The plain decryption unpacks the archive as well
+    fs::remove_dir_all(&root)?;
+    file_encryptor::try_decrypt_with_options(
+        &archive,
+        &identities,
+        &DecryptionOptions::default(),
+    )?;
+    for name in ["a.txt", "sub/b.txt", "sub/deep/c.txt"] {
+        assert_eq!(
+            read_all(&root.join(name)),
+            name.as_bytes()
+        );
+    }
+    assert_eq!(read_all(&root.join("sub/big.bin")), big);
+
+    // The existing directory is not replaced. The content is unpacked
+    // on the fly, the plain text of the archive is never written
+    let names = || -> io::Result<Vec<_>> {
+        let mut names = fs::read_dir(temp.path())?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    };
+    assert_eq!(
+        names()?,
+        ["extract", "src", "src.enc", "unpack"]
+    );
+    assert_eq!(
+        file_encryptor::try_decrypt(&archive, KEY, false)
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    // The preview lists the entries, nothing is written
+    file_encryptor::try_decrypt(&archive, KEY, true)?;
+    assert_eq!(
+        names()?,
+        ["extract", "src", "src.enc", "unpack"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_archive_err() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let root = temp.path().join("src");
+    create_tree(&root)?;
+    let recipients = [Recipient::Password(KEY.to_string())];
+    file_encryptor::try_pack(
+        &root,
+        None,
+        &recipients,
+        &EncryptionOptions::default(),
+        &BulkOptions::default(),
+    )?;
+
+    let archive = temp.path().join("src.enc");
+    assert_eq!(
+        file_encryptor::try_pack(
+            &root,
+            None,
+            &recipients,
+            &EncryptionOptions::default(),
+            &BulkOptions::default(),
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::FileAlreadyExist
+    );
+
+    // The wrong key leaves nothing behind
+    let unpack_dir = temp.path().join("unpack");
+    fs::create_dir(&unpack_dir)?;
+    assert_eq!(
+        file_encryptor::try_unpack(
+            &archive,
+            Some(&unpack_dir),
+            &[Identity::Password("wrong".to_string())],
+            &DecryptionOptions::default(),
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::WrongPassword
+    );
+    assert_eq!(fs::read_dir(&unpack_dir)?.count(), 0);
+
+    let identities = [Identity::Password(KEY.to_string())];
+    assert_eq!(
+        file_encryptor::try_extract(
+            &archive,
+            b"sub/none.txt",
+            Some(&unpack_dir),
+            &identities,
+            &DecryptionOptions::default(),
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::FileNotFound
+    );
+    assert_eq!(
+        file_encryptor::try_extract(
+            &archive,
+            b"sub/..",
+            Some(&unpack_dir),
+            &identities,
+            &DecryptionOptions::default(),
+        )
+        .err()
+        .unwrap()
+        .kind(),
+        ErrorKind::UnsafeFilename
+    );
+    assert_eq!(fs::read_dir(&unpack_dir)?.count(), 0);
+
+    // The regular encrypted file is not an archive
+    file_encryptor::try_encrypt_to(
+        &root.join("a.txt"),
+        None,
+        &recipients,
+        &EncryptionOptions::default(),
+    )?;
+    assert_eq!(
+        file_encryptor::try_list_archive(&root.join("a.txt.enc"), &identities)
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::InvalidArgument
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_size() -> error::Result<()> {
+    for chunk_size in [16, 500, 4096, 1024 * 1024] {
+        let options = EncryptionOptions {
+            chunk_size,
+            ..EncryptionOptions::default()
+        };
+
+        test_roundtrip("to_enc_large", &options)?;
+        test_roundtrip("to_enc_small", &options)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_chunks() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let content: Vec<u8> = (0..64 * 100 + 7)
+        .map(|i| (i * 31 % 251) as u8)
+        .collect();
+    let enc_meta = EncryptedMeta {
+        filename: b"x.txt".to_vec(),
+        content: Some(ContentDigest::from_reader(
+            &mut content.as_slice(),
+        )?),
+        attributes: FileAttributes::default(),
+        xattrs: Vec::new(),
+        is_archive: false,
+    };
+
+    for cipher_kind in [CipherKind::ChaCha20Poly1305, CipherKind::AesGcm] {
+        for parity in [None, Some(ParityParams::new(2))] {
+            let data_key = generate_data_key();
+            let raw_meta = RawMeta {
+                version: RawMeta::latest_version(),
+                cipher_kind,
+                nonce: generate_nonce(cipher_kind, &mut RandomNonceGenerator),
+                kdf: None,
+                salt: [0u8; KDF_SALT_SIZE],
+                chunk_size: 64,
+                key_check: Some(key_check_value(&data_key)),
+                parity,
+                meta_len: Some((MetaHeader::size() + enc_meta.len()) as u32),
+            };
+            let params =
+                StreamParams::new(&raw_meta, &raw_meta_to_bytes(&raw_meta));
+
+            // The output does not depend on the number of the threads
+            let encrypt = |threads: usize| -> error::Result<Vec<u8>> {
+                let enc_file_path = temp
+                    .path()
+                    .join(format!("{threads}.enc"));
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("")
+                    .install(|| {
+                        select_cipher(cipher_kind, params.clone(), None)
+                            .encrypt(
+                                Box::new(io::Cursor::new(content.clone())),
+                                Box::new(File::create(&enc_file_path)?),
+                                &data_key,
+                                &raw_meta.nonce,
+                                &enc_meta,
+                            )
+                    })?;
+                Ok(read_all(&enc_file_path))
+            };
+            let encrypted = encrypt(4)?;
+            assert_eq!(encrypted, encrypt(1)?);
+
+            let dec_file_path = temp.path().join("x.txt");
+            let decrypted_meta = rayon::ThreadPoolBuilder::new()
+                .num_threads(4)
+                .build()
+                .expect("")
+                .install(|| {
+                    select_cipher(cipher_kind, params.clone(), None).decrypt(
+                        Box::new(io::Cursor::new(encrypted)),
+                        Box::new(File::create(&dec_file_path)?),
+                        &data_key,
+                        &raw_meta.nonce,
+                    )
+                })?;
+            assert_eq!(decrypted_meta, enc_meta);
+            assert_eq!(read_all(&dec_file_path), content);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_size_invalid() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
+        .expect("");
+
+    let options = EncryptionOptions {
+        chunk_size: 8,
+        ..EncryptionOptions::default()
+    };
+    let result = file_encryptor::try_encrypt(
+        temp.child("to_enc.txt").path(),
+        None,
+        KEY,
+        &options,
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+    assert!(!temp
+        .child("to_enc.txt.enc")
+        .path()
+        .exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_header_tampered() -> error::Result<()> {
+    // MetaHeader reserved byte, RawMeta reserved byte, version downgrade
+    for (index, value) in [(5usize, 0x01u8), (16 + 2, 0x01), (3, 3)] {
+        let temp = assert_fs::TempDir::new()?;
+        let enc_file_path = encrypt_two_chunks(&temp)?;
+
+        let mut content = read_all(&enc_file_path);
+        content[index] = value;
+        fs::write(&enc_file_path, content)?;
+
+        assert!(
+            file_encryptor::try_decrypt(&enc_file_path, KEY, false).is_err(),
+            "Tampered byte {index} is not detected"
+        );
+    }
+
+    Ok(())
+}
+
+/// Encrypts `to_enc.txt` by `KEY`
+fn encrypt_common(temp: &assert_fs::TempDir) -> error::Result<PathBuf> {
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
+        .expect("");
+
+    let raw_file = temp.child("to_enc.txt");
+    file_encryptor::try_encrypt(
+        raw_file.path(),
+        None,
+        KEY,
+        &EncryptionOptions::default(),
+    )?;
+    fs::remove_file(raw_file.path()).expect("");
+
+    Ok(temp
+        .child("to_enc.txt.enc")
+        .path()
+        .to_path_buf())
+}
+
+/// Decrypts the copy of the file, returns the decrypted content
+fn decrypt_copy(enc_file_path: &Path, key: &str) -> error::Result<Vec<u8>> {
+    decrypt_copy_with(
+        enc_file_path,
+        &[Identity::Password(key.to_string())],
+    )
+}
+
+fn decrypt_copy_with(
+    enc_file_path: &Path,
+    identities: &[Identity],
+) -> error::Result<Vec<u8>> {
+    let temp = assert_fs::TempDir::new()?;
+    let copy_path = temp.child("copy.enc");
+    fs::copy(enc_file_path, copy_path.path())?;
+
+    file_encryptor::try_decrypt_with(copy_path.path(), identities, false)?;
+    Ok(read_all(
+        temp.child("to_enc.txt").path(),
+    ))
+}
+
+/// The encrypted stream, following the key slots
+fn read_stream(enc_file_path: &Path) -> error::Result<Vec<u8>> {
+    let mut file = File::open(enc_file_path)?;
+    get_raw_meta(&mut file)?;
+    get_key_slots(&mut file)?;
+
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[test]
+fn test_add_remove_key() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_common(&temp)?;
+    let expected = read_all(&Path::new(ROOT_FILE_DIR).join("to_enc.txt"));
+    let stream = read_stream(&enc_file_path)?;
+
+    file_encryptor::try_add_key(
+        &enc_file_path,
+        KEY,
+        "second",
+        &KdfParams::default(),
+    )?;
+    assert_eq!(read_stream(&enc_file_path)?, stream);
+    assert_eq!(
+        decrypt_copy(&enc_file_path, KEY)?,
+        expected
+    );
+    assert_eq!(
+        decrypt_copy(&enc_file_path, "second")?,
+        expected
+    );
+
+    // Only the existing key may add the new one
+    let result = file_encryptor::try_add_key(
+        &enc_file_path,
+        "third",
+        "third",
+        &KdfParams::default(),
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::WrongPassword
+    );
+
+    file_encryptor::try_remove_key(&enc_file_path, KEY)?;
+    assert_eq!(read_stream(&enc_file_path)?, stream);
+    assert!(decrypt_copy(&enc_file_path, KEY).is_err());
+    assert_eq!(
+        decrypt_copy(&enc_file_path, "second")?,
+        expected
+    );
+
+    let result = file_encryptor::try_remove_key(&enc_file_path, "second");
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+    assert_eq!(
+        decrypt_copy(&enc_file_path, "second")?,
+        expected
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rekey() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_common(&temp)?;
+    let expected = read_all(&Path::new(ROOT_FILE_DIR).join("to_enc.txt"));
+    let stream = read_stream(&enc_file_path)?;
+
+    file_encryptor::try_rekey(
+        &enc_file_path,
+        KEY,
+        "changed",
+        &KdfParams::default(),
+    )?;
+    assert_eq!(read_stream(&enc_file_path)?, stream);
+    assert!(decrypt_copy(&enc_file_path, KEY).is_err());
+    assert_eq!(
+        decrypt_copy(&enc_file_path, "changed")?,
+        expected
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_add_key_temp_file() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let enc_file_path = encrypt_common(&temp)?;
+    #[cfg(unix)]
+    fs::set_permissions(
+        &enc_file_path,
+        fs::Permissions::from_mode(0o600),
+    )?;
+    let enc_file = read_all(&enc_file_path);
+
+    // The temp file of the concurrent update is not reused
+    let temp_file = temp.child("to_enc.txt.enc.tmp-key");
+    temp_file.write_str("concurrent")?;
+    let result = file_encryptor::try_add_key(
+        &enc_file_path,
+        KEY,
+        "second",
+        &KdfParams::default(),
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    assert_eq!(
+        read_all(temp_file.path()),
+        b"concurrent"
+    );
+    assert_eq!(read_all(&enc_file_path), enc_file);
+
+    // The permissions of the rewritten file are kept
+    fs::remove_file(temp_file.path())?;
+    file_encryptor::try_add_key(
+        &enc_file_path,
+        KEY,
+        "second",
+        &KdfParams::default(),
+    )?;
+    assert!(!temp_file.path().exists());
+    #[cfg(unix)]
+    assert_eq!(
+        fs::metadata(&enc_file_path)?
+            .permissions()
+            .mode()
+            & 0o7777,
+        0o600
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_key_slots_limit() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let raw_file = temp.child("to_enc.txt");
+    raw_file.write_str("content")?;
+    let identity = X25519Identity::generate();
+    let recipient = Recipient::X25519(identity.recipient());
+    let slot_len = 0x54;
+    let max_slots = KeySlots::MAX_SIZE / slot_len;
+
+    // Too many recipients, the file is not created
+    let result = file_encryptor::try_encrypt_to(
+        raw_file.path(),
+        None,
+        &vec![recipient.clone(); max_slots + 1],
+        &EncryptionOptions::default(),
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+    let enc_file_path = temp
+        .child("to_enc.txt.enc")
+        .path()
+        .to_path_buf();
+    assert!(!enc_file_path.exists());
+
+    file_encryptor::try_encrypt_to(
+        raw_file.path(),
+        None,
+        &vec![recipient.clone(); max_slots - 2],
+        &EncryptionOptions::default(),
+    )?;
+    let identities = [Identity::X25519(identity)];
+    let add_key = || {
+        file_encryptor::try_add_key_with(
+            &enc_file_path,
+            &identities,
+            &recipient,
+            &KdfParams::default(),
+        )
+    };
+    add_key()?;
+    add_key()?;
+    let enc_file = read_all(&enc_file_path);
+    let result = add_key();
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+    assert_eq!(read_all(&enc_file_path), enc_file);
+
+    let mut file = File::open(&enc_file_path)?;
+    get_raw_meta(&mut file)?;
+    assert_eq!(
+        get_key_slots(&mut file)?.slots.len(),
+        max_slots
+    );
+    assert_eq!(
+        decrypt_copy_with(&enc_file_path, &identities)?,
+        b"content"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_rekey_legacy() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(LEGACY_FILE_DIR, &["legacy_v5.enc"])
+        .expect("");
+
+    let result = file_encryptor::try_rekey(
+        temp.child("legacy_v5.enc").path(),
+        KEY,
+        "changed",
+        &KdfParams::default(),
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::InvalidArgument
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_x25519_recipients() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
+        .expect("");
+    let expected = read_all(&Path::new(ROOT_FILE_DIR).join("to_enc.txt"));
+
+    let alice = X25519Identity::generate();
+    let bob = X25519Identity::generate();
+    file_encryptor::try_encrypt_to(
+        temp.child("to_enc.txt").path(),
+        None,
+        &[
+            Recipient::X25519(alice.recipient()),
+            Recipient::X25519(bob.recipient()),
+            Recipient::Password(KEY.to_string()),
+        ],
+        &EncryptionOptions::default(),
+    )?;
+    let enc_file_path = temp
+        .child("to_enc.txt.enc")
+        .path()
+        .to_path_buf();
+    fs::remove_file(temp.child("to_enc.txt").path()).expect("");
+
+    let mut file = File::open(&enc_file_path)?;
+    get_raw_meta(&mut file)?;
+    assert_eq!(get_key_slots(&mut file)?.slots.len(), 3);
+    for identity in [alice, bob] {
+        assert_eq!(
+            decrypt_copy_with(
+                &enc_file_path,
+                &[Identity::X25519(identity)]
+            )?,
+            expected
+        );
+    }
+    assert_eq!(
+        decrypt_copy(&enc_file_path, KEY)?,
+        expected
+    );
+
+    let result = decrypt_copy_with(
+        &enc_file_path,
+        &[Identity::X25519(X25519Identity::generate())],
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::WrongPassword
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_keygen_files() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    let identity_path = temp.child("key.txt");
+
+    let pub_path = write_new_identity(identity_path.path())?;
+    assert_eq!(
+        pub_path,
+        temp.child("key.txt.pub").path()
+    );
+
+    let identities: Vec<X25519Identity> = read_keys_file(identity_path.path())?;
+    let recipients: Vec<X25519Recipient> = read_keys_file(&pub_path)?;
+    assert_eq!(identities.len(), 1);
+    assert_eq!(
+        recipients,
+        vec![identities[0].recipient()]
+    );
+
+    // Existing keys are never overwritten
+    let result = write_new_identity(identity_path.path());
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::FileAlreadyExist
+    );
+    assert_eq!(
+        read_keys_file::<X25519Identity>(identity_path.path())?[0].recipient(),
+        identities[0].recipient()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_ssh_ed25519_recipient() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
+        .expect("");
+    let expected = read_all(&Path::new(ROOT_FILE_DIR).join("to_enc.txt"));
+
+    let ssh_dir = Path::new(SSH_FILE_DIR);
+    let recipients: Vec<Recipient> =
+        read_keys_file(&ssh_dir.join("id_ed25519.pub"))?;
+    let identity: SshEd25519Identity =
+        fs::read_to_string(ssh_dir.join("id_ed25519"))?.parse()?;
+
+    file_encryptor::try_encrypt_to(
+        temp.child("to_enc.txt").path(),
+        None,
+        &recipients,
+        &EncryptionOptions {
+            cipher_kind: CipherKind::AesGcm,
+            ..Default::default()
+        },
+    )?;
+    let enc_file_path = temp
+        .child("to_enc.txt.enc")
+        .path()
+        .to_path_buf();
+    fs::remove_file(temp.child("to_enc.txt").path()).expect("");
+
+    assert_eq!(
+        decrypt_copy_with(
+            &enc_file_path,
+            &[Identity::SshEd25519(identity)]
+        )?,
+        expected
+    );
+
+    let result = decrypt_copy_with(
+        &enc_file_path,
+        &[
+            Identity::X25519(X25519Identity::generate()),
+            Identity::Password(KEY.to_string()),
+        ],
+    );
+    assert_eq!(
+        result.err().unwrap().kind(),
+        ErrorKind::WrongPassword
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_keyfile() -> error::Result<()> {
+    let temp = assert_fs::TempDir::new()?;
+    temp.copy_from(ROOT_FILE_DIR, &["to_enc.txt"])
+        .expect("");
+    let expected = read_all(&Path::new(ROOT_FILE_DIR).join("to_enc.txt"));
+
+    let keyfile_path = temp.child("key.bin");
+    keyfile_path.write_binary(&[0x42u8; 1000])?;
+    let keyfile = Keyfile::read(keyfile_path.path())?;
+
+    file_encryptor::try_encrypt_to(
+        temp.child("to_enc.txt").path(),
+        None,
+        &[Recipient::PasswordKeyfile(KEY.to_string(), keyfile.clone())],
+        &EncryptionOptions::default(),
+    )?;
+    let enc_file_path = temp
+        .child("to_enc.txt.enc")
+        .path()
+        .to_path_buf();
+    fs::remove_file(temp.child("to_enc.txt").path()).expect("");
+
+    assert_eq!(
+        decrypt_copy_with(
+            &enc_file_path,
+            &[Identity::PasswordKeyfile(KEY.to_string(), keyfile.clone())]
+        )?,
+        expected
+    );
+    for identities in [
+        vec![Identity::Password(KEY.to_string())],
+        vec![Identity::Keyfile(keyfile.clone())],
+        vec![
+            Identity::Password(KEY.to_string()),
+            Identity::Keyfile(keyfile.clone()),
+        ],
+    ] {
+        let result = decrypt_copy_with(&enc_file_path, &identities);
+        assert_eq!(
+            result.err().unwrap().kind(),
+            ErrorKind::WrongPassword
+        );
+    }
+
+    // The keyfile alone
+    file_encryptor::try_rekey_with(
+        &enc_file_path,
+        &[Identity::PasswordKeyfile(KEY.to_string(), keyfile.clone())],
+        &Recipient::Keyfile(keyfile.clone()),
+        &KdfParams::default(),
+    )?;
+    assert_eq!(
+        decrypt_copy_with(
+            &enc_file_path,
+            &[Identity::Keyfile(keyfile)]
+        )?,
+        expected
+    );
 
     Ok(())
 }